@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE weekly_availabilities;
+DROP TABLE availabilities;
+DROP TABLE periods
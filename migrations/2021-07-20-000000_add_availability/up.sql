@@ -0,0 +1,35 @@
+-- Your SQL goes here
+CREATE TABLE periods (
+  id BIGSERIAL NOT NULL PRIMARY KEY,
+  start_date DATE NOT NULL,
+  end_date DATE NOT NULL,
+  deadline TIMESTAMP WITH TIME ZONE NOT NULL,
+  created_by VARCHAR NOT NULL,
+  FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  CHECK (end_date >= start_date)
+);
+
+CREATE TABLE availabilities (
+  id BIGSERIAL NOT NULL PRIMARY KEY,
+  username VARCHAR NOT NULL,
+  start_time TIMESTAMP WITH TIME ZONE NOT NULL,
+  end_time TIMESTAMP WITH TIME ZONE NOT NULL,
+  kind VARCHAR NOT NULL,
+  FOREIGN KEY (username) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  CHECK (end_time > start_time),
+  CHECK (kind IN ('available', 'unavailable', 'preferred'))
+);
+
+-- weekday: 0 = Sunday, times are store local time
+CREATE TABLE weekly_availabilities (
+  id BIGSERIAL NOT NULL PRIMARY KEY,
+  username VARCHAR NOT NULL,
+  weekday SMALLINT NOT NULL,
+  start_time TIME NOT NULL,
+  end_time TIME NOT NULL,
+  kind VARCHAR NOT NULL,
+  FOREIGN KEY (username) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  CHECK (weekday BETWEEN 0 AND 6),
+  CHECK (end_time > start_time),
+  CHECK (kind IN ('available', 'unavailable', 'preferred'))
+)
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE periods ADD COLUMN opens_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE periods ADD CHECK (opens_at IS NULL OR opens_at < deadline)
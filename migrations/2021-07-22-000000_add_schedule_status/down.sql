@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE schedules ADD COLUMN permitted BOOLEAN NOT NULL DEFAULT 'f';
+ALTER TABLE schedules ADD COLUMN absent BOOLEAN NOT NULL DEFAULT 'f';
+ALTER TABLE schedules ADD COLUMN enable BOOLEAN NOT NULL DEFAULT 't';
+
+UPDATE schedules SET
+  permitted = status IN ('approved', 'absence_requested', 'absent_approved'),
+  absent = status IN ('absence_requested', 'absent_approved'),
+  enable = status NOT IN ('absent_approved', 'cancelled', 'rejected');
+
+ALTER TABLE schedules ADD CHECK (permitted OR NOT absent);
+ALTER TABLE schedules DROP COLUMN status
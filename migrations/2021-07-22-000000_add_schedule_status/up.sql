@@ -0,0 +1,19 @@
+-- Your SQL goes here
+ALTER TABLE schedules ADD COLUMN status VARCHAR NOT NULL DEFAULT 'requested';
+
+UPDATE schedules SET status = CASE
+  WHEN NOT enable AND permitted AND absent THEN 'absent_approved'
+  WHEN NOT enable THEN 'cancelled'
+  WHEN permitted AND absent THEN 'absence_requested'
+  WHEN permitted THEN 'approved'
+  WHEN username = created_by THEN 'requested'
+  ELSE 'assigned'
+END;
+
+ALTER TABLE schedules ADD CHECK (status IN (
+  'requested', 'assigned', 'approved', 'absence_requested',
+  'absent_approved', 'cancelled', 'rejected'
+));
+ALTER TABLE schedules DROP COLUMN permitted;
+ALTER TABLE schedules DROP COLUMN absent;
+ALTER TABLE schedules DROP COLUMN enable
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX schedules_start_time_id;
+DROP TABLE settings;
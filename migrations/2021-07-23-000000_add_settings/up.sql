@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE settings (
+  key VARCHAR PRIMARY KEY,
+  value TEXT NOT NULL
+);
+
+CREATE INDEX schedules_start_time_id ON schedules (start_time, id);
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE schedule_events (
+  id BIGSERIAL PRIMARY KEY,
+  schedule_id BIGINT NOT NULL REFERENCES schedules(id) ON DELETE CASCADE,
+  actor VARCHAR NOT NULL,
+  action VARCHAR NOT NULL,
+  from_status VARCHAR,
+  to_status VARCHAR,
+  old_start_time TIMESTAMP WITH TIME ZONE,
+  old_end_time TIMESTAMP WITH TIME ZONE,
+  new_start_time TIMESTAMP WITH TIME ZONE,
+  new_end_time TIMESTAMP WITH TIME ZONE,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX schedule_events_schedule_id ON schedule_events (schedule_id, id);
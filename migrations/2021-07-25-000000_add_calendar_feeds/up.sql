@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE calendar_feeds (
+  username VARCHAR NOT NULL PRIMARY KEY,
+  token VARCHAR NOT NULL UNIQUE,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+  FOREIGN KEY (username) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
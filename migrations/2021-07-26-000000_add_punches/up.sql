@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE punches (
+  id BIGSERIAL NOT NULL PRIMARY KEY,
+  username VARCHAR NOT NULL,
+  kind VARCHAR NOT NULL,
+  punched_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+  FOREIGN KEY (username) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  CHECK (kind IN ('clock_in', 'break_start', 'break_end', 'clock_out'))
+);
+
+CREATE INDEX punches_username_punched_at ON punches (username, punched_at);
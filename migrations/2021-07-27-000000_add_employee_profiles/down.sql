@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE wages;
+ALTER TABLE users DROP COLUMN hire_date;
+ALTER TABLE users DROP COLUMN employee_number;
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN employee_number VARCHAR UNIQUE;
+ALTER TABLE users ADD COLUMN hire_date DATE;
+
+-- hourly wage in yen, effective from a local date until the next row
+CREATE TABLE wages (
+  id BIGSERIAL NOT NULL PRIMARY KEY,
+  username VARCHAR NOT NULL,
+  hourly_wage INTEGER NOT NULL,
+  effective_from DATE NOT NULL,
+  created_by VARCHAR NOT NULL,
+  FOREIGN KEY (username) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  UNIQUE (username, effective_from),
+  CHECK (hourly_wage > 0)
+);
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- deactivated staff keep their history but can no longer sign in
+ALTER TABLE users ADD COLUMN deactivated_at TIMESTAMP WITH TIME ZONE;
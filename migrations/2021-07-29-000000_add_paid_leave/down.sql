@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE leave_requests;
+DROP TABLE leave_grants;
@@ -0,0 +1,34 @@
+-- Your SQL goes here
+-- 年次有給休暇: one row per statutory grant
+CREATE TABLE leave_grants (
+  id BIGSERIAL NOT NULL PRIMARY KEY,
+  username VARCHAR NOT NULL,
+  granted_on DATE NOT NULL,
+  expires_on DATE NOT NULL,
+  days INTEGER NOT NULL,
+  -- scheduled days per week the grant was computed from, 5 for full-time
+  weekly_days SMALLINT NOT NULL,
+  FOREIGN KEY (username) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  UNIQUE (username, granted_on),
+  CHECK (days > 0),
+  CHECK (granted_on < expires_on)
+);
+
+CREATE TABLE leave_requests (
+  id BIGSERIAL NOT NULL PRIMARY KEY,
+  username VARCHAR NOT NULL,
+  leave_date DATE NOT NULL,
+  status VARCHAR NOT NULL DEFAULT 'requested',
+  -- paid time for the day, usually the planned shift
+  minutes INTEGER NOT NULL,
+  note TEXT,
+  decided_by VARCHAR,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+  decided_at TIMESTAMP WITH TIME ZONE,
+  FOREIGN KEY (username) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  CHECK (status IN ('requested', 'approved', 'rejected', 'cancelled')),
+  CHECK (minutes > 0 AND minutes <= 24 * 60)
+);
+
+CREATE UNIQUE INDEX leave_requests_active_date ON leave_requests (username, leave_date)
+  WHERE status IN ('requested', 'approved');
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE schedules ADD COLUMN absence_reason VARCHAR;
+
+ALTER TABLE schedules ADD CHECK (absence_reason IN (
+  'sick', 'family', 'bereavement', 'unpaid', 'company_rest'
+));
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+-- store-specific days on top of the national holidays, which are computed
+CREATE TABLE store_days (
+  day DATE NOT NULL PRIMARY KEY,
+  kind VARCHAR NOT NULL,
+  name VARCHAR NOT NULL,
+  created_by VARCHAR NOT NULL,
+  CHECK (kind IN ('closed', 'special'))
+);
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+-- exceptions are detected from schedules and punches; only a manager's resolution is stored
+CREATE TABLE exception_resolutions (
+  id BIGSERIAL NOT NULL PRIMARY KEY,
+  username VARCHAR NOT NULL,
+  work_date DATE NOT NULL,
+  kind VARCHAR NOT NULL,
+  note TEXT NOT NULL,
+  resolved_by VARCHAR NOT NULL,
+  resolved_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+  FOREIGN KEY (username) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  UNIQUE (username, work_date, kind),
+  CHECK (kind IN ('late', 'early_leave', 'no_show', 'missing_clock_out', 'unscheduled')),
+  CHECK (note <> '')
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE punch_corrections;
+ALTER TABLE periods DROP COLUMN closed_at;
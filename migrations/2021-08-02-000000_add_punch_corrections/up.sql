@@ -0,0 +1,27 @@
+-- Your SQL goes here
+-- once set and passed, attendance in the period is final
+ALTER TABLE periods ADD COLUMN closed_at TIMESTAMP WITH TIME ZONE;
+
+-- punches are never edited; an approved correction is applied on top of them
+CREATE TABLE punch_corrections (
+  id BIGSERIAL NOT NULL PRIMARY KEY,
+  username VARCHAR NOT NULL,
+  -- the punch to move, or NULL to add a forgotten one of `kind`
+  punch_id BIGINT,
+  kind VARCHAR,
+  punched_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  reason TEXT NOT NULL,
+  status VARCHAR NOT NULL DEFAULT 'requested',
+  decided_by VARCHAR,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+  decided_at TIMESTAMP WITH TIME ZONE,
+  FOREIGN KEY (username) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  FOREIGN KEY (punch_id) REFERENCES punches(id) ON DELETE CASCADE,
+  CHECK ((punch_id IS NULL) <> (kind IS NULL)),
+  CHECK (kind IN ('clock_in', 'break_start', 'break_end', 'clock_out')),
+  CHECK (status IN ('requested', 'approved', 'rejected', 'cancelled')),
+  CHECK (reason <> '')
+);
+
+CREATE UNIQUE INDEX punch_corrections_active_punch ON punch_corrections (punch_id)
+  WHERE status IN ('requested', 'approved');
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE punches DROP COLUMN kiosk_id;
+DROP TABLE user_pins;
+DROP TABLE kiosks;
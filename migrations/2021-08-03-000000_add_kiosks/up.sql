@@ -0,0 +1,26 @@
+-- Your SQL goes here
+-- a shared tablet at the counter, known by the token issued when it was registered
+CREATE TABLE kiosks (
+  id BIGSERIAL NOT NULL PRIMARY KEY,
+  name VARCHAR NOT NULL,
+  token VARCHAR NOT NULL UNIQUE,
+  created_by VARCHAR NOT NULL,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+  revoked_at TIMESTAMP WITH TIME ZONE,
+  -- wrong PINs in a row on this device, whoever they were entered for
+  failures INTEGER NOT NULL DEFAULT 0,
+  locked_until TIMESTAMP WITH TIME ZONE,
+  CHECK (name <> '')
+);
+
+-- kiosk PINs, kept apart from the login password
+CREATE TABLE user_pins (
+  username VARCHAR NOT NULL PRIMARY KEY,
+  pin VARCHAR NOT NULL,
+  failures INTEGER NOT NULL DEFAULT 0,
+  locked_until TIMESTAMP WITH TIME ZONE,
+  updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+  FOREIGN KEY (username) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+ALTER TABLE punches ADD COLUMN kiosk_id BIGINT REFERENCES kiosks(id);
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM exception_resolutions WHERE kind = 'off_network';
+ALTER TABLE exception_resolutions DROP CONSTRAINT exception_resolutions_kind_check;
+ALTER TABLE exception_resolutions ADD CONSTRAINT exception_resolutions_kind_check
+  CHECK (kind IN ('late', 'early_leave', 'no_show', 'missing_clock_out', 'unscheduled'));
+ALTER TABLE punches DROP COLUMN off_network;
+ALTER TABLE punches DROP COLUMN client_ip;
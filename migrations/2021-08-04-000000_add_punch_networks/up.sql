@@ -0,0 +1,8 @@
+-- Your SQL goes here
+-- where a punch came from, and whether that was outside the store's networks
+ALTER TABLE punches ADD COLUMN client_ip VARCHAR;
+ALTER TABLE punches ADD COLUMN off_network BOOLEAN NOT NULL DEFAULT false;
+
+ALTER TABLE exception_resolutions DROP CONSTRAINT exception_resolutions_kind_check;
+ALTER TABLE exception_resolutions ADD CONSTRAINT exception_resolutions_kind_check
+  CHECK (kind IN ('late', 'early_leave', 'no_show', 'missing_clock_out', 'unscheduled', 'off_network'));
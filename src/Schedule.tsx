@@ -30,7 +30,7 @@ import { makeStyles, useTheme, Theme, createStyles } from '@material-ui/core/sty
 import Box from '@material-ui/core/Box';
 import { sizing, palette, positions } from '@material-ui/system';
 import * as Utils from './Utils';
-import { getSchedules, seq, Shift, day, updateSchedule, deleteSchedule, getToken, addDay, toDate, timeFormat, postSchedule, decodeJwt, iter, getUsers, User } from './Utils';
+import { getSchedules, seq, Shift, day, updateSchedule, deleteSchedule, getToken, addDay, toDate, timeFormat, postSchedule, decodeJwt, iter, getUsers, User, ShiftStatus } from './Utils';
 import { useResizeDetector } from 'react-resize-detector';
 import { useHistory } from 'react-router-dom';
 import Popover from '@material-ui/core/Popover';
@@ -58,6 +58,16 @@ const useStyles = makeStyles((theme: Theme) =>
     }),
 );
 
+const statusLabel: { [k in ShiftStatus]: string } = {
+    requested: '申請中',
+    assigned: '割当済',
+    approved: '確定',
+    absence_requested: '欠勤希望',
+    absent_approved: '欠勤',
+    cancelled: '取消',
+    rejected: '拒否',
+};
+
 const date2index = (d: Date) => {
     let t = d.getTime() - toDate(d).getTime();
     return t / (30 * 60 * 1000);
@@ -269,12 +279,18 @@ export default function Schedule() {
     }, [data, history]);
 
     const onDisable = useCallback(async (s: Shift) => {
+        const t = getToken();
         const i = data.findIndex((x) => x.id === s.id);
         let nd = [...data];
-        nd[i] = { ...s, enable: false };
+        if (s.status === 'absence_requested') {
+            nd[i] = { ...s, status: 'absent_approved' };
+        } else if (t && ((s.status === 'requested' && decodeJwt(t).isadmin) || (s.status === 'assigned' && s.created_by !== decodeJwt(t).user))) {
+            nd[i] = { ...s, status: 'rejected' };
+        } else {
+            nd[i] = { ...s, status: 'cancelled' };
+        }
         setData(nd);
         let ret = await Utils.disableSchedule(s.id);
-        const t = getToken();
         if (!ret && !t) {
             history.push('/login');
         } else if (!ret) {
@@ -285,7 +301,7 @@ export default function Schedule() {
     const onPermit = useCallback(async (s: Shift) => {
         const i = data.findIndex((x) => x.id === s.id);
         let nd = [...data];
-        nd[i] = { ...s, permitted: true };
+        nd[i] = { ...s, status: 'approved' };
         setData(nd);
         let ret = await Utils.permitSchedule(s.id);
         const t = getToken();
@@ -300,11 +316,7 @@ export default function Schedule() {
         const t = getToken();
         const i = data.findIndex((x) => x.id === s.id);
         let nd = [...data];
-        if (t && decodeJwt(t).isadmin && s.absent) {
-            nd[i] = { ...s, absent: false };
-        } else {
-            nd[i] = { ...s, absent: true };
-        }
+        nd[i] = { ...s, status: 'absence_requested' };
         setData(nd);
         let ret = await Utils.absentSchedule(s.id);
         if (!ret && !t) {
@@ -315,7 +327,7 @@ export default function Schedule() {
     }, [data, history]);
 
     const onUp = useCallback(async (s: Shift) => {
-        if (s.permitted) {
+        if (s.status !== 'requested' && s.status !== 'assigned') {
             alert('許可されたシフトは変更できません'); // todo: replace
             setData([...data]);
             return;
@@ -484,28 +496,28 @@ export default function Schedule() {
                 >
                     <Toolbar style={{ justifyContent: "flex-end" }}>
                         {
-                            sft.enable && (!sft.permitted || sft.absent) && (isadmin || (sft.created_by !== sft.username && sft.username === user)) ?
+                            (sft.status === 'absence_requested' && isadmin) || (sft.status === 'requested' && (isadmin || sft.created_by === user)) || (sft.status === 'assigned' && (sft.created_by === user || sft.username === user)) || (sft.status === 'approved' && isadmin) ?
                                 <IconButton color="inherit" onClick={disableSft}>
                                     <BlockIcon />
                                 </IconButton>
                                 : undefined
                         }
                         {
-                            !sft.permitted && sft.created_by === user ?
+                            (sft.status === 'requested' || sft.status === 'assigned') && sft.created_by === user ?
                                 <IconButton color="inherit" onClick={deleteSft}>
                                     <DeleteIcon />
                                 </IconButton>
                                 : undefined
                         }
                         {
-                            sft.enable && sft.permitted && ((sft.username === user && !sft.absent) || (isadmin && sft.absent)) ?
+                            sft.status === 'approved' && sft.username === user ?
                                 <IconButton color="inherit" onClick={absentSft}>
                                     <HealingIcon />
                                 </IconButton>
                                 : undefined
                         }
                         {
-                            sft.enable && !sft.permitted && (isadmin || (sft.created_by !== sft.username && sft.username === user)) ?
+                            (sft.status === 'requested' && isadmin) || (sft.status === 'assigned' && sft.created_by !== user && sft.username === user) ?
                                 <IconButton color="inherit" onClick={permitSft}>
                                     <AssignmentIcon />
                                 </IconButton>
@@ -523,11 +535,7 @@ export default function Schedule() {
                     </MuiPickersUtilsProvider>
                     <Typography>{sft.username}</Typography>
                     <Typography>{sft.created_by}</Typography>
-                    <Typography>{sft.permitted.toString()}</Typography>
-                    <Typography>{sft.absent.toString()}</Typography>
-                    <Typography>{sft.enable.toString()}</Typography>
-                    {!sft.enable ? <Typography>拒否</Typography> : undefined}
-                    {sft.absent ? <Typography>欠勤希望</Typography> : undefined}
+                    <Typography>{statusLabel[sft.status]}</Typography>
                 </Popover>
             </div>
         );
@@ -545,7 +553,7 @@ export default function Schedule() {
                 const id = await postSchedule(u, start_time, end_time);
                 if (id) {
                     let nd = [...data];
-                    nd.push({ start_time, end_time, id, permitted: false, absent: false, enable: true, created_by: u, username: u })
+                    nd.push({ start_time, end_time, id, status: 'requested', created_by: u, username: u })
                     setData(nd);
                 }
             } else {
@@ -553,7 +561,7 @@ export default function Schedule() {
                     const id = await postSchedule(target, start_time, end_time);
                     if (id) {
                         let nd = [...data];
-                        nd.push({ start_time, end_time, id, permitted: false, absent: false, enable: true, created_by: u, username: target })
+                        nd.push({ start_time, end_time, id, status: target === u ? 'requested' : 'assigned', created_by: u, username: target })
                         setData(nd);
                     }
                     popoverClose();
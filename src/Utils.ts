@@ -63,13 +63,14 @@ export type User = {
     id: string;
 }
 
+export type ShiftStatus =
+    'requested' | 'assigned' | 'approved' | 'absence_requested' | 'absent_approved' | 'cancelled' | 'rejected';
+
 export type Shift = {
     start_time: Date;
     end_time: Date;
-    permitted: boolean;
-    absent: boolean;
+    status: ShiftStatus;
     username: string;
-    enable: boolean;
     created_by: string;
     id: number;
 }
@@ -0,0 +1,103 @@
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::{Pg, PgConnection};
+use diesel::prelude::*;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Varchar;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+use super::settings;
+
+/// Why a worker is off an approved shift.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    AsExpression,
+    FromSqlRow,
+)]
+#[serde(rename_all = "snake_case")]
+#[sql_type = "Varchar"]
+pub enum Reason {
+    Sick,
+    Family,
+    Bereavement,
+    Unpaid,
+    // 労働基準法 第26条: rest ordered by the employer owes 休業手当
+    CompanyRest,
+}
+
+pub const REASONS: [Reason; 5] = [
+    Reason::Sick,
+    Reason::Family,
+    Reason::Bereavement,
+    Reason::Unpaid,
+    Reason::CompanyRest,
+];
+
+// 休業手当 may not be less than 60%
+pub const COMPANY_REST_MIN_PERCENT: i64 = 60;
+
+impl Reason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Reason::Sick => "sick",
+            Reason::Family => "family",
+            Reason::Bereavement => "bereavement",
+            Reason::Unpaid => "unpaid",
+            Reason::CompanyRest => "company_rest",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Reason> {
+        REASONS.iter().cloned().find(|r| r.as_str() == s)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Reason::Sick => "病欠",
+            Reason::Family => "家族都合",
+            Reason::Bereavement => "忌引",
+            Reason::Unpaid => "欠勤",
+            Reason::CompanyRest => "休業",
+        }
+    }
+
+    /// The store setting holding the share of the planned shift paid, in percent.
+    pub fn setting(&self) -> &'static str {
+        match self {
+            Reason::Sick => settings::ABSENCE_PAY_SICK,
+            Reason::Family => settings::ABSENCE_PAY_FAMILY,
+            Reason::Bereavement => settings::ABSENCE_PAY_BEREAVEMENT,
+            Reason::Unpaid => settings::ABSENCE_PAY_UNPAID,
+            Reason::CompanyRest => settings::ABSENCE_PAY_COMPANY_REST,
+        }
+    }
+}
+
+impl ToSql<Varchar, Pg> for Reason {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        ToSql::<Varchar, Pg>::to_sql(self.as_str(), out)
+    }
+}
+
+impl FromSql<Varchar, Pg> for Reason {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        let s: String = FromSql::<Varchar, Pg>::from_sql(bytes)?;
+        Reason::parse(&s).ok_or_else(|| format!("unknown absence reason: {}", s).into())
+    }
+}
+
+/// Paid share of the planned shift for each reason, in percent.
+pub fn pay_percents(conn: &PgConnection) -> QueryResult<Vec<(Reason, i64)>> {
+    REASONS
+        .iter()
+        .map(|r| settings::get_number(conn, r.setting()).map(|x| (*r, x)))
+        .collect()
+}
@@ -0,0 +1,290 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::models::Punch;
+use super::roster::LEGAL_DAILY_MINUTES;
+use super::rounding::Policy;
+use super::{corrections, from_local, schema, to_local};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    ClockIn,
+    BreakStart,
+    BreakEnd,
+    ClockOut,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::ClockIn => "clock_in",
+            Kind::BreakStart => "break_start",
+            Kind::BreakEnd => "break_end",
+            Kind::ClockOut => "clock_out",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Kind> {
+        match s {
+            "clock_in" => Some(Kind::ClockIn),
+            "break_start" => Some(Kind::BreakStart),
+            "break_end" => Some(Kind::BreakEnd),
+            "clock_out" => Some(Kind::ClockOut),
+            _ => None,
+        }
+    }
+
+    /// Whether this punch may follow `last`, the worker's previous punch.
+    pub fn may_follow(&self, last: Option<Kind>) -> bool {
+        match self {
+            Kind::ClockIn => matches!(last, None | Some(Kind::ClockOut)),
+            Kind::BreakStart | Kind::ClockOut => {
+                matches!(last, Some(Kind::ClockIn) | Some(Kind::BreakEnd))
+            }
+            Kind::BreakEnd => last == Some(Kind::BreakStart),
+        }
+    }
+}
+
+// 労働基準法 第37条: 22時から5時までの深夜労働
+fn late_night_minutes(start: &NaiveDateTime, end: &NaiveDateTime) -> i64 {
+    let (start, end) = (to_local(start), to_local(end));
+    let mut d = start.date() - Duration::days(1);
+    let mut ret = 0;
+    while d <= end.date() {
+        let ws = d.and_time(NaiveTime::from_hms(22, 0, 0));
+        let we = (d + Duration::days(1)).and_time(NaiveTime::from_hms(5, 0, 0));
+        let s = start.max(ws);
+        let e = end.min(we);
+        if s < e {
+            ret += (e - s).num_minutes();
+        }
+        d += Duration::days(1);
+    }
+    ret
+}
+
+/// Minutes worked, split the way payroll needs them. `late_night` overlaps
+/// `regular` and `overtime`; it is a premium on top, not a third bucket.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Split {
+    pub break_minutes: i64,
+    pub worked: i64,
+    pub regular: i64,
+    pub overtime: i64,
+    pub late_night: i64,
+}
+
+impl Split {
+    /// Splits worked intervals belonging to one working day.
+    pub fn of(intervals: &[(NaiveDateTime, NaiveDateTime)], break_minutes: i64) -> Split {
+        let worked: i64 = intervals.iter().map(|(s, e)| (*e - *s).num_minutes()).sum();
+        let overtime = (worked - LEGAL_DAILY_MINUTES).max(0);
+        Split {
+            break_minutes,
+            worked,
+            regular: worked - overtime,
+            overtime,
+            late_night: intervals
+                .iter()
+                .map(|(s, e)| late_night_minutes(s, e))
+                .sum(),
+        }
+    }
+}
+
+// 労働基準法 第34条: 6時間を超えると45分, 8時間を超えると1時間
+pub fn statutory_break(minutes: i64) -> i64 {
+    if minutes > 8 * 60 {
+        60
+    } else if minutes > 6 * 60 {
+        45
+    } else {
+        0
+    }
+}
+
+/// Planned split of a scheduled shift, assuming the statutory break is taken.
+pub fn planned(start: &NaiveDateTime, end: &NaiveDateTime) -> Split {
+    let brk = statutory_break((*end - *start).num_minutes());
+    let mut s = Split::of(&[(*start, *end)], brk);
+    s.worked -= brk;
+    s.overtime = (s.worked - LEGAL_DAILY_MINUTES).max(0);
+    s.regular = s.worked - s.overtime;
+    s.late_night = s.late_night.min(s.worked);
+    s
+}
+
+/// One worker's attendance on a local date, built from their punches.
+#[derive(Debug, Clone, Serialize)]
+pub struct Day {
+    pub username: String,
+    pub date: NaiveDate,
+    pub clock_in: NaiveDateTime,
+    // None while the worker is still in, or forgot to clock out
+    pub clock_out: Option<NaiveDateTime>,
+    pub split: Split,
+    // the same under the store's rounding policy; this is what is paid
+    pub rounded_clock_in: NaiveDateTime,
+    pub rounded_clock_out: Option<NaiveDateTime>,
+    pub rounded: Split,
+}
+
+// the session's clock in and clock out moved by the policy; breaks are kept
+fn round_session(
+    worked: &[(NaiveDateTime, NaiveDateTime)],
+    clock_in: &NaiveDateTime,
+    clock_out: &Option<NaiveDateTime>,
+    policy: &Policy,
+) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+    worked
+        .iter()
+        .map(|(s, e)| {
+            let s = if s == clock_in {
+                policy.clock_in(s)
+            } else {
+                *s
+            };
+            let e = if Some(*e) == *clock_out {
+                policy.clock_out(e)
+            } else {
+                *e
+            };
+            (s, e)
+        })
+        .filter(|(s, e)| s < e)
+        .collect()
+}
+
+/// Pairs punches into working days. Punches must be sorted by user and time;
+/// a session belongs to the local date it was clocked in on.
+pub fn days(punches: &[Punch], policy: &Policy) -> Vec<Day> {
+    let mut ret: Vec<Day> = Vec::new();
+    let mut i = 0;
+    while i < punches.len() {
+        let p = &punches[i];
+        i += 1;
+        if Kind::parse(&p.kind) != Some(Kind::ClockIn) {
+            continue;
+        }
+        let mut worked = Vec::new();
+        let mut from = Some(p.punched_at);
+        let mut break_minutes = 0;
+        let mut break_from = None;
+        let mut clock_out = None;
+        while i < punches.len() && punches[i].username == p.username {
+            let q = &punches[i];
+            match Kind::parse(&q.kind) {
+                Some(Kind::ClockIn) => break,
+                Some(Kind::BreakStart) => {
+                    if let Some(f) = from.take() {
+                        worked.push((f, q.punched_at));
+                    }
+                    break_from = Some(q.punched_at);
+                }
+                Some(Kind::BreakEnd) => {
+                    if let Some(b) = break_from.take() {
+                        break_minutes += (q.punched_at - b).num_minutes();
+                    }
+                    from = Some(q.punched_at);
+                }
+                Some(Kind::ClockOut) => {
+                    if let Some(f) = from.take() {
+                        worked.push((f, q.punched_at));
+                    }
+                    clock_out = Some(q.punched_at);
+                    i += 1;
+                    break;
+                }
+                None => {}
+            }
+            i += 1;
+        }
+        if clock_out.is_none() {
+            // without a clock out nothing is counted until it is corrected
+            worked.clear();
+        }
+        let date = to_local(&p.punched_at).date();
+        let split = Split::of(&worked, break_minutes);
+        let rounded = Split::of(
+            &round_session(&worked, &p.punched_at, &clock_out, policy),
+            break_minutes,
+        );
+        let rounded_clock_out = clock_out.as_ref().map(|t| policy.clock_out(t));
+        match ret.last_mut() {
+            Some(d) if d.username == p.username && d.date == date => {
+                d.clock_out = clock_out;
+                d.split = merge(&d.split, &split);
+                d.rounded_clock_out = rounded_clock_out;
+                d.rounded = merge(&d.rounded, &rounded);
+            }
+            _ => ret.push(Day {
+                username: p.username.clone(),
+                date,
+                clock_in: p.punched_at,
+                clock_out,
+                split,
+                rounded_clock_in: policy.clock_in(&p.punched_at),
+                rounded_clock_out,
+                rounded,
+            }),
+        }
+    }
+    for d in ret.iter_mut() {
+        d.rounded = policy.day(&d.rounded);
+    }
+    ret
+}
+
+fn merge(a: &Split, b: &Split) -> Split {
+    let worked = a.worked + b.worked;
+    let overtime = (worked - LEGAL_DAILY_MINUTES).max(0);
+    Split {
+        break_minutes: a.break_minutes + b.break_minutes,
+        worked,
+        regular: worked - overtime,
+        overtime,
+        late_night: a.late_night + b.late_night,
+    }
+}
+
+/// Working days on local dates `start..end`, with approved corrections applied
+/// and rounded by the store's policy.
+pub fn load(
+    conn: &PgConnection,
+    start: NaiveDate,
+    end: NaiveDate,
+    username: Option<&str>,
+) -> QueryResult<Vec<Day>> {
+    let ps = corrections::effective(
+        conn,
+        from_local(&start.and_hms(0, 0, 0)),
+        // a session clocked in on the last day may end after midnight
+        Some(from_local(&(end + Duration::days(1)).and_hms(0, 0, 0))),
+        username,
+    )?;
+    let policy = Policy::load(conn)?;
+    Ok(days(&ps, &policy)
+        .into_iter()
+        .filter(|d| d.date < end)
+        .collect())
+}
+
+/// The worker's latest punch as corrected.
+pub fn last(conn: &PgConnection, username: &str) -> QueryResult<Option<Punch>> {
+    use schema::punches;
+    let latest = punches::table
+        .select(punches::punched_at)
+        .filter(punches::username.eq(username))
+        .order(punches::punched_at.desc())
+        .first::<NaiveDateTime>(conn)
+        .optional()?;
+    // a correction may move the latest punch back by up to a day
+    let from = latest.map_or(NaiveDate::from_ymd(1970, 1, 1).and_hms(0, 0, 0), |t| {
+        t - Duration::days(1)
+    });
+    corrections::effective(conn, from, None, Some(username)).map(|mut ps| ps.pop())
+}
@@ -0,0 +1,122 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use super::models::{Availability, WeeklyAvailability};
+use super::{from_local, schema, to_local};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Available,
+    Unavailable,
+    Preferred,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Available => "available",
+            Kind::Unavailable => "unavailable",
+            Kind::Preferred => "preferred",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Kind> {
+        match s {
+            "available" => Some(Kind::Available),
+            "unavailable" => Some(Kind::Unavailable),
+            "preferred" => Some(Kind::Preferred),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Window {
+    pub username: String,
+    pub start_time: NaiveDateTime,
+    pub end_time: NaiveDateTime,
+    pub kind: Kind,
+    // true if the window comes from the weekly defaults
+    pub weekly: bool,
+}
+
+/// Overlays explicit entries on the weekly defaults for local dates `start..end`.
+/// An explicit entry on a date replaces all of that user's defaults for the date.
+pub fn effective(
+    entries: &[Availability],
+    defaults: &[WeeklyAvailability],
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Vec<Window> {
+    let overridden: HashSet<(&str, NaiveDate)> = entries
+        .iter()
+        .map(|e| (e.username.as_str(), to_local(&e.start_time).date()))
+        .collect();
+    let mut ws: Vec<Window> = entries
+        .iter()
+        .filter_map(|e| {
+            Some(Window {
+                username: e.username.clone(),
+                start_time: e.start_time,
+                end_time: e.end_time,
+                kind: Kind::parse(&e.kind)?,
+                weekly: false,
+            })
+        })
+        .collect();
+    let mut d = start;
+    while d < end {
+        let wd = d.weekday().num_days_from_sunday() as i16;
+        for w in defaults.iter().filter(|w| w.weekday == wd) {
+            if overridden.contains(&(w.username.as_str(), d)) {
+                continue;
+            }
+            if let Some(kind) = Kind::parse(&w.kind) {
+                ws.push(Window {
+                    username: w.username.clone(),
+                    start_time: from_local(&d.and_time(w.start_time)),
+                    end_time: from_local(&d.and_time(w.end_time)),
+                    kind,
+                    weekly: true,
+                });
+            }
+        }
+        d += Duration::days(1);
+    }
+    ws.sort_by(|a, b| (&a.username, a.start_time).cmp(&(&b.username, b.start_time)));
+    ws
+}
+
+pub fn load(
+    conn: &PgConnection,
+    start: NaiveDate,
+    end: NaiveDate,
+    username: Option<&str>,
+) -> QueryResult<Vec<Window>> {
+    use schema::{availabilities, users, weekly_availabilities};
+    // deactivated staff are not offered shifts any more
+    let active = || {
+        users::table
+            .select(users::id)
+            .filter(users::deactivated_at.is_null())
+    };
+    let mut eq = availabilities::table
+        .filter(availabilities::start_time.ge(from_local(&start.and_hms(0, 0, 0))))
+        .filter(availabilities::start_time.lt(from_local(&end.and_hms(0, 0, 0))))
+        .filter(availabilities::username.eq_any(active()))
+        .into_boxed();
+    let mut wq = weekly_availabilities::table
+        .filter(weekly_availabilities::username.eq_any(active()))
+        .into_boxed();
+    if let Some(u) = username {
+        eq = eq.filter(availabilities::username.eq(u));
+        wq = wq.filter(weekly_availabilities::username.eq(u));
+    }
+    let entries = eq.get_results::<Availability>(conn)?;
+    let defaults = wq.get_results::<WeeklyAvailability>(conn)?;
+    Ok(effective(&entries, &defaults, start, end))
+}
@@ -0,0 +1,595 @@
+extern crate diesel;
+extern crate kintai;
+
+use chrono::{NaiveDate, Utc};
+use diesel::connection::{AnsiTransactionManager, TransactionManager};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde_json::{json, Value};
+use std::io::Write;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+use kintai::models::{Schedule, User};
+use kintai::*;
+
+#[derive(StructOpt)]
+#[structopt(name = "kintai-admin", about = "Administration tasks for kintai")]
+struct Opt {
+    /// Print JSON instead of text, for scripts
+    #[structopt(long, global = true)]
+    json: bool,
+    #[structopt(subcommand)]
+    cmd: Command,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Manage staff accounts
+    User(UserCommand),
+    /// List, import and export schedules
+    Schedule(ScheduleCommand),
+    /// Manage submission periods
+    Period(PeriodCommand),
+    /// Paid annual leave
+    Leave(LeaveCommand),
+    /// Create the administrator account if it does not exist yet, and demo data on request
+    Seed(SeedOptions),
+}
+
+#[derive(StructOpt)]
+struct SeedOptions {
+    #[structopt(long, default_value = "root")]
+    id: String,
+    /// Defaults to $ROOT_PASSWORD, or a generated password that is printed once
+    #[structopt(long)]
+    password: Option<String>,
+    /// Also create demo staff with wages, availability, schedules and attendance
+    #[structopt(long)]
+    demo: bool,
+    #[structopt(long, default_value = "10")]
+    staff: usize,
+    /// The same seed always yields the same data
+    #[structopt(long, default_value = "1")]
+    seed: u64,
+    /// Month to fill, as YYYY-MM; defaults to the current one
+    #[structopt(long)]
+    month: Option<String>,
+    /// Password shared by every demo account
+    #[structopt(long, default_value = "demo-pass")]
+    demo_password: String,
+}
+
+#[derive(StructOpt)]
+enum UserCommand {
+    /// Create a user; the password is generated unless given
+    Create {
+        id: String,
+        #[structopt(long)]
+        admin: bool,
+        #[structopt(long)]
+        first_name: Option<String>,
+        #[structopt(long)]
+        last_name: Option<String>,
+        #[structopt(long)]
+        password: Option<String>,
+    },
+    /// List users, without deactivated ones unless --all
+    List {
+        #[structopt(long)]
+        all: bool,
+    },
+    /// Keep a user's history but stop them from signing in
+    Deactivate { id: String },
+    /// Set a new password; one is generated unless given
+    ResetPassword {
+        id: String,
+        #[structopt(long)]
+        password: Option<String>,
+    },
+    /// Make a user an administrator
+    Promote { id: String },
+    /// Create users from an employee CSV; prints their initial passwords
+    Import {
+        file: PathBuf,
+        #[structopt(long)]
+        dry_run: bool,
+        #[structopt(long, default_value = "root")]
+        created_by: String,
+    },
+}
+
+#[derive(StructOpt)]
+enum ScheduleCommand {
+    /// List schedules starting on local dates start..end
+    List {
+        #[structopt(long)]
+        start: NaiveDate,
+        #[structopt(long)]
+        end: NaiveDate,
+        #[structopt(long)]
+        user: Option<String>,
+    },
+    /// Create assigned schedules from a flat CSV or a roster grid
+    Import {
+        file: PathBuf,
+        /// "flat" or "grid"; guessed from the header when absent
+        #[structopt(long)]
+        layout: Option<String>,
+        #[structopt(long)]
+        dry_run: bool,
+        #[structopt(long, default_value = "root")]
+        created_by: String,
+    },
+    /// Write schedules starting on local dates start..end as CSV to stdout
+    Export {
+        #[structopt(long)]
+        start: NaiveDate,
+        #[structopt(long)]
+        end: NaiveDate,
+        #[structopt(long)]
+        user: Option<String>,
+        #[structopt(long, default_value = "utf-8")]
+        encoding: String,
+    },
+}
+
+#[derive(StructOpt)]
+enum PeriodCommand {
+    /// Close a period now, ending submissions and freezing its attendance
+    Close { id: i64 },
+}
+
+#[derive(StructOpt)]
+enum LeaveCommand {
+    /// Record the statutory grants due by a date; safe to run daily
+    Accrue {
+        /// Defaults to today
+        #[structopt(long)]
+        date: Option<NaiveDate>,
+        #[structopt(long)]
+        user: Option<String>,
+    },
+}
+
+// what a command prints: `json` with --json, `text` otherwise
+struct Output {
+    json: Value,
+    text: String,
+}
+
+fn output(json: Value, text: impl Into<String>) -> Output {
+    Output {
+        json,
+        text: text.into(),
+    }
+}
+
+impl From<String> for Output {
+    fn from(e: String) -> Output {
+        output(json!({ "error": e }), e)
+    }
+}
+
+// failures are printed to stderr the same way, with --json or without
+type CliResult = Result<Output, Output>;
+
+fn user_json(u: &User) -> Value {
+    json!({
+        "id": u.id,
+        "first_name": u.first_name,
+        "last_name": u.last_name,
+        "isadmin": u.isadmin,
+        "active": u.deactivated_at.is_none(),
+        "employee_number": u.employee_number,
+        "hire_date": u.hire_date,
+    })
+}
+
+fn report(errors: &[String]) -> String {
+    if errors.is_empty() {
+        "ok".to_string()
+    } else {
+        errors.join("; ")
+    }
+}
+
+fn password(given: Option<String>) -> Result<String, String> {
+    match given {
+        Some(p) => Ok(p),
+        None => create_pg().generate_one().map_err(|e| e.to_string()),
+    }
+}
+
+// updates one user and fails when there is no such user
+fn update_user<F>(conn: &PgConnection, id: &str, f: F) -> Result<User, String>
+where
+    F: FnOnce(&PgConnection) -> QueryResult<usize>,
+{
+    use schema::users;
+    match f(conn).map_err(|e| e.to_string())? {
+        0 => Err(format!("user {} does not exist", id)),
+        _ => users::table
+            .filter(users::id.eq(id))
+            .get_result::<User>(conn)
+            .map_err(|e| e.to_string()),
+    }
+}
+
+fn user_command(conn: &PgConnection, cmd: UserCommand) -> CliResult {
+    use schema::users;
+
+    match cmd {
+        UserCommand::Create {
+            id,
+            admin,
+            first_name,
+            last_name,
+            password: given,
+        } => {
+            let pass = password(given)?;
+            let u = create_user(
+                conn,
+                &id,
+                &pass,
+                &admin,
+                first_name.as_deref(),
+                last_name.as_deref(),
+            )
+            .map_err(|e| e.to_string())?;
+            Ok(output(
+                json!({ "id": u.id, "pass": pass }),
+                format!("created {} with password {}", u.id, pass),
+            ))
+        }
+        UserCommand::List { all } => {
+            let mut q = users::table.order(users::id.asc()).into_boxed();
+            if !all {
+                q = q.filter(users::deactivated_at.is_null());
+            }
+            let us = q.get_results::<User>(conn).map_err(|e| e.to_string())?;
+            let text = us
+                .iter()
+                .map(|u| {
+                    format!(
+                        "{}\t{}\t{}{}",
+                        u.id,
+                        export::name(u),
+                        if u.isadmin { "admin" } else { "staff" },
+                        if u.deactivated_at.is_some() {
+                            "\tdeactivated"
+                        } else {
+                            ""
+                        }
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            Ok(output(us.iter().map(user_json).collect(), text))
+        }
+        UserCommand::Deactivate { id } => {
+            // deactivating twice keeps the original date
+            let u = update_user(conn, &id, |conn| {
+                diesel::update(users::table.filter(users::id.eq(&id)))
+                    .filter(users::deactivated_at.is_null())
+                    .set(users::deactivated_at.eq(Utc::now().naive_utc()))
+                    .execute(conn)?;
+                users::table
+                    .filter(users::id.eq(&id))
+                    .count()
+                    .get_result::<i64>(conn)
+                    .map(|n| n as usize)
+            })?;
+            Ok(output(user_json(&u), format!("deactivated {}", u.id)))
+        }
+        UserCommand::ResetPassword {
+            id,
+            password: given,
+        } => {
+            let pass = password(given)?;
+            match reset_password(conn, &id, &pass).map_err(|e| e.to_string())? {
+                0 => Err(format!("user {} does not exist", id).into()),
+                _ => Ok(output(
+                    json!({ "id": id, "pass": pass }),
+                    format!("new password for {}: {}", id, pass),
+                )),
+            }
+        }
+        UserCommand::Promote { id } => {
+            let u = update_user(conn, &id, |conn| {
+                diesel::update(users::table.filter(users::id.eq(&id)))
+                    .set(users::isadmin.eq(true))
+                    .execute(conn)
+            })?;
+            Ok(output(
+                user_json(&u),
+                format!("{} is now an administrator", u.id),
+            ))
+        }
+        UserCommand::Import {
+            file,
+            dry_run,
+            created_by,
+        } => {
+            let bytes = std::fs::read(&file).map_err(|e| e.to_string())?;
+            let ansi = AnsiTransactionManager::new();
+            ansi.begin_transaction(conn).map_err(|e| e.to_string())?;
+            let (es, rows) = employees::validate(conn, &employees::decode(&bytes))?;
+            let invalid = rows.iter().any(|r| !r.errors.is_empty());
+            let report = rows
+                .iter()
+                .map(|r| format!("line {}: {} {}", r.line, r.id, report(&r.errors)))
+                .collect::<Vec<_>>()
+                .join("\n");
+            if invalid || dry_run {
+                ansi.rollback_transaction(conn).map_err(|e| e.to_string())?;
+                let out = output(json!({ "dry_run": dry_run, "rows": rows }), report);
+                return if invalid { Err(out) } else { Ok(out) };
+            }
+            let passwords = create_pg().generate(es.len()).map_err(|e| e.to_string())?;
+            let creds =
+                employees::import(conn, &es, &passwords, &created_by).map_err(|e| e.to_string())?;
+            ansi.commit_transaction(conn).map_err(|e| e.to_string())?;
+            let csv = employees::credentials_csv(&creds).map_err(|e| e.to_string())?;
+            Ok(output(
+                json!({ "dry_run": false, "rows": rows, "credentials": creds }),
+                String::from_utf8_lossy(&csv[3..]).trim_end().to_string(),
+            ))
+        }
+    }
+}
+
+fn schedule_text(s: &Schedule) -> String {
+    format!(
+        "{}\t{}\t{}\t{}\t{}",
+        s.id,
+        s.username,
+        to_local(&s.start_time).format("%Y-%m-%d %H:%M"),
+        to_local(&s.end_time).format("%Y-%m-%d %H:%M"),
+        s.status.as_str()
+    )
+}
+
+fn schedules(
+    conn: &PgConnection,
+    start: NaiveDate,
+    end: NaiveDate,
+    user: Option<&str>,
+) -> QueryResult<Vec<Schedule>> {
+    use schema::schedules;
+    let mut q = schedules::table
+        .filter(schedules::start_time.ge(from_local(&start.and_hms(0, 0, 0))))
+        .filter(schedules::start_time.lt(from_local(&end.and_hms(0, 0, 0))))
+        .into_boxed();
+    if let Some(u) = user {
+        q = q.filter(schedules::username.eq(u));
+    }
+    q.order((schedules::start_time.asc(), schedules::id.asc()))
+        .get_results::<Schedule>(conn)
+}
+
+fn schedule_command(conn: &PgConnection, cmd: ScheduleCommand) -> CliResult {
+    match cmd {
+        ScheduleCommand::List { start, end, user } => {
+            let ss = schedules(conn, start, end, user.as_deref()).map_err(|e| e.to_string())?;
+            let text = ss.iter().map(schedule_text).collect::<Vec<_>>().join("\n");
+            Ok(output(json!(ss), text))
+        }
+        ScheduleCommand::Import {
+            file,
+            layout,
+            dry_run,
+            created_by,
+        } => {
+            let layout = match layout {
+                None => None,
+                Some(x) => Some(import::Format::parse(&x).ok_or(format!("unknown layout: {}", x))?),
+            };
+            let bytes = std::fs::read(&file).map_err(|e| e.to_string())?;
+            let ansi = AnsiTransactionManager::new();
+            ansi.begin_transaction(conn).map_err(|e| e.to_string())?;
+            let rows = import::validate(conn, &employees::decode(&bytes), layout)?;
+            let invalid = rows.iter().any(|r| !r.errors.is_empty());
+            let report = rows
+                .iter()
+                .map(|r| {
+                    format!(
+                        "line {}{}: {} {}",
+                        r.line,
+                        r.column
+                            .map(|c| format!(" column {}", c))
+                            .unwrap_or_default(),
+                        r.username,
+                        report(&r.errors)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            if invalid || dry_run {
+                ansi.rollback_transaction(conn).map_err(|e| e.to_string())?;
+                let out = output(json!({ "dry_run": dry_run, "rows": rows }), report);
+                return if invalid { Err(out) } else { Ok(out) };
+            }
+            let created = import::import(conn, &rows, &created_by).map_err(|e| e.to_string())?;
+            ansi.commit_transaction(conn).map_err(|e| e.to_string())?;
+            let text = created
+                .iter()
+                .map(schedule_text)
+                .collect::<Vec<_>>()
+                .join("\n");
+            Ok(output(
+                json!({ "dry_run": false, "rows": rows, "schedules": created }),
+                text,
+            ))
+        }
+        ScheduleCommand::Export {
+            start,
+            end,
+            user,
+            encoding,
+        } => {
+            use schema::users;
+            let enc = export::Encoding::parse(&encoding)
+                .ok_or(format!("unknown encoding: {}", encoding))?;
+            let names = users::table
+                .get_results::<User>(conn)
+                .map_err(|e| e.to_string())?
+                .into_iter()
+                .map(|u| (u.id.clone(), export::name(&u)))
+                .collect::<std::collections::HashMap<_, _>>();
+            let ss = schedules(conn, start, end, user.as_deref()).map_err(|e| e.to_string())?;
+            let mut out = std::io::stdout();
+            let write =
+                |out: &mut std::io::Stdout, b: &[u8]| out.write_all(b).map_err(|e| e.to_string());
+            write(&mut out, enc.preamble())?;
+            write(
+                &mut out,
+                &export::chunk(enc, vec![export::SCHEDULE_HEADER]).map_err(|e| e.to_string())?,
+            )?;
+            write(
+                &mut out,
+                &export::chunk(
+                    enc,
+                    ss.iter().map(|s| {
+                        let name = names.get(&s.username).map(|x| x.as_str());
+                        export::schedule_record(s, name.unwrap_or(""))
+                    }),
+                )
+                .map_err(|e| e.to_string())?,
+            )?;
+            // the CSV itself is the output
+            Ok(output(Value::Null, ""))
+        }
+    }
+}
+
+fn period_command(conn: &PgConnection, cmd: PeriodCommand) -> CliResult {
+    match cmd {
+        PeriodCommand::Close { id } => {
+            let p = period::close(conn, id, Utc::now().naive_utc())
+                .optional()
+                .map_err(|e| e.to_string())?
+                .ok_or(format!("period {} does not exist", id))?;
+            Ok(output(
+                json!(p),
+                format!(
+                    "period {} ({} - {}) closed at {}",
+                    p.id,
+                    p.start_date,
+                    p.end_date,
+                    to_local(&p.closed_at.unwrap_or(p.deadline)).format("%Y-%m-%d %H:%M")
+                ),
+            ))
+        }
+    }
+}
+
+fn leave_command(conn: &PgConnection, cmd: LeaveCommand) -> CliResult {
+    match cmd {
+        LeaveCommand::Accrue { date, user } => {
+            let date = date.unwrap_or_else(|| to_local(&Utc::now().naive_utc()).date());
+            let gs = leave::accrue(conn, user.as_deref(), date).map_err(|e| e.to_string())?;
+            let text = gs
+                .iter()
+                .map(|g| {
+                    format!(
+                        "{}\t{}\t{} days\texpires {}",
+                        g.username, g.granted_on, g.days, g.expires_on
+                    )
+                })
+                .chain(std::iter::once(format!("{} grants recorded", gs.len())))
+                .collect::<Vec<_>>()
+                .join("\n");
+            Ok(output(json!(gs), text))
+        }
+    }
+}
+
+fn seed(conn: &PgConnection, opts: SeedOptions) -> CliResult {
+    use schema::users;
+
+    let exists = users::table
+        .filter(users::id.eq(&opts.id))
+        .count()
+        .get_result::<i64>(conn)
+        .map_err(|e| e.to_string())?
+        > 0;
+    let mut ret = json!({ "id": opts.id, "created": !exists });
+    let mut text = if exists {
+        format!("{} already exists", opts.id)
+    } else {
+        let pass = password(
+            opts.password
+                .or_else(|| std::env::var("ROOT_PASSWORD").ok()),
+        )?;
+        create_user(conn, &opts.id, &pass, &true, None, None).map_err(|e| e.to_string())?;
+        ret["pass"] = json!(pass);
+        format!("created {} with password {}", opts.id, pass)
+    };
+    if opts.demo {
+        let today = to_local(&Utc::now().naive_utc()).date();
+        let month = match opts.month {
+            None => today,
+            Some(m) => NaiveDate::parse_from_str(&format!("{}-01", m), "%Y-%m-%d")
+                .map_err(|_| format!("cannot parse month: {}", m))?,
+        };
+        let summary = seed::run(
+            conn,
+            &seed::Options {
+                seed: opts.seed,
+                staff: opts.staff,
+                month,
+                today,
+                password: opts.demo_password,
+                created_by: opts.id,
+            },
+        )
+        .map_err(|e| e.to_string())?;
+        text += &format!(
+            "\ndemo: {} users, {} wages, {} weekly availabilities, {} availabilities, \
+             {} schedules ({} absences), {} punches",
+            summary.users,
+            summary.wages,
+            summary.weekly_availabilities,
+            summary.availabilities,
+            summary.schedules,
+            summary.absences,
+            summary.punches
+        );
+        ret["demo"] = json!(summary);
+    }
+    Ok(output(ret, text))
+}
+
+fn render(out: &Output, json: bool) -> String {
+    if json {
+        out.json.to_string()
+    } else {
+        out.text.clone()
+    }
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let pool = establish_connection().expect("cannot connect to DATABASE_URL");
+    let conn = pool.get().expect("cannot connect to DATABASE_URL");
+    let ret = match opt.cmd {
+        Command::User(cmd) => user_command(&conn, cmd),
+        Command::Schedule(cmd) => schedule_command(&conn, cmd),
+        Command::Period(cmd) => period_command(&conn, cmd),
+        Command::Leave(cmd) => leave_command(&conn, cmd),
+        Command::Seed(opts) => seed(&conn, opts),
+    };
+    match ret {
+        Ok(out) => {
+            // commands that write their own output, like exports, leave both empty
+            if !out.json.is_null() {
+                println!("{}", render(&out, opt.json));
+            }
+        }
+        Err(out) => {
+            eprintln!("{}", render(&out, opt.json));
+            std::process::exit(1);
+        }
+    }
+}
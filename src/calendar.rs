@@ -0,0 +1,196 @@
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+use super::models::StoreDay;
+use super::schema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    // 国民の祝日, 振替休日 and 国民の休日
+    NationalHoliday,
+    // the store does not open; no shifts
+    Closed,
+    // open, with the special-day premium
+    Special,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::NationalHoliday => "national_holiday",
+            Kind::Closed => "closed",
+            Kind::Special => "special",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Kind> {
+        match s {
+            "national_holiday" => Some(Kind::NationalHoliday),
+            "closed" => Some(Kind::Closed),
+            "special" => Some(Kind::Special),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Entry {
+    pub date: NaiveDate,
+    pub kind: Kind,
+    pub name: String,
+}
+
+fn nth_monday(year: i32, month: u32, n: u8) -> NaiveDate {
+    NaiveDate::from_weekday_of_month(year, month, Weekday::Mon, n)
+}
+
+// years the equinox approximation holds for; no holidays are computed outside
+const FIRST_YEAR: i32 = 1980;
+const LAST_YEAR: i32 = 2150;
+
+// 海上保安庁 / 国立天文台 approximation, good for FIRST_YEAR..=LAST_YEAR
+fn equinox(year: i32, spring: bool) -> u32 {
+    let base = match (spring, year < 2100) {
+        (true, true) => 20.8431,
+        (true, false) => 21.8510,
+        (false, true) => 23.2488,
+        (false, false) => 24.2488,
+    };
+    let y = (year - 1980) as f64;
+    (base + 0.242194 * y - (y / 4.0).floor()).floor() as u32
+}
+
+// 国民の祝日に関する法律 as in force since 2000, with the one-off moves of 2019-2021
+fn statutory(year: i32) -> Vec<(NaiveDate, &'static str)> {
+    let d = |m, d| NaiveDate::from_ymd(year, m, d);
+    let mut ret = vec![
+        (d(1, 1), "元日"),
+        (nth_monday(year, 1, 2), "成人の日"),
+        (d(2, 11), "建国記念の日"),
+        (d(3, equinox(year, true)), "春分の日"),
+        (d(5, 3), "憲法記念日"),
+        (d(5, 5), "こどもの日"),
+        (d(9, equinox(year, false)), "秋分の日"),
+        (d(11, 3), "文化の日"),
+        (d(11, 23), "勤労感謝の日"),
+    ];
+    if year >= 2020 {
+        ret.push((d(2, 23), "天皇誕生日"));
+    } else if year <= 2018 {
+        ret.push((d(12, 23), "天皇誕生日"));
+    }
+    if year >= 2007 {
+        ret.push((d(4, 29), "昭和の日"));
+        ret.push((d(5, 4), "みどりの日"));
+    } else {
+        ret.push((d(4, 29), "みどりの日"));
+    }
+    ret.push(match year {
+        2020 => (d(7, 23), "海の日"),
+        2021 => (d(7, 22), "海の日"),
+        y if y >= 2003 => (nth_monday(year, 7, 3), "海の日"),
+        _ => (d(7, 20), "海の日"),
+    });
+    match year {
+        2020 => ret.push((d(8, 10), "山の日")),
+        2021 => ret.push((d(8, 8), "山の日")),
+        y if y >= 2016 => ret.push((d(8, 11), "山の日")),
+        _ => {}
+    }
+    ret.push(if year >= 2003 {
+        (nth_monday(year, 9, 3), "敬老の日")
+    } else {
+        (d(9, 15), "敬老の日")
+    });
+    ret.push(match year {
+        2020 => (d(7, 24), "スポーツの日"),
+        2021 => (d(7, 23), "スポーツの日"),
+        y if y >= 2020 => (nth_monday(year, 10, 2), "スポーツの日"),
+        _ => (nth_monday(year, 10, 2), "体育の日"),
+    });
+    if year == 2019 {
+        ret.push((d(5, 1), "天皇の即位の日"));
+        ret.push((d(10, 22), "即位礼正殿の儀の行われる日"));
+    }
+    ret
+}
+
+/// National holidays of a year, computed without any data file: the statutory
+/// days, 振替休日 (a holiday on Sunday moves to the next non-holiday) and
+/// 国民の休日 (a weekday between two holidays). Empty for years before 1980
+/// or after 2150, where the equinox dates are not known.
+pub fn national_holidays(year: i32) -> Vec<(NaiveDate, &'static str)> {
+    if !(FIRST_YEAR..=LAST_YEAR).contains(&year) {
+        return vec![];
+    }
+    let base: BTreeMap<NaiveDate, &'static str> = statutory(year).into_iter().collect();
+    let mut ret = base.clone();
+    for d in base.keys().filter(|d| d.weekday() == Weekday::Sun) {
+        let mut next = *d + Duration::days(1);
+        while base.contains_key(&next) {
+            next += Duration::days(1);
+        }
+        ret.insert(next, "振替休日");
+    }
+    for d in base.keys() {
+        let between = *d + Duration::days(1);
+        if base.contains_key(&(between + Duration::days(1)))
+            && !ret.contains_key(&between)
+            && between.weekday() != Weekday::Sun
+        {
+            ret.insert(between, "国民の休日");
+        }
+    }
+    ret.into_iter().filter(|(d, _)| d.year() == year).collect()
+}
+
+/// National holidays and store days on dates `start..end`, by date. A store
+/// day on a holiday is listed next to it.
+pub fn load(conn: &PgConnection, start: NaiveDate, end: NaiveDate) -> QueryResult<Vec<Entry>> {
+    use schema::store_days;
+    let mut ret: Vec<Entry> = (start.year().max(FIRST_YEAR)..=end.year().min(LAST_YEAR))
+        .flat_map(national_holidays)
+        .filter(|(d, _)| start <= *d && *d < end)
+        .map(|(date, name)| Entry {
+            date,
+            kind: Kind::NationalHoliday,
+            name: name.to_string(),
+        })
+        .collect();
+    ret.extend(
+        store_days::table
+            .filter(store_days::day.ge(start))
+            .filter(store_days::day.lt(end))
+            .get_results::<StoreDay>(conn)?
+            .into_iter()
+            .filter_map(|s| {
+                Some(Entry {
+                    date: s.day,
+                    kind: Kind::parse(&s.kind)?,
+                    name: s.name,
+                })
+            }),
+    );
+    ret.sort_by_key(|e| (e.date, e.kind as u8));
+    Ok(ret)
+}
+
+/// Dates in `start..end` the store is closed on.
+pub fn closed(
+    conn: &PgConnection,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> QueryResult<HashSet<NaiveDate>> {
+    use schema::store_days;
+    store_days::table
+        .select(store_days::day)
+        .filter(store_days::kind.eq(Kind::Closed.as_str()))
+        .filter(store_days::day.ge(start))
+        .filter(store_days::day.lt(end))
+        .get_results::<NaiveDate>(conn)
+        .map(|ds| ds.into_iter().collect())
+}
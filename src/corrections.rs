@@ -0,0 +1,199 @@
+use chrono::{Duration, NaiveDateTime};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::attendance::Kind;
+use super::models::{Punch, PunchCorrection};
+use super::schedule::{Actor, TransitionError};
+use super::schema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Requested,
+    Approved,
+    Rejected,
+    Cancelled,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Requested => "requested",
+            Status::Approved => "approved",
+            Status::Rejected => "rejected",
+            Status::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Status> {
+        match s {
+            "requested" => Some(Status::Requested),
+            "approved" => Some(Status::Approved),
+            "rejected" => Some(Status::Rejected),
+            "cancelled" => Some(Status::Cancelled),
+            _ => None,
+        }
+    }
+}
+
+/// Who may move a correction where. Managers decide on requests and may take
+/// back an approval; workers may withdraw their own while it is pending.
+pub fn transition(
+    c: &PunchCorrection,
+    actor: &Actor,
+    to: Status,
+) -> Result<Status, TransitionError> {
+    let from = Status::parse(&c.status).ok_or(TransitionError::InvalidState)?;
+    let permitted = match (from, to) {
+        (Status::Requested, Status::Approved)
+        | (Status::Requested, Status::Rejected)
+        | (Status::Approved, Status::Cancelled) => actor.isadmin,
+        (Status::Requested, Status::Cancelled) => actor.isadmin || c.username == actor.username,
+        _ => return Err(TransitionError::InvalidState),
+    };
+    if permitted {
+        Ok(to)
+    } else {
+        Err(TransitionError::NotPermitted)
+    }
+}
+
+/// Applies approved corrections to punches: a moved punch keeps its id with the
+/// corrected time, an added one gets the correction's id negated. The result
+/// is sorted by user and time, as `attendance::days` expects.
+pub fn apply(punches: Vec<Punch>, corrections: &[PunchCorrection]) -> Vec<Punch> {
+    let mut ret: BTreeMap<i64, Punch> = punches.into_iter().map(|p| (p.id, p)).collect();
+    for c in corrections {
+        match (c.punch_id, &c.kind) {
+            (Some(id), _) => {
+                if let Some(p) = ret.get_mut(&id) {
+                    p.punched_at = c.punched_at;
+                }
+            }
+            (None, Some(kind)) => {
+                ret.insert(
+                    -c.id,
+                    Punch {
+                        id: -c.id,
+                        username: c.username.clone(),
+                        kind: kind.clone(),
+                        punched_at: c.punched_at,
+                        created_at: c.decided_at.unwrap_or(c.created_at),
+                        kiosk_id: None,
+                        client_ip: None,
+                        off_network: false,
+                    },
+                );
+            }
+            (None, None) => {}
+        }
+    }
+    let mut ret: Vec<Punch> = ret.into_values().collect();
+    ret.sort_by(|a, b| (&a.username, a.punched_at, a.id).cmp(&(&b.username, b.punched_at, b.id)));
+    ret
+}
+
+/// The first of the user's punches around `c` that could not follow the one
+/// before it, with that one's kind. Run it after `c`'s status is written, in
+/// the same transaction, so approving and cancelling it are both checked. Shifts are taken to be shorter than a day: punches from the day
+/// around the correction are checked, and leading ones before it that are not
+/// a clock in are left alone as the end of an earlier shift.
+pub fn out_of_sequence(
+    conn: &PgConnection,
+    c: &PunchCorrection,
+) -> QueryResult<Option<(Option<Kind>, Punch)>> {
+    use schema::punches;
+    let mut times = vec![c.punched_at];
+    if let Some(id) = c.punch_id {
+        times.push(
+            punches::table
+                .select(punches::punched_at)
+                .filter(punches::id.eq(id))
+                .get_result::<NaiveDateTime>(conn)?,
+        );
+    }
+    let lo = *times.iter().min().unwrap();
+    let hi = *times.iter().max().unwrap();
+    let ps = effective(
+        conn,
+        lo - Duration::days(1),
+        Some(hi + Duration::days(1)),
+        Some(&c.username),
+    )?;
+    let mut last = None;
+    for p in ps
+        .into_iter()
+        .skip_while(|p| p.punched_at < lo && p.kind != Kind::ClockIn.as_str())
+    {
+        let kind = Kind::parse(&p.kind);
+        if !matches!(kind, Some(k) if k.may_follow(last)) {
+            return Ok(Some((last, p)));
+        }
+        last = kind;
+    }
+    Ok(None)
+}
+
+/// Punches as corrected whose corrected time is in `[from, to)`, or from
+/// `from` on when `to` is None.
+pub fn effective(
+    conn: &PgConnection,
+    from: NaiveDateTime,
+    to: Option<NaiveDateTime>,
+    username: Option<&str>,
+) -> QueryResult<Vec<Punch>> {
+    use schema::{punch_corrections, punches};
+    let mut q = punches::table
+        .filter(punches::punched_at.ge(from))
+        .into_boxed();
+    if let Some(t) = to {
+        q = q.filter(punches::punched_at.lt(t));
+    }
+    if let Some(u) = username {
+        q = q.filter(punches::username.eq(u));
+    }
+    let mut ps = q.get_results::<Punch>(conn)?;
+    let ids: Vec<i64> = ps.iter().map(|p| p.id).collect();
+
+    // corrections of the punches above, and those moving or adding one into the range
+    let mut q = punch_corrections::table
+        .filter(punch_corrections::status.eq(Status::Approved.as_str()))
+        .into_boxed();
+    q = match to {
+        Some(t) => q.filter(
+            punch_corrections::punch_id
+                .eq_any(ids.clone())
+                .or(punch_corrections::punched_at
+                    .ge(from)
+                    .and(punch_corrections::punched_at.lt(t))),
+        ),
+        None => q.filter(
+            punch_corrections::punch_id
+                .eq_any(ids.clone())
+                .or(punch_corrections::punched_at.ge(from)),
+        ),
+    };
+    if let Some(u) = username {
+        q = q.filter(punch_corrections::username.eq(u));
+    }
+    let cs = q.get_results::<PunchCorrection>(conn)?;
+    let moved_in: Vec<i64> = cs
+        .iter()
+        .filter_map(|c| c.punch_id)
+        .filter(|id| !ids.contains(id))
+        .collect();
+    if !moved_in.is_empty() {
+        ps.extend(
+            punches::table
+                .filter(punches::id.eq_any(moved_in))
+                .get_results::<Punch>(conn)?,
+        );
+    }
+    Ok(apply(ps, &cs)
+        .into_iter()
+        .filter(|p| from <= p.punched_at && to.iter().all(|t| p.punched_at < *t))
+        .collect())
+}
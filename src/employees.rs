@@ -0,0 +1,239 @@
+use chrono::{Local, NaiveDate};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::Serialize;
+use std::collections::HashSet;
+
+use super::{create_user, schema, CreateUserError};
+
+pub const COLUMNS: [&str; 7] = [
+    "id",
+    "last_name",
+    "first_name",
+    "employee_number",
+    "hire_date",
+    "hourly_wage",
+    "isadmin",
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Employee {
+    pub id: String,
+    pub last_name: Option<String>,
+    pub first_name: Option<String>,
+    pub employee_number: Option<String>,
+    pub hire_date: Option<NaiveDate>,
+    pub hourly_wage: Option<i32>,
+    pub isadmin: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RowReport {
+    // 1-based line in the file, counting the header
+    pub line: usize,
+    pub id: String,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Credential {
+    pub id: String,
+    pub pass: String,
+}
+
+/// Reads the upload as UTF-8 (with or without a BOM), falling back to Shift_JIS
+/// since that is what older Excel saves as "CSV".
+pub fn decode(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(s) => s.to_string(),
+        Err(_) => encoding_rs::SHIFT_JIS.decode(bytes).0.into_owned(),
+    }
+}
+
+fn field(r: &csv::StringRecord, header: &[usize; 7], i: usize) -> Option<String> {
+    r.get(header[i])
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+}
+
+fn parse_row(
+    r: &csv::StringRecord,
+    header: &[usize; 7],
+) -> (String, Result<Employee, Vec<String>>) {
+    let mut errors = Vec::new();
+    let id = field(r, header, 0).unwrap_or_default();
+    if id.is_empty() {
+        errors.push("id is required".to_string());
+    } else if id.chars().any(char::is_whitespace) {
+        errors.push("id must not contain spaces".to_string());
+    }
+    let hire_date = field(r, header, 4).and_then(|x| {
+        NaiveDate::parse_from_str(&x, "%Y-%m-%d")
+            .or_else(|_| NaiveDate::parse_from_str(&x, "%Y/%m/%d"))
+            .map_err(|_| errors.push(format!("cannot parse hire_date: {}", x)))
+            .ok()
+    });
+    let hourly_wage = field(r, header, 5).and_then(|x| {
+        x.parse::<i32>().ok().filter(|w| *w > 0).or_else(|| {
+            errors.push(format!("hourly_wage must be a positive integer: {}", x));
+            None
+        })
+    });
+    let isadmin = match field(r, header, 6).as_deref() {
+        None | Some("false") | Some("0") | Some("no") => false,
+        Some("true") | Some("1") | Some("yes") => true,
+        Some(x) => {
+            errors.push(format!("cannot parse isadmin: {}", x));
+            false
+        }
+    };
+    let ret = if errors.is_empty() {
+        Ok(Employee {
+            id: id.clone(),
+            last_name: field(r, header, 1),
+            first_name: field(r, header, 2),
+            employee_number: field(r, header, 3),
+            hire_date,
+            hourly_wage,
+            isadmin,
+        })
+    } else {
+        Err(errors)
+    };
+    (id, ret)
+}
+
+/// Parses the file and checks every row, including against existing users.
+/// Fails only when the header itself is unusable.
+pub fn validate(
+    conn: &PgConnection,
+    text: &str,
+) -> Result<(Vec<Employee>, Vec<RowReport>), String> {
+    use schema::users;
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let names = rdr.headers().map_err(|e| e.to_string())?.clone();
+    if let Some(x) = names.iter().find(|x| !COLUMNS.contains(&x.trim())) {
+        return Err(format!("unknown column: {}", x));
+    }
+    let mut header = [usize::MAX; 7];
+    for (i, c) in COLUMNS.iter().enumerate() {
+        header[i] = names
+            .iter()
+            .position(|x| x.trim() == *c)
+            .unwrap_or(usize::MAX);
+    }
+    if header[0] == usize::MAX {
+        return Err("id column is required".to_string());
+    }
+
+    let existing_ids: HashSet<String> = users::table
+        .select(users::id)
+        .get_results::<String>(conn)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .collect();
+    let existing_numbers: HashSet<String> = users::table
+        .select(users::employee_number)
+        .get_results::<Option<String>>(conn)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .flatten()
+        .collect();
+
+    let mut ids = HashSet::new();
+    let mut numbers = HashSet::new();
+    let mut employees = Vec::new();
+    let mut reports = Vec::new();
+    for (i, r) in rdr.records().enumerate() {
+        let line = i + 2;
+        let (id, parsed) = match r {
+            Ok(r) => parse_row(&r, &header),
+            Err(e) => (String::new(), Err(vec![e.to_string()])),
+        };
+        let mut errors = parsed.as_ref().err().cloned().unwrap_or_default();
+        if let Ok(e) = &parsed {
+            if existing_ids.contains(&e.id) {
+                errors.push(format!("user {} already exists", e.id));
+            }
+            if !ids.insert(e.id.clone()) {
+                errors.push(format!("id {} appears more than once", e.id));
+            }
+            if let Some(n) = &e.employee_number {
+                if existing_numbers.contains(n) {
+                    errors.push(format!("employee_number {} is already used", n));
+                }
+                if !numbers.insert(n.clone()) {
+                    errors.push(format!("employee_number {} appears more than once", n));
+                }
+            }
+        }
+        if let (Ok(e), true) = (parsed, errors.is_empty()) {
+            employees.push(e);
+        }
+        reports.push(RowReport { line, id, errors });
+    }
+    Ok((employees, reports))
+}
+
+/// Creates the users, their profiles and initial wages. `passwords` pairs up
+/// with `employees`; run it inside a transaction so a failure leaves nothing behind.
+pub fn import(
+    conn: &PgConnection,
+    employees: &[Employee],
+    passwords: &[String],
+    created_by: &str,
+) -> Result<Vec<Credential>, CreateUserError> {
+    use schema::{users, wages};
+
+    let today = Local::now().naive_local().date();
+    let mut ret = Vec::new();
+    for (e, pass) in employees.iter().zip(passwords) {
+        create_user(
+            conn,
+            &e.id,
+            pass,
+            &e.isadmin,
+            e.first_name.as_deref(),
+            e.last_name.as_deref(),
+        )?;
+        diesel::update(users::table.filter(users::id.eq(&e.id)))
+            .set((
+                users::employee_number.eq(&e.employee_number),
+                users::hire_date.eq(&e.hire_date),
+            ))
+            .execute(conn)
+            .map_err(CreateUserError::QueryError)?;
+        if let Some(w) = e.hourly_wage {
+            diesel::insert_into(wages::table)
+                .values((
+                    wages::username.eq(&e.id),
+                    wages::hourly_wage.eq(w),
+                    wages::effective_from.eq(e.hire_date.unwrap_or(today)),
+                    wages::created_by.eq(created_by),
+                ))
+                .execute(conn)
+                .map_err(CreateUserError::QueryError)?;
+        }
+        ret.push(Credential {
+            id: e.id.clone(),
+            pass: pass.clone(),
+        });
+    }
+    Ok(ret)
+}
+
+pub fn credentials_csv(creds: &[Credential]) -> csv::Result<Vec<u8>> {
+    let mut w = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::CRLF)
+        .from_writer(b"\xef\xbb\xbf".to_vec());
+    w.write_record(["id", "pass"])?;
+    for c in creds {
+        w.write_record([&c.id, &c.pass])?;
+    }
+    w.into_inner().map_err(|e| e.into_error().into())
+}
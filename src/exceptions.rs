@@ -0,0 +1,228 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::attendance;
+use super::models::{ExceptionResolution, Schedule};
+use super::schedule::Status;
+use super::{from_local, schema, settings};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Late,
+    EarlyLeave,
+    // a shift passed without a clock in and without an approved absence
+    NoShow,
+    MissingClockOut,
+    // clocked in on a day without a shift
+    Unscheduled,
+    // punched from outside the store's networks, accepted but flagged
+    OffNetwork,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Late => "late",
+            Kind::EarlyLeave => "early_leave",
+            Kind::NoShow => "no_show",
+            Kind::MissingClockOut => "missing_clock_out",
+            Kind::Unscheduled => "unscheduled",
+            Kind::OffNetwork => "off_network",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Kind> {
+        match s {
+            "late" => Some(Kind::Late),
+            "early_leave" => Some(Kind::EarlyLeave),
+            "no_show" => Some(Kind::NoShow),
+            "missing_clock_out" => Some(Kind::MissingClockOut),
+            "unscheduled" => Some(Kind::Unscheduled),
+            "off_network" => Some(Kind::OffNetwork),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Exception {
+    pub username: String,
+    pub date: NaiveDate,
+    pub kind: Kind,
+    // the shift the punches were compared with
+    pub schedule_id: Option<i64>,
+    pub expected: Option<NaiveDateTime>,
+    pub actual: Option<NaiveDateTime>,
+    // how late or how early
+    pub minutes: Option<i64>,
+    pub resolution: Option<ExceptionResolution>,
+}
+
+// a worker's shifts on the day, as one span from the first start to the last end
+struct Span {
+    schedule_id: i64,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    last_id: i64,
+}
+
+/// Compares the shifts and punches of local date `date` as of `now`. Nothing
+/// is reported about a shift before it is due: a no-show only once the grace
+/// after its start has passed, a missing clock out only once its end has.
+pub fn detect(
+    conn: &PgConnection,
+    date: NaiveDate,
+    now: NaiveDateTime,
+) -> QueryResult<Vec<Exception>> {
+    use schema::{exception_resolutions, punches, schedules};
+    let grace = Duration::minutes(settings::get_number(
+        conn,
+        settings::EXCEPTION_GRACE_MINUTES,
+    )?);
+    let (day_start, day_end) = (
+        from_local(&date.and_hms(0, 0, 0)),
+        from_local(&date.succ().and_hms(0, 0, 0)),
+    );
+    // an absence still waiting for a manager does not excuse anyone yet
+    let ss = schedules::table
+        .filter(schedules::start_time.ge(day_start))
+        .filter(schedules::start_time.lt(day_end))
+        .filter(schedules::status.eq_any(vec![Status::Approved, Status::AbsenceRequested]))
+        .order(schedules::start_time.asc())
+        .get_results::<Schedule>(conn)?;
+    let mut spans: BTreeMap<String, Span> = BTreeMap::new();
+    for s in ss {
+        let e = spans.entry(s.username.clone()).or_insert(Span {
+            schedule_id: s.id,
+            start: s.start_time,
+            end: s.end_time,
+            last_id: s.id,
+        });
+        if s.end_time > e.end {
+            e.end = s.end_time;
+            e.last_id = s.id;
+        }
+    }
+    let days: BTreeMap<String, attendance::Day> = attendance::load(conn, date, date.succ(), None)?
+        .into_iter()
+        .map(|d| (d.username.clone(), d))
+        .collect();
+
+    let mut ret = Vec::new();
+    let mut push = |username: &str,
+                    kind: Kind,
+                    schedule_id: Option<i64>,
+                    expected: Option<NaiveDateTime>,
+                    actual: Option<NaiveDateTime>| {
+        let minutes = expected
+            .zip(actual)
+            .map(|(e, a)| (a - e).num_minutes().abs());
+        ret.push(Exception {
+            username: username.to_string(),
+            date,
+            kind,
+            schedule_id,
+            expected,
+            actual,
+            minutes,
+            resolution: None,
+        });
+    };
+    for (u, s) in &spans {
+        match days.get(u) {
+            None if s.start + grace < now => {
+                push(u, Kind::NoShow, Some(s.schedule_id), Some(s.start), None)
+            }
+            None => {}
+            Some(d) => {
+                if d.clock_in > s.start + grace {
+                    push(
+                        u,
+                        Kind::Late,
+                        Some(s.schedule_id),
+                        Some(s.start),
+                        Some(d.clock_in),
+                    );
+                }
+                match d.clock_out {
+                    Some(o) if o + grace < s.end => {
+                        push(u, Kind::EarlyLeave, Some(s.last_id), Some(s.end), Some(o))
+                    }
+                    None if s.end + grace < now => {
+                        push(u, Kind::MissingClockOut, Some(s.last_id), Some(s.end), None)
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    for (u, d) in &days {
+        if spans.contains_key(u) {
+            continue;
+        }
+        push(u, Kind::Unscheduled, None, None, Some(d.clock_in));
+        if d.clock_out.is_none() && day_end < now {
+            push(u, Kind::MissingClockOut, None, None, None);
+        }
+    }
+    let mut flagged: Vec<(String, NaiveDateTime)> = punches::table
+        .select((punches::username, punches::punched_at))
+        .filter(punches::off_network.eq(true))
+        .filter(punches::punched_at.ge(day_start))
+        .filter(punches::punched_at.lt(day_end))
+        .order((punches::username.asc(), punches::punched_at.asc()))
+        .get_results(conn)?;
+    flagged.dedup_by(|a, b| a.0 == b.0);
+    for (u, t) in &flagged {
+        push(u, Kind::OffNetwork, None, None, Some(*t));
+    }
+
+    let mut resolutions: BTreeMap<(String, String), ExceptionResolution> =
+        exception_resolutions::table
+            .filter(exception_resolutions::work_date.eq(date))
+            .get_results::<ExceptionResolution>(conn)?
+            .into_iter()
+            .map(|r| ((r.username.clone(), r.kind.clone()), r))
+            .collect();
+    for e in ret.iter_mut() {
+        e.resolution = resolutions.remove(&(e.username.clone(), e.kind.as_str().to_string()));
+    }
+    ret.sort_by(|a, b| (&a.username, a.kind as u8).cmp(&(&b.username, b.kind as u8)));
+    Ok(ret)
+}
+
+/// Records a manager's note on an exception, replacing an earlier one.
+pub fn resolve(
+    conn: &PgConnection,
+    username: &str,
+    date: NaiveDate,
+    kind: Kind,
+    note: &str,
+    resolved_by: &str,
+) -> QueryResult<ExceptionResolution> {
+    use schema::exception_resolutions;
+    diesel::insert_into(exception_resolutions::table)
+        .values((
+            exception_resolutions::username.eq(username),
+            exception_resolutions::work_date.eq(date),
+            exception_resolutions::kind.eq(kind.as_str()),
+            exception_resolutions::note.eq(note),
+            exception_resolutions::resolved_by.eq(resolved_by),
+        ))
+        .on_conflict((
+            exception_resolutions::username,
+            exception_resolutions::work_date,
+            exception_resolutions::kind,
+        ))
+        .do_update()
+        .set((
+            exception_resolutions::note.eq(note),
+            exception_resolutions::resolved_by.eq(resolved_by),
+            exception_resolutions::resolved_at.eq(diesel::dsl::now),
+        ))
+        .get_result::<ExceptionResolution>(conn)
+}
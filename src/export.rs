@@ -0,0 +1,181 @@
+use super::attendance::{self, Day, Split};
+use super::models::{Schedule, User};
+use super::to_local;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    // with a BOM, which Excel needs to detect UTF-8
+    Utf8,
+    // for Excel versions that cannot read UTF-8 at all
+    ShiftJis,
+}
+
+impl Encoding {
+    pub fn parse(s: &str) -> Option<Encoding> {
+        match s.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "shift_jis" | "sjis" | "cp932" => Some(Encoding::ShiftJis),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "text/csv; charset=utf-8",
+            Encoding::ShiftJis => "text/csv; charset=shift_jis",
+        }
+    }
+
+    pub fn preamble(&self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => b"\xef\xbb\xbf",
+            Encoding::ShiftJis => b"",
+        }
+    }
+
+    /// Whether `s` can be written in this encoding without replacements.
+    pub fn represents(&self, s: &str) -> bool {
+        match self {
+            Encoding::Utf8 => true,
+            Encoding::ShiftJis => !encoding_rs::SHIFT_JIS.encode(s).2,
+        }
+    }
+
+    // encoding_rs would write an unmappable character as an HTML numeric
+    // entity, so those are replaced with REPLACEMENT first
+    fn encode(&self, s: &str) -> Vec<u8> {
+        match self {
+            Encoding::Utf8 => s.as_bytes().to_vec(),
+            Encoding::ShiftJis => {
+                let (b, _, errors) = encoding_rs::SHIFT_JIS.encode(s);
+                if !errors {
+                    return b.into_owned();
+                }
+                let s: String = s
+                    .chars()
+                    .map(|c| {
+                        if self.represents(c.encode_utf8(&mut [0; 4])) {
+                            c
+                        } else {
+                            REPLACEMENT
+                        }
+                    })
+                    .collect();
+                encoding_rs::SHIFT_JIS.encode(&s).0.into_owned()
+            }
+        }
+    }
+}
+
+/// Written in place of a character the export encoding cannot represent; the
+/// geta mark is what Japanese software conventionally shows for one.
+pub const REPLACEMENT: char = '〓';
+
+pub const SCHEDULE_HEADER: [&str; 12] = [
+    "ID",
+    "従業員ID",
+    "氏名",
+    "日付",
+    "開始",
+    "終了",
+    "休憩(分)",
+    "通常(時間)",
+    "残業(時間)",
+    "深夜(時間)",
+    "状態",
+    "欠勤理由",
+];
+
+pub const WORKTIME_HEADER: [&str; 13] = [
+    "従業員ID",
+    "氏名",
+    "日付",
+    "出勤",
+    "退勤",
+    "休憩(分)",
+    "通常(時間)",
+    "残業(時間)",
+    "深夜(時間)",
+    "労働(時間)",
+    "丸め出勤",
+    "丸め退勤",
+    "丸め労働(時間)",
+];
+
+pub fn name(u: &User) -> String {
+    [&u.last_name, &u.first_name]
+        .iter()
+        .filter_map(|x| x.as_deref())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn hours(minutes: i64) -> String {
+    format!("{:.2}", minutes as f64 / 60.0)
+}
+
+fn time(t: &chrono::NaiveDateTime) -> String {
+    to_local(t).format("%H:%M").to_string()
+}
+
+fn split(s: &Split) -> Vec<String> {
+    vec![
+        s.break_minutes.to_string(),
+        hours(s.regular),
+        hours(s.overtime),
+        hours(s.late_night),
+    ]
+}
+
+/// Planned hours of a shift; the break is the statutory minimum.
+pub fn schedule_record(s: &Schedule, name: &str) -> Vec<String> {
+    let mut r = vec![
+        s.id.to_string(),
+        s.username.clone(),
+        name.to_string(),
+        to_local(&s.start_time).date().to_string(),
+        time(&s.start_time),
+        time(&s.end_time),
+    ];
+    r.extend(split(&attendance::planned(&s.start_time, &s.end_time)));
+    r.push(s.status.as_str().to_string());
+    r.push(
+        s.absence_reason
+            .map(|x| x.label().to_string())
+            .unwrap_or_default(),
+    );
+    r
+}
+
+pub fn worktime_record(d: &Day, name: &str) -> Vec<String> {
+    let mut r = vec![
+        d.username.clone(),
+        name.to_string(),
+        d.date.to_string(),
+        time(&d.clock_in),
+        d.clock_out.as_ref().map(time).unwrap_or_default(),
+    ];
+    r.extend(split(&d.split));
+    r.push(hours(d.split.worked));
+    r.push(time(&d.rounded_clock_in));
+    r.push(d.rounded_clock_out.as_ref().map(time).unwrap_or_default());
+    r.push(hours(d.rounded.worked));
+    r
+}
+
+/// Writes records as CSV lines in the requested encoding.
+pub fn chunk<I, R>(enc: Encoding, records: I) -> csv::Result<Vec<u8>>
+where
+    I: IntoIterator<Item = R>,
+    R: IntoIterator,
+    R::Item: AsRef<[u8]>,
+{
+    let mut w = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::CRLF)
+        .from_writer(vec![]);
+    for r in records {
+        w.write_record(r)?;
+    }
+    let buf = w.into_inner().map_err(|e| e.into_error())?;
+    Ok(enc.encode(&String::from_utf8_lossy(&buf)))
+}
@@ -0,0 +1,103 @@
+use chrono::NaiveDateTime;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use super::models::Schedule;
+use super::schedule::Status;
+use super::schema;
+
+const PRODID: &str = "-//kintai//shifts//JA";
+
+fn timestamp(t: &NaiveDateTime) -> String {
+    t.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+// RFC 5545 3.1: lines are at most 75 octets, continued with CRLF and a space
+fn fold(line: &str, out: &mut String) {
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            len = 1;
+        }
+        out.push(c);
+        len += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+/// Renders the worker's shifts that still stand as a VCALENDAR.
+/// UIDs only depend on the schedule id, so calendar apps update events in place.
+pub fn render(ss: &[Schedule], now: &NaiveDateTime) -> String {
+    let mut out = String::new();
+    let mut line = |l: String| fold(&l, &mut out);
+    line("BEGIN:VCALENDAR".to_string());
+    line("VERSION:2.0".to_string());
+    line(format!("PRODID:{}", PRODID));
+    line("CALSCALE:GREGORIAN".to_string());
+    line("METHOD:PUBLISH".to_string());
+    for s in ss
+        .iter()
+        .filter(|s| s.status == Status::Approved || s.status == Status::AbsenceRequested)
+    {
+        line("BEGIN:VEVENT".to_string());
+        line(format!("UID:schedule-{}@kintai", s.id));
+        line(format!("DTSTAMP:{}", timestamp(now)));
+        line(format!("DTSTART:{}", timestamp(&s.start_time)));
+        line(format!("DTEND:{}", timestamp(&s.end_time)));
+        line("SUMMARY:シフト".to_string());
+        line(format!(
+            "STATUS:{}",
+            if s.status == Status::Approved {
+                "CONFIRMED"
+            } else {
+                "TENTATIVE"
+            }
+        ));
+        line("END:VEVENT".to_string());
+    }
+    line("END:VCALENDAR".to_string());
+    out
+}
+
+/// The user a feed token belongs to, unless they have been deactivated.
+pub fn feed_owner(conn: &PgConnection, token: &str) -> QueryResult<Option<String>> {
+    use schema::{calendar_feeds, users};
+    calendar_feeds::table
+        .inner_join(users::table)
+        .select(calendar_feeds::username)
+        .filter(calendar_feeds::token.eq(token))
+        .filter(users::deactivated_at.is_null())
+        .get_result::<String>(conn)
+        .optional()
+}
+
+/// Issues a new feed token for the user, invalidating the previous one.
+pub fn regenerate(conn: &PgConnection, username: &str) -> QueryResult<String> {
+    use schema::calendar_feeds;
+    let token = format!(
+        "{}{}",
+        Uuid::new_v4().to_simple(),
+        Uuid::new_v4().to_simple()
+    );
+    diesel::insert_into(calendar_feeds::table)
+        .values((
+            calendar_feeds::username.eq(username),
+            calendar_feeds::token.eq(&token),
+        ))
+        .on_conflict(calendar_feeds::username)
+        .do_update()
+        .set((
+            calendar_feeds::token.eq(&token),
+            calendar_feeds::created_at.eq(diesel::dsl::now),
+        ))
+        .execute(conn)?;
+    Ok(token)
+}
+
+pub fn revoke(conn: &PgConnection, username: &str) -> QueryResult<usize> {
+    use schema::calendar_feeds;
+    diesel::delete(calendar_feeds::table.filter(calendar_feeds::username.eq(username)))
+        .execute(conn)
+}
@@ -0,0 +1,327 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::Serialize;
+use std::collections::HashSet;
+
+use super::calendar;
+use super::models::Schedule;
+use super::schedule::{self, Status};
+use super::{from_local, schema, to_local};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    // one shift per line: username,start,end
+    Flat,
+    // rows = staff, columns = days, cells like "9-17"
+    Grid,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Option<Format> {
+        match s {
+            "flat" => Some(Format::Flat),
+            "grid" => Some(Format::Grid),
+            _ => None,
+        }
+    }
+}
+
+/// One shift read from the file, with everything wrong with it.
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    // 1-based line in the file, counting the header
+    pub line: usize,
+    // 1-based column of the cell, for the grid format
+    pub column: Option<usize>,
+    pub username: String,
+    pub start_time: Option<NaiveDateTime>,
+    pub end_time: Option<NaiveDateTime>,
+    pub errors: Vec<String>,
+}
+
+fn parse_date(s: &str) -> Option<NaiveDate> {
+    // spreadsheets like to append the weekday, as in "2021/08/02(月)"
+    let s = s.split(['(', '（']).next().unwrap_or(s).trim();
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(s, "%Y/%m/%d"))
+        .ok()
+}
+
+fn parse_datetime(s: &str) -> Option<NaiveDateTime> {
+    [
+        "%Y-%m-%d %H:%M",
+        "%Y/%m/%d %H:%M",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M:%S",
+    ]
+    .iter()
+    .find_map(|f| NaiveDateTime::parse_from_str(s.trim(), f).ok())
+}
+
+// "9", "9:30" or "25:00"; hours past 24 are the next day, as rosters often write them
+fn parse_clock(s: &str) -> Option<i64> {
+    let mut it = s.trim().splitn(2, ':');
+    let h = it.next()?.parse::<i64>().ok()?;
+    let m = it.next().map_or(Some(0), |m| m.parse::<i64>().ok())?;
+    if (0..48).contains(&h) && (0..60).contains(&m) {
+        Some(h * 60 + m)
+    } else {
+        None
+    }
+}
+
+/// Reads a grid cell into minutes from the start of the day. An end at or
+/// before the start means the shift runs past midnight.
+pub fn parse_cell(s: &str) -> Option<(i64, i64)> {
+    let mut it = s.splitn(2, ['-', '~', '〜', '～']);
+    let start = parse_clock(it.next()?)?;
+    let mut end = parse_clock(it.next()?)?;
+    if start >= 24 * 60 {
+        return None;
+    }
+    if end <= start {
+        end += 24 * 60;
+    }
+    Some((start, end))
+}
+
+// grid cells that mark a day off rather than a shift
+const DAYS_OFF: [&str; 7] = ["休", "公休", "有休", "希望休", "×", "-", "off"];
+
+fn entry(line: usize, column: Option<usize>, username: &str) -> Entry {
+    Entry {
+        line,
+        column,
+        username: username.trim().to_string(),
+        start_time: None,
+        end_time: None,
+        errors: Vec::new(),
+    }
+}
+
+// a record the CSV reader gave up on, reported like any other bad line
+fn unreadable(i: usize, e: csv::Error) -> Entry {
+    let line = e.position().map_or(i + 2, |p| p.line() as usize);
+    let mut en = entry(line, None, "");
+    en.errors.push(format!("cannot read line {}: {}", line, e));
+    en
+}
+
+fn flat(rdr: &mut csv::Reader<&[u8]>) -> Result<Vec<Entry>, String> {
+    let names = rdr.headers().map_err(|e| e.to_string())?.clone();
+    let column = |cs: &[&str]| {
+        names
+            .iter()
+            .position(|x| cs.contains(&x.trim()))
+            .ok_or_else(|| format!("{} column is required", cs[0]))
+    };
+    let (u, s, e) = (
+        column(&["username", "user"])?,
+        column(&["start", "start_time"])?,
+        column(&["end", "end_time"])?,
+    );
+    let mut ret = Vec::new();
+    for (i, r) in rdr.records().enumerate() {
+        let r = match r {
+            Ok(r) => r,
+            Err(e) => {
+                ret.push(unreadable(i, e));
+                continue;
+            }
+        };
+        let mut en = entry(i + 2, None, r.get(u).unwrap_or_default());
+        let time = |c: usize| {
+            let x = r.get(c).unwrap_or_default();
+            parse_datetime(x)
+                .map(|x| from_local(&x))
+                .ok_or_else(|| format!("cannot parse time: {}", x))
+        };
+        match (time(s), time(e)) {
+            (Ok(s), Ok(e)) => {
+                en.start_time = Some(s);
+                en.end_time = Some(e);
+            }
+            (s, e) => en.errors.extend(s.err().into_iter().chain(e.err())),
+        }
+        ret.push(en);
+    }
+    Ok(ret)
+}
+
+fn grid(rdr: &mut csv::Reader<&[u8]>) -> Result<Vec<Entry>, String> {
+    let names = rdr.headers().map_err(|e| e.to_string())?.clone();
+    let dates = names
+        .iter()
+        .skip(1)
+        .map(|x| parse_date(x).ok_or_else(|| format!("cannot parse date column: {}", x)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut ret = Vec::new();
+    for (i, r) in rdr.records().enumerate() {
+        let r = match r {
+            Ok(r) => r,
+            Err(e) => {
+                ret.push(unreadable(i, e));
+                continue;
+            }
+        };
+        let username = r.get(0).unwrap_or_default();
+        for (j, cell) in r.iter().enumerate().skip(1) {
+            let cell = cell.trim();
+            // blank, "休" and the like are days off
+            if cell.is_empty() || DAYS_OFF.iter().any(|x| x.eq_ignore_ascii_case(cell)) {
+                continue;
+            }
+            let mut en = entry(i + 2, Some(j + 1), username);
+            let date = match dates.get(j - 1) {
+                Some(d) => d,
+                None => {
+                    en.errors
+                        .push(format!("no date for column {}: {}", j + 1, cell));
+                    ret.push(en);
+                    continue;
+                }
+            };
+            match parse_cell(cell) {
+                Some((s, e)) => {
+                    let midnight = date.and_hms(0, 0, 0);
+                    en.start_time = Some(from_local(&(midnight + Duration::minutes(s))));
+                    en.end_time = Some(from_local(&(midnight + Duration::minutes(e))));
+                }
+                None => en.errors.push(format!("cannot parse shift: {}", cell)),
+            }
+            ret.push(en);
+        }
+    }
+    Ok(ret)
+}
+
+/// Reads the file and checks every shift against the users, the schedules
+/// already on the roster and the rest of the file. Fails only when the file
+/// cannot be read at all. Without `format`, a header of dates means a grid.
+pub fn validate(
+    conn: &PgConnection,
+    text: &str,
+    format: Option<Format>,
+) -> Result<Vec<Entry>, String> {
+    use schema::{schedules, users};
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let format = match format {
+        Some(f) => f,
+        None => {
+            let names = rdr.headers().map_err(|e| e.to_string())?;
+            if names.get(1).and_then(parse_date).is_some() {
+                Format::Grid
+            } else {
+                Format::Flat
+            }
+        }
+    };
+    let mut entries = match format {
+        Format::Flat => flat(&mut rdr)?,
+        Format::Grid => grid(&mut rdr)?,
+    };
+
+    let names: HashSet<&str> = entries.iter().map(|e| e.username.as_str()).collect();
+    let (known, deactivated): (Vec<_>, Vec<_>) = users::table
+        .select((users::id, users::deactivated_at.is_not_null()))
+        .filter(users::id.eq_any(names))
+        .get_results::<(String, bool)>(conn)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .partition(|(_, d)| !d);
+    let known: HashSet<String> = known.into_iter().map(|(u, _)| u).collect();
+    let deactivated: HashSet<String> = deactivated.into_iter().map(|(u, _)| u).collect();
+    let times = entries.iter().filter_map(|e| e.start_time.zip(e.end_time));
+    let (from, to) = (times.clone().map(|x| x.0).min(), times.map(|x| x.1).max());
+    let existing = match (from, to) {
+        (Some(from), Some(to)) => schedules::table
+            .filter(schedules::start_time.lt(to))
+            .filter(schedules::end_time.gt(from))
+            .filter(schedules::username.eq_any(&known))
+            .get_results::<Schedule>(conn)
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .filter(|s| s.status.is_active())
+            .collect(),
+        _ => vec![],
+    };
+    let closed = match (from, to) {
+        (Some(from), Some(to)) => {
+            calendar::closed(conn, to_local(&from).date(), to_local(&to).date().succ())
+                .map_err(|e| e.to_string())?
+        }
+        _ => HashSet::new(),
+    };
+
+    for i in 0..entries.len() {
+        let mut errors = Vec::new();
+        let e = &entries[i];
+        if e.username.is_empty() {
+            errors.push("username is required".to_string());
+        } else if deactivated.contains(&e.username) {
+            errors.push(format!("user {} is deactivated", e.username));
+        } else if !known.contains(&e.username) {
+            errors.push(format!("user {} does not exist", e.username));
+        }
+        if let (Some(s), Some(t)) = (e.start_time, e.end_time) {
+            if t <= s {
+                errors.push("end must be after start".to_string());
+            } else {
+                if closed.contains(&to_local(&s).date()) {
+                    errors.push(format!("the store is closed on {}", to_local(&s).date()));
+                }
+                if let Some(x) = existing
+                    .iter()
+                    .find(|x| x.username == e.username && x.start_time < t && x.end_time > s)
+                {
+                    errors.push(format!("overlaps schedule {}", x.id));
+                }
+                if let Some(x) = entries[..i].iter().find(|x| {
+                    x.username == e.username
+                        && x.start_time.is_some_and(|xs| xs < t)
+                        && x.end_time.is_some_and(|xe| xe > s)
+                }) {
+                    errors.push(format!("overlaps line {} in this file", x.line));
+                }
+            }
+        }
+        entries[i].errors.extend(errors);
+    }
+    Ok(entries)
+}
+
+/// Creates the shifts as assigned schedules. Run it inside a transaction,
+/// after `validate` found nothing wrong.
+pub fn import(
+    conn: &PgConnection,
+    entries: &[Entry],
+    created_by: &str,
+) -> QueryResult<Vec<Schedule>> {
+    use schema::schedules;
+
+    if entries.is_empty() {
+        return Ok(vec![]);
+    }
+    let ret = diesel::insert_into(schedules::table)
+        .values(
+            entries
+                .iter()
+                .filter_map(|e| {
+                    Some((
+                        schedules::username.eq(&e.username),
+                        schedules::created_by.eq(created_by),
+                        schedules::start_time.eq(e.start_time?),
+                        schedules::end_time.eq(e.end_time?),
+                        schedules::status.eq(Status::Assigned),
+                    ))
+                })
+                .collect::<Vec<_>>(),
+        )
+        .get_results::<Schedule>(conn)?;
+    schedule::record_created(conn, &ret, created_by)?;
+    Ok(ret)
+}
@@ -0,0 +1,144 @@
+use bcrypt::{hash, verify, DEFAULT_COST};
+use chrono::{Duration, NaiveDateTime};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use super::models::Kiosk;
+use super::{schema, CreateUserError};
+
+// wrong PINs in a row before the employee is locked out of kiosks
+pub const MAX_PIN_FAILURES: i32 = 5;
+// wrong PINs in a row before the device stops taking any, so employee numbers
+// cannot be tried one after another
+pub const MAX_KIOSK_FAILURES: i32 = 20;
+pub const LOCKOUT_MINUTES: i64 = 15;
+
+/// Outcome of a PIN entered on a kiosk. An unknown employee number and a wrong
+/// PIN look the same from outside.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Check {
+    Accepted(String),
+    Rejected,
+    Locked(NaiveDateTime),
+}
+
+/// Four to eight digits.
+pub fn valid_pin(pin: &str) -> bool {
+    (4..=8).contains(&pin.len()) && pin.bytes().all(|b| b.is_ascii_digit())
+}
+
+pub fn register(conn: &PgConnection, name: &str, created_by: &str) -> QueryResult<Kiosk> {
+    use schema::kiosks;
+    let token = format!(
+        "{}{}",
+        Uuid::new_v4().to_simple(),
+        Uuid::new_v4().to_simple()
+    );
+    diesel::insert_into(kiosks::table)
+        .values((
+            kiosks::name.eq(name),
+            kiosks::token.eq(&token),
+            kiosks::created_by.eq(created_by),
+        ))
+        .get_result::<Kiosk>(conn)
+}
+
+/// The kiosk a device token belongs to, unless it was revoked.
+pub fn by_token(conn: &PgConnection, token: &str) -> QueryResult<Option<Kiosk>> {
+    use schema::kiosks;
+    kiosks::table
+        .filter(kiosks::token.eq(token))
+        .filter(kiosks::revoked_at.is_null())
+        .get_result::<Kiosk>(conn)
+        .optional()
+}
+
+pub fn revoke(conn: &PgConnection, id: i64) -> QueryResult<Kiosk> {
+    use schema::kiosks;
+    diesel::update(kiosks::table.filter(kiosks::id.eq(id)))
+        .set(kiosks::revoked_at.eq(diesel::dsl::now))
+        .get_result::<Kiosk>(conn)
+}
+
+/// Sets the user's PIN, lifting any lockout.
+pub fn set_pin(conn: &PgConnection, username: &str, pin: &str) -> Result<usize, CreateUserError> {
+    use schema::user_pins;
+    let hashed = hash(pin, DEFAULT_COST).map_err(CreateUserError::HashError)?;
+    diesel::insert_into(user_pins::table)
+        .values((user_pins::username.eq(username), user_pins::pin.eq(&hashed)))
+        .on_conflict(user_pins::username)
+        .do_update()
+        .set((
+            user_pins::pin.eq(&hashed),
+            user_pins::failures.eq(0),
+            user_pins::locked_until.eq(None::<NaiveDateTime>),
+            user_pins::updated_at.eq(diesel::dsl::now),
+        ))
+        .execute(conn)
+        .map_err(CreateUserError::QueryError)
+}
+
+/// Checks an employee number and PIN entered on `kiosk` as of `now`. Failures
+/// count against the employee and the device alike and must be committed
+/// whatever happens next, so this is not meant to run inside a transaction.
+pub fn check_pin(
+    conn: &PgConnection,
+    kiosk: &Kiosk,
+    employee_number: &str,
+    pin: &str,
+    now: NaiveDateTime,
+) -> QueryResult<Check> {
+    use schema::{kiosks, user_pins, users};
+    if let Some(t) = kiosk.locked_until.filter(|t| *t > now) {
+        return Ok(Check::Locked(t));
+    }
+    let found = users::table
+        .inner_join(user_pins::table)
+        .select((users::id, user_pins::pin, user_pins::locked_until))
+        .filter(users::employee_number.eq(employee_number))
+        .filter(users::deactivated_at.is_null())
+        .get_result::<(String, String, Option<NaiveDateTime>)>(conn)
+        .optional()?;
+    if let Some(t) = found.as_ref().and_then(|(_, _, t)| *t).filter(|t| *t > now) {
+        return Ok(Check::Locked(t));
+    }
+    let lock = now + Duration::minutes(LOCKOUT_MINUTES);
+    match found {
+        Some((username, hashed, _)) if verify(pin, &hashed).unwrap_or(false) => {
+            diesel::update(user_pins::table.filter(user_pins::username.eq(&username)))
+                .set((
+                    user_pins::failures.eq(0),
+                    user_pins::locked_until.eq(None::<NaiveDateTime>),
+                ))
+                .execute(conn)?;
+            diesel::update(kiosks::table.filter(kiosks::id.eq(kiosk.id)))
+                .set(kiosks::failures.eq(0))
+                .execute(conn)?;
+            Ok(Check::Accepted(username))
+        }
+        found => {
+            if let Some((username, _, _)) = found {
+                let n = diesel::update(user_pins::table.filter(user_pins::username.eq(&username)))
+                    .set(user_pins::failures.eq(user_pins::failures + 1))
+                    .returning(user_pins::failures)
+                    .get_result::<i32>(conn)?;
+                if n >= MAX_PIN_FAILURES {
+                    diesel::update(user_pins::table.filter(user_pins::username.eq(&username)))
+                        .set((user_pins::failures.eq(0), user_pins::locked_until.eq(lock)))
+                        .execute(conn)?;
+                }
+            }
+            let n = diesel::update(kiosks::table.filter(kiosks::id.eq(kiosk.id)))
+                .set(kiosks::failures.eq(kiosks::failures + 1))
+                .returning(kiosks::failures)
+                .get_result::<i32>(conn)?;
+            if n >= MAX_KIOSK_FAILURES {
+                diesel::update(kiosks::table.filter(kiosks::id.eq(kiosk.id)))
+                    .set((kiosks::failures.eq(0), kiosks::locked_until.eq(lock)))
+                    .execute(conn)?;
+            }
+            Ok(Check::Rejected)
+        }
+    }
+}
@@ -0,0 +1,412 @@
+use chrono::{Datelike, Duration, NaiveDate};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::attendance::planned;
+use super::models::{LeaveGrant, LeaveRequest, Schedule, User};
+use super::schedule::{Actor, TransitionError};
+use super::{from_local, schema, to_local};
+
+// 労働基準法 第115条: the right to a grant lapses after two years
+pub const EXPIRY_YEARS: u32 = 2;
+// 労働基準法 第39条第7項: 10 days or more granted means 5 must be taken within a year
+pub const MANDATORY_DAYS: i64 = 5;
+pub const MANDATORY_MIN_GRANT: i32 = 10;
+
+// 労働基準法 第39条 and 施行規則 第24条の3: days granted by completed service
+// (0.5, 1.5, 2.5, ... 6.5 years or more) for 1, 2, 3, 4 and 5+ scheduled days a week
+const TABLE: [[i32; 7]; 5] = [
+    [1, 2, 2, 2, 3, 3, 3],
+    [3, 4, 4, 5, 6, 6, 7],
+    [5, 6, 6, 8, 9, 10, 11],
+    [7, 8, 9, 10, 12, 13, 15],
+    [10, 11, 12, 14, 16, 18, 20],
+];
+
+// 30 hours a week or more counts as full-time whatever the number of days
+const FULL_TIME_WEEKLY_MINUTES: i64 = 30 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Requested,
+    Approved,
+    Rejected,
+    Cancelled,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Requested => "requested",
+            Status::Approved => "approved",
+            Status::Rejected => "rejected",
+            Status::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Status> {
+        match s {
+            "requested" => Some(Status::Requested),
+            "approved" => Some(Status::Approved),
+            "rejected" => Some(Status::Rejected),
+            "cancelled" => Some(Status::Cancelled),
+            _ => None,
+        }
+    }
+
+    // whether the request holds a day of the balance
+    pub fn is_active(&self) -> bool {
+        matches!(self, Status::Requested | Status::Approved)
+    }
+}
+
+/// Who may move a request where. Managers decide on requests; workers may
+/// withdraw their own, approved ones only before the day.
+pub fn transition(
+    r: &LeaveRequest,
+    actor: &Actor,
+    to: Status,
+    today: NaiveDate,
+) -> Result<Status, TransitionError> {
+    let from = Status::parse(&r.status).ok_or(TransitionError::InvalidState)?;
+    let owner = r.username == actor.username;
+    let permitted = match (from, to) {
+        (Status::Requested, Status::Approved) | (Status::Requested, Status::Rejected) => {
+            actor.isadmin
+        }
+        (Status::Requested, Status::Cancelled) => actor.isadmin || owner,
+        (Status::Approved, Status::Cancelled) => actor.isadmin || (owner && today < r.leave_date),
+        _ => return Err(TransitionError::InvalidState),
+    };
+    if permitted {
+        Ok(to)
+    } else {
+        Err(TransitionError::NotPermitted)
+    }
+}
+
+/// Days granted after `step` grants (0 for the one at six months) to a worker
+/// scheduled `weekly_days` a week.
+pub fn statutory_days(step: usize, weekly_days: i16) -> i32 {
+    if weekly_days < 1 {
+        return 0;
+    }
+    TABLE[(weekly_days.min(5) - 1) as usize][step.min(6)]
+}
+
+/// `d` moved by `months`, clamped to the end of a shorter month.
+pub fn add_months(d: NaiveDate, months: u32) -> NaiveDate {
+    let m = d.month0() + months;
+    let (y, m) = (d.year() + (m / 12) as i32, m % 12 + 1);
+    (0..4)
+        .filter_map(|i| NaiveDate::from_ymd_opt(y, m, d.day() - i))
+        .next()
+        .unwrap()
+}
+
+/// Grant dates from hiring up to `today`: six months in, then every year.
+pub fn grant_dates(hire_date: NaiveDate, today: NaiveDate) -> Vec<NaiveDate> {
+    (0..)
+        .map(|i| add_months(hire_date, 6 + 12 * i))
+        .take_while(|d| *d <= today)
+        .collect()
+}
+
+/// Scheduled days per week over local dates `start..end`, 5 when the worker is
+/// full-time by days or hours. Only shifts still on the roster count.
+pub fn weekly_days(
+    conn: &PgConnection,
+    username: &str,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> QueryResult<i16> {
+    use schema::schedules;
+    let ss = schedules::table
+        .filter(schedules::username.eq(username))
+        .filter(schedules::start_time.ge(from_local(&start.and_hms(0, 0, 0))))
+        .filter(schedules::start_time.lt(from_local(&end.and_hms(0, 0, 0))))
+        .get_results::<Schedule>(conn)?;
+    let mut dates = std::collections::HashSet::new();
+    let mut minutes = 0;
+    for s in ss.iter().filter(|s| s.status.is_active()) {
+        dates.insert(to_local(&s.start_time).date());
+        minutes += planned(&s.start_time, &s.end_time).worked;
+    }
+    let days = (end - start).num_days();
+    if days <= 0 {
+        return Ok(0);
+    }
+    if minutes as f64 * 7.0 / days as f64 >= FULL_TIME_WEEKLY_MINUTES as f64 {
+        return Ok(5);
+    }
+    // the statute bands part-timers by days a year where the weekly count varies
+    let yearly = dates.len() as i64 * 365 / days;
+    Ok(YEARLY_DAYS.iter().filter(|d| yearly >= **d).count() as i16)
+}
+
+// fewest days a year for 1, 2, 3, 4 and 5 days a week
+const YEARLY_DAYS: [i64; 5] = [48, 73, 121, 169, 217];
+
+/// Records the grants due by `today` to everyone with a hire date, or only to
+/// `username`. Grants already recorded are kept as they are, so this can run
+/// any number of times. The 80% attendance condition is assumed to be met;
+/// the scheduled days are taken from the roster of the preceding six months
+/// for the first grant and the preceding year after that.
+pub fn accrue(
+    conn: &PgConnection,
+    username: Option<&str>,
+    today: NaiveDate,
+) -> QueryResult<Vec<LeaveGrant>> {
+    use schema::{leave_grants, users};
+    let mut q = users::table
+        .filter(users::hire_date.is_not_null())
+        .filter(users::deactivated_at.is_null())
+        .into_boxed();
+    if let Some(u) = username {
+        q = q.filter(users::id.eq(u));
+    }
+    let mut ret = Vec::new();
+    for u in q.get_results::<User>(conn)? {
+        let hire_date = u.hire_date.unwrap();
+        for (step, d) in grant_dates(hire_date, today).into_iter().enumerate() {
+            let from = if step == 0 {
+                hire_date
+            } else {
+                add_months(hire_date, 6 + 12 * (step as u32 - 1))
+            };
+            let weekly = weekly_days(conn, &u.id, from, d)?;
+            let days = statutory_days(step, weekly);
+            if days == 0 {
+                continue;
+            }
+            ret.extend(
+                diesel::insert_into(leave_grants::table)
+                    .values((
+                        leave_grants::username.eq(&u.id),
+                        leave_grants::granted_on.eq(d),
+                        leave_grants::expires_on.eq(add_months(d, 12 * EXPIRY_YEARS)),
+                        leave_grants::days.eq(days),
+                        leave_grants::weekly_days.eq(weekly),
+                    ))
+                    .on_conflict_do_nothing()
+                    .get_results::<LeaveGrant>(conn)?,
+            );
+        }
+    }
+    Ok(ret)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GrantBalance {
+    pub granted_on: NaiveDate,
+    pub expires_on: NaiveDate,
+    pub days: i32,
+    pub used: i32,
+    // held by requests waiting for a manager
+    pub pending: i32,
+    pub remaining: i32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Balance {
+    pub username: String,
+    // days left on grants valid today, net of pending requests
+    pub remaining: i32,
+    pub grants: Vec<GrantBalance>,
+    // active requests on dates no grant covers
+    pub uncovered: Vec<NaiveDate>,
+}
+
+/// Charges active requests to grants, oldest grant first, in date order.
+/// A request can only use a grant valid on its date.
+pub fn allocate(
+    username: &str,
+    grants: &[LeaveGrant],
+    requests: &[LeaveRequest],
+    today: NaiveDate,
+) -> Balance {
+    let mut gs: Vec<GrantBalance> = grants
+        .iter()
+        .map(|g| GrantBalance {
+            granted_on: g.granted_on,
+            expires_on: g.expires_on,
+            days: g.days,
+            used: 0,
+            pending: 0,
+            remaining: g.days,
+        })
+        .collect();
+    gs.sort_by_key(|g| g.granted_on);
+    let mut rs: Vec<&LeaveRequest> = requests
+        .iter()
+        .filter(|r| matches!(Status::parse(&r.status), Some(s) if s.is_active()))
+        .collect();
+    rs.sort_by_key(|r| (r.leave_date, r.id));
+    let mut uncovered = Vec::new();
+    for r in rs {
+        let g = gs.iter_mut().find(|g| {
+            g.granted_on <= r.leave_date && r.leave_date < g.expires_on && g.remaining > 0
+        });
+        match g {
+            Some(g) => {
+                if Status::parse(&r.status) == Some(Status::Approved) {
+                    g.used += 1;
+                } else {
+                    g.pending += 1;
+                }
+                g.remaining -= 1;
+            }
+            None => uncovered.push(r.leave_date),
+        }
+    }
+    Balance {
+        username: username.to_string(),
+        remaining: gs
+            .iter()
+            .filter(|g| g.granted_on <= today && today < g.expires_on)
+            .map(|g| g.remaining)
+            .sum(),
+        grants: gs,
+        uncovered,
+    }
+}
+
+// grants and active requests by user
+type Ledger = BTreeMap<String, (Vec<LeaveGrant>, Vec<LeaveRequest>)>;
+
+fn load(conn: &PgConnection, username: Option<&str>) -> QueryResult<Ledger> {
+    use schema::{leave_grants, leave_requests};
+    let mut gq = leave_grants::table.into_boxed();
+    let mut rq = leave_requests::table
+        .filter(
+            leave_requests::status
+                .eq_any(vec![Status::Requested.as_str(), Status::Approved.as_str()]),
+        )
+        .into_boxed();
+    if let Some(u) = username {
+        gq = gq.filter(leave_grants::username.eq(u));
+        rq = rq.filter(leave_requests::username.eq(u));
+    }
+    let mut ret = Ledger::new();
+    for g in gq.get_results::<LeaveGrant>(conn)? {
+        ret.entry(g.username.clone()).or_default().0.push(g);
+    }
+    for r in rq.get_results::<LeaveRequest>(conn)? {
+        ret.entry(r.username.clone()).or_default().1.push(r);
+    }
+    Ok(ret)
+}
+
+pub fn balances(
+    conn: &PgConnection,
+    username: Option<&str>,
+    today: NaiveDate,
+) -> QueryResult<Vec<Balance>> {
+    let mut ret: Vec<Balance> = load(conn, username)?
+        .iter()
+        .map(|(u, (gs, rs))| allocate(u, gs, rs, today))
+        .collect();
+    if let (Some(u), true) = (username, ret.is_empty()) {
+        ret.push(allocate(u, &[], &[], today));
+    }
+    Ok(ret)
+}
+
+/// Progress towards the five days a year an employer must make sure are taken.
+#[derive(Debug, Clone, Serialize)]
+pub struct Obligation {
+    pub username: String,
+    pub granted_on: NaiveDate,
+    // last day of the year the days must be taken in
+    pub deadline: NaiveDate,
+    pub taken: i64,
+    pub shortfall: i64,
+}
+
+/// Obligations for grants of ten days or more whose year includes `today`.
+pub fn obligations(conn: &PgConnection, today: NaiveDate) -> QueryResult<Vec<Obligation>> {
+    use schema::{leave_grants, leave_requests};
+    let gs = leave_grants::table
+        .filter(leave_grants::days.ge(MANDATORY_MIN_GRANT))
+        .filter(leave_grants::granted_on.le(today))
+        .order((leave_grants::username.asc(), leave_grants::granted_on.asc()))
+        .get_results::<LeaveGrant>(conn)?;
+    let mut ret = Vec::new();
+    for g in gs {
+        let end = add_months(g.granted_on, 12);
+        if end <= today {
+            continue;
+        }
+        let taken = leave_requests::table
+            .filter(leave_requests::username.eq(&g.username))
+            .filter(leave_requests::status.eq(Status::Approved.as_str()))
+            .filter(leave_requests::leave_date.ge(g.granted_on))
+            .filter(leave_requests::leave_date.lt(end))
+            .count()
+            .get_result::<i64>(conn)?;
+        ret.push(Obligation {
+            username: g.username,
+            granted_on: g.granted_on,
+            deadline: end - Duration::days(1),
+            taken,
+            shortfall: (MANDATORY_DAYS - taken).max(0),
+        });
+    }
+    Ok(ret)
+}
+
+/// Paid minutes for a day of leave: the shift planned that day, or else the
+/// average shift over the previous 90 days. None when neither exists.
+pub fn default_minutes(
+    conn: &PgConnection,
+    username: &str,
+    date: NaiveDate,
+) -> QueryResult<Option<i32>> {
+    use schema::schedules;
+    let ss = schedules::table
+        .filter(schedules::username.eq(username))
+        .filter(schedules::start_time.ge(from_local(&(date - Duration::days(90)).and_hms(0, 0, 0))))
+        .filter(schedules::start_time.lt(from_local(&(date + Duration::days(1)).and_hms(0, 0, 0))))
+        .get_results::<Schedule>(conn)?;
+    let ss: Vec<&Schedule> = ss.iter().filter(|s| s.status.is_active()).collect();
+    let minutes = |s: &Schedule| planned(&s.start_time, &s.end_time).worked as i32;
+    let on_date: Vec<&&Schedule> = ss
+        .iter()
+        .filter(|s| to_local(&s.start_time).date() == date)
+        .collect();
+    if !on_date.is_empty() {
+        return Ok(Some(on_date.iter().map(|s| minutes(s)).sum()));
+    }
+    if ss.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(
+        ss.iter().map(|s| minutes(s)).sum::<i32>() / ss.len() as i32,
+    ))
+}
+
+/// Approved leave on local dates `start..end`.
+pub fn approved(
+    conn: &PgConnection,
+    start: NaiveDate,
+    end: NaiveDate,
+    username: Option<&str>,
+) -> QueryResult<Vec<LeaveRequest>> {
+    use schema::leave_requests;
+    let mut q = leave_requests::table
+        .filter(leave_requests::status.eq(Status::Approved.as_str()))
+        .filter(leave_requests::leave_date.ge(start))
+        .filter(leave_requests::leave_date.lt(end))
+        .into_boxed();
+    if let Some(u) = username {
+        q = q.filter(leave_requests::username.eq(u));
+    }
+    q.order((
+        leave_requests::username.asc(),
+        leave_requests::leave_date.asc(),
+    ))
+    .get_results::<LeaveRequest>(conn)
+}
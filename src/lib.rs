@@ -1,5 +1,26 @@
+pub mod absence;
+pub mod attendance;
+pub mod availability;
+pub mod calendar;
+pub mod corrections;
+pub mod employees;
+pub mod exceptions;
+pub mod export;
+pub mod ical;
+pub mod import;
+pub mod kiosk;
+pub mod leave;
 pub mod models;
+pub mod network;
+pub mod payroll;
+pub mod period;
+pub mod qr;
+pub mod roster;
+pub mod rounding;
+pub mod schedule;
 pub mod schema;
+pub mod seed;
+pub mod settings;
 #[macro_use]
 extern crate diesel;
 extern crate bcrypt;
@@ -8,7 +29,7 @@ extern crate passwords;
 extern crate qstring;
 
 use bcrypt::{hash, verify, BcryptError, DEFAULT_COST};
-use chrono::Utc;
+use chrono::{FixedOffset, Utc};
 use derive_more::{Display, Error};
 use diesel::prelude::*;
 use diesel::{
@@ -23,11 +44,55 @@ use std::env;
 
 use self::models::{NewUser, User};
 
+fn env_number(key: &str) -> Option<u64> {
+    env::var(key).ok().map(|x| {
+        x.parse::<u64>()
+            .unwrap_or_else(|_| panic!("{} must be a number", key))
+    })
+}
+
+/// Builds the connection pool. Besides `DATABASE_URL` it reads
+/// `DATABASE_POOL_SIZE` (default 10), `DATABASE_POOL_MIN_IDLE`,
+/// `DATABASE_POOL_TIMEOUT` (seconds to wait for a free connection before a
+/// request gets 503, default 5), `DATABASE_POOL_IDLE_TIMEOUT` and
+/// `DATABASE_POOL_MAX_LIFETIME` (seconds, r2d2's defaults when unset).
 pub fn establish_connection() -> Option<r2d2::Pool<ConnectionManager<PgConnection>>> {
+    use std::time::Duration;
+
     let _ = dotenv().ok()?;
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let manager = ConnectionManager::<PgConnection>::new(database_url);
-    r2d2::Pool::builder().build(manager).ok()
+    let mut builder = r2d2::Pool::builder()
+        .max_size(env_number("DATABASE_POOL_SIZE").unwrap_or(10) as u32)
+        .min_idle(env_number("DATABASE_POOL_MIN_IDLE").map(|x| x as u32))
+        .connection_timeout(Duration::from_secs(
+            env_number("DATABASE_POOL_TIMEOUT").unwrap_or(5),
+        ));
+    if let Some(x) = env_number("DATABASE_POOL_IDLE_TIMEOUT") {
+        builder = builder.idle_timeout(Some(Duration::from_secs(x)));
+    }
+    if let Some(x) = env_number("DATABASE_POOL_MAX_LIFETIME") {
+        builder = builder.max_lifetime(Some(Duration::from_secs(x)));
+    }
+    builder.build(manager).ok()
+}
+
+// schedules are stored in UTC; business rules (weekdays, periods) use the store's local time
+pub fn utc_offset() -> FixedOffset {
+    let _ = dotenv();
+    let minutes = env::var("UTC_OFFSET_MINUTES")
+        .ok()
+        .and_then(|x| x.parse::<i32>().ok())
+        .unwrap_or(9 * 60);
+    FixedOffset::east(minutes * 60)
+}
+
+pub fn to_local(t: &chrono::NaiveDateTime) -> chrono::NaiveDateTime {
+    *t + chrono::Duration::seconds(utc_offset().local_minus_utc().into())
+}
+
+pub fn from_local(t: &chrono::NaiveDateTime) -> chrono::NaiveDateTime {
+    *t - chrono::Duration::seconds(utc_offset().local_minus_utc().into())
 }
 
 #[derive(Debug, Display, Error)]
@@ -106,6 +171,7 @@ pub fn validate_user(conn: &PgConnection, username: &str, password: &str) -> Opt
     use schema::users::dsl::*;
     users
         .filter(id.eq(username))
+        .filter(deactivated_at.is_null())
         .get_result::<User>(conn)
         .ok()
         .and_then(|user| {
@@ -119,7 +185,11 @@ pub fn validate_user(conn: &PgConnection, username: &str, password: &str) -> Opt
 
 pub fn get_user(conn: &PgConnection, username: &str) -> Option<User> {
     use schema::users::dsl::*;
-    users.filter(id.eq(username)).get_result::<User>(conn).ok()
+    users
+        .filter(id.eq(username))
+        .filter(deactivated_at.is_null())
+        .get_result::<User>(conn)
+        .ok()
 }
 
 pub fn login(conn: &PgConnection, username: &str, password: &str) -> Option<String> {
@@ -149,7 +219,7 @@ pub fn update_password<'a>(
     pass: &'a str,
 ) -> Result<usize, UpdatePasswordError> {
     use schema::users;
-    let _ = validate_user(conn, id, old).ok_or(UpdatePasswordError::AuthenticationError);
+    validate_user(conn, id, old).ok_or(UpdatePasswordError::AuthenticationError)?;
     let hashed = hash(pass, DEFAULT_COST).map_err(|x| UpdatePasswordError::HashError(x))?;
 
     diesel::update(users::table.filter(users::id.eq(id)))
@@ -158,6 +228,17 @@ pub fn update_password<'a>(
         .map_err(|x| UpdatePasswordError::QueryError(x))
 }
 
+/// Replaces the password without asking for the old one, for administrators.
+pub fn reset_password(conn: &PgConnection, id: &str, pass: &str) -> Result<usize, CreateUserError> {
+    use schema::users;
+    let hashed = hash(pass, DEFAULT_COST).map_err(CreateUserError::HashError)?;
+
+    diesel::update(users::table.filter(users::id.eq(id)))
+        .set(users::pass.eq(hashed))
+        .execute(conn)
+        .map_err(CreateUserError::QueryError)
+}
+
 pub fn create_pg() -> PasswordGenerator {
     PasswordGenerator {
         length: 12,
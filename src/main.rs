@@ -1,25 +1,44 @@
+#[cfg(test)]
+#[macro_use]
+extern crate diesel_migrations;
+
 use actix_files::{Files, NamedFile};
-use actix_web::{dev::HttpResponseBuilder, http::header, http::StatusCode};
-use actix_web::{error, web, App, HttpRequest, HttpResponse, HttpServer, Responder, Result};
+use actix_web::{dev::HttpResponseBuilder, dev::Payload, error::BlockingError, http::StatusCode};
+use actix_web::{
+    error, web, App, FromRequest, HttpRequest, HttpResponse, HttpServer, Responder, Result,
+};
 use chrono::prelude::*;
 use chrono::{Duration, NaiveDate};
 use derive_more::{Display, Error};
-use diesel::connection::{AnsiTransactionManager, TransactionManager};
+use diesel::connection::TransactionManager;
 use diesel::query_dsl::methods::FilterDsl;
 use diesel::{
     pg::PgConnection,
     r2d2::{self, ConnectionManager},
     RunQueryDsl,
 };
-use kintai::models::{Schedule, User};
+use futures::future::LocalBoxFuture;
+use futures::StreamExt;
+use kintai::absence::Reason;
+use kintai::availability::{self, Kind};
+use kintai::models::{
+    Availability, Kiosk, LeaveRequest, NewScheduleEvent, Period, PeriodChanges, Punch,
+    PunchCorrection, Schedule, StoreDay, User, WeeklyAvailability,
+};
+use kintai::roster::{self, Assignment, RosterInput};
+use kintai::schedule::{self, Action, Actor, Status, TransitionError};
 use kintai::{
-    create_pg, create_user, decode, establish_connection, get_user, login, schema, CreateUserError,
+    attendance, calendar, corrections, create_pg, create_user, decode, employees,
+    establish_connection, exceptions, export, from_local, get_user, ical, import, kiosk, leave,
+    login, network, payroll, period, qr, schema, settings, to_local, CreateUserError,
     UpdatePasswordError,
 };
 use passwords::PasswordGenerator;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::collections::HashMap;
 use std::env;
+use std::net::IpAddr;
 
 async fn index(_: HttpRequest) -> Result<NamedFile> {
     Ok(NamedFile::open("./build/index.html")?)
@@ -37,6 +56,22 @@ pub struct UserName {
     pub isadmin: bool,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportOptions {
+    #[serde(default)]
+    pub dry_run: bool,
+    // "csv" returns the generated credentials as a file instead of JSON
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScheduleImportOptions {
+    #[serde(default)]
+    pub dry_run: bool,
+    // "flat" or "grid"; guessed from the header when absent
+    pub layout: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Passwords {
     pub new: String,
@@ -56,6 +91,141 @@ pub struct StartEndWithUser {
     pub end_time: chrono::NaiveDateTime,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewPunch {
+    pub kind: attendance::Kind,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KioskPunch {
+    pub employee_number: String,
+    pub pin: String,
+    pub kind: attendance::Kind,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QrPunch {
+    // as scanned from the store display
+    pub payload: String,
+    pub kind: attendance::Kind,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewKiosk {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewPin {
+    pub pin: String,
+    // required when setting one's own PIN
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewPunchCorrection {
+    // the punch to move; without one, a forgotten punch of `kind` is added
+    pub punch_id: Option<i64>,
+    pub kind: Option<attendance::Kind>,
+    pub punched_at: chrono::NaiveDateTime,
+    pub reason: String,
+    // a manager correcting a worker's punch; approved right away
+    pub username: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CorrectionDecision {
+    pub status: corrections::Status,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewLeaveRequest {
+    pub date: NaiveDate,
+    // defaults to the shift planned that day; only a manager may set it
+    pub minutes: Option<i32>,
+    pub note: Option<String>,
+    // a manager designating leave for a worker; approved right away
+    pub username: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LeaveDecision {
+    pub status: leave::Status,
+    // lets a manager correct the paid time when approving
+    pub minutes: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewStoreDay {
+    pub kind: calendar::Kind,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewResolution {
+    pub username: String,
+    pub date: NaiveDate,
+    pub kind: exceptions::Kind,
+    pub note: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SettingValue {
+    pub value: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransitionAction {
+    pub action: Action,
+    // for request_absence, approve_absence and excuse
+    #[serde(default)]
+    pub reason: Option<Reason>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AbsenceRequest {
+    pub reason: Option<Reason>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RosterRequest {
+    pub week_start: NaiveDate,
+    pub seed: u64,
+    pub requirements: Vec<roster::Requirement>,
+    // falls back to the submitted availability when empty
+    #[serde(default)]
+    pub availability: Vec<roster::Availability>,
+    #[serde(default)]
+    pub caps: Vec<roster::WeeklyCap>,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewAvailability {
+    pub username: Option<String>,
+    pub start_time: chrono::NaiveDateTime,
+    pub end_time: chrono::NaiveDateTime,
+    pub kind: Kind,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WeeklyEntry {
+    pub weekday: i16,
+    pub start_time: chrono::NaiveTime,
+    pub end_time: chrono::NaiveTime,
+    pub kind: Kind,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewPeriod {
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+    pub deadline: chrono::NaiveDateTime,
+    #[serde(default)]
+    pub opens_at: Option<chrono::NaiveDateTime>,
+}
+
 #[derive(Debug, Display, Error)]
 enum ServerError {
     #[display(fmt = "internal error")]
@@ -63,1189 +233,6930 @@ enum ServerError {
     QueryError(diesel::result::Error),
     CreateUserError(CreateUserError),
     UpdatePasswordError(UpdatePasswordError),
+    #[display(fmt = "unauthorized error")]
+    Unauthorized,
+    #[display(fmt = "method is allowed for only admin")]
+    AdminOnly,
+    #[display(fmt = "server is busy, try again later")]
+    Unavailable,
+    #[display(fmt = "{}", _1)]
+    BadRequest(&'static str, String),
+    #[display(fmt = "{}", _1)]
+    Forbidden(&'static str, String),
+    #[display(fmt = "{}", _1)]
+    NotFound(&'static str, String),
+    #[display(fmt = "{}", _1)]
+    Conflict(&'static str, String, Value),
+    #[display(fmt = "{}", _1)]
+    Unprocessable(&'static str, String, Value),
+    #[display(fmt = "{}", _1)]
+    TooManyRequests(&'static str, String, Value),
+}
+
+fn query_error_status(e: &diesel::result::Error) -> StatusCode {
+    use diesel::result::{DatabaseErrorKind, Error};
+    match e {
+        Error::NotFound => StatusCode::NOT_FOUND,
+        Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => StatusCode::CONFLICT,
+        Error::DatabaseError(DatabaseErrorKind::SerializationFailure, _) => StatusCode::CONFLICT,
+        Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => {
+            StatusCode::UNPROCESSABLE_ENTITY
+        }
+        // check constraints are the only unclassified errors that name a constraint
+        Error::DatabaseError(_, info) if info.constraint_name().is_some() => {
+            StatusCode::UNPROCESSABLE_ENTITY
+        }
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn query_error_code(e: &diesel::result::Error) -> &'static str {
+    use diesel::result::{DatabaseErrorKind, Error};
+    match e {
+        Error::NotFound => "not_found",
+        Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => "already_exists",
+        Error::DatabaseError(DatabaseErrorKind::SerializationFailure, _) => "serialization_failure",
+        Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => "invalid_reference",
+        Error::DatabaseError(_, info) if info.constraint_name().is_some() => "constraint_violation",
+        _ => "internal_error",
+    }
+}
+
+fn query_error_details(e: &diesel::result::Error) -> Value {
+    match e {
+        diesel::result::Error::DatabaseError(_, info)
+            if query_error_status(e).is_client_error() =>
+        {
+            json!({
+                "table": info.table_name(),
+                "column": info.column_name(),
+                "constraint": info.constraint_name(),
+            })
+        }
+        _ => json!({}),
+    }
+}
+
+impl ServerError {
+    fn code(&self) -> &'static str {
+        match self {
+            ServerError::InternalError => "internal_error",
+            ServerError::QueryError(e) => query_error_code(e),
+            ServerError::CreateUserError(CreateUserError::QueryError(e)) => {
+                match query_error_code(e) {
+                    "already_exists" => "user_already_exists",
+                    c => c,
+                }
+            }
+            ServerError::CreateUserError(_) => "internal_error",
+            ServerError::UpdatePasswordError(UpdatePasswordError::AuthenticationError) => {
+                "wrong_password"
+            }
+            ServerError::UpdatePasswordError(UpdatePasswordError::QueryError(e)) => {
+                query_error_code(e)
+            }
+            ServerError::UpdatePasswordError(_) => "internal_error",
+            ServerError::Unauthorized => "unauthorized",
+            ServerError::AdminOnly => "admin_only",
+            ServerError::Unavailable => "service_unavailable",
+            ServerError::BadRequest(c, _)
+            | ServerError::Forbidden(c, _)
+            | ServerError::NotFound(c, _)
+            | ServerError::Conflict(c, _, _)
+            | ServerError::Unprocessable(c, _, _)
+            | ServerError::TooManyRequests(c, _, _) => c,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            ServerError::QueryError(e)
+            | ServerError::CreateUserError(CreateUserError::QueryError(e))
+            | ServerError::UpdatePasswordError(UpdatePasswordError::QueryError(e)) => {
+                match query_error_status(e) {
+                    StatusCode::NOT_FOUND => "resource not found".to_string(),
+                    StatusCode::CONFLICT => "resource already exists or was changed".to_string(),
+                    StatusCode::UNPROCESSABLE_ENTITY => match e {
+                        diesel::result::Error::DatabaseError(_, info) => info.message().to_string(),
+                        _ => e.to_string(),
+                    },
+                    _ => "internal error".to_string(),
+                }
+            }
+            ServerError::CreateUserError(_) | ServerError::UpdatePasswordError(_) => {
+                match self.code() {
+                    "wrong_password" => "old password is wrong".to_string(),
+                    _ => "internal error".to_string(),
+                }
+            }
+            _ => self.to_string(),
+        }
+    }
+
+    fn details(&self) -> Value {
+        match self {
+            ServerError::QueryError(e)
+            | ServerError::CreateUserError(CreateUserError::QueryError(e))
+            | ServerError::UpdatePasswordError(UpdatePasswordError::QueryError(e)) => {
+                query_error_details(e)
+            }
+            ServerError::Conflict(_, _, d)
+            | ServerError::Unprocessable(_, _, d)
+            | ServerError::TooManyRequests(_, _, d) => d.clone(),
+            _ => json!({}),
+        }
+    }
 }
 
 impl error::ResponseError for ServerError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponseBuilder::new(self.status_code())
-            .set_header(header::CONTENT_TYPE, "text/html; charset=utf-8")
-            .body(self.to_string())
+        HttpResponseBuilder::new(self.status_code()).json(json!({
+            "error": {
+                "code": self.code(),
+                "message": self.message(),
+                "details": self.details(),
+            }
+        }))
     }
 
     fn status_code(&self) -> StatusCode {
-        match *self {
+        match self {
             ServerError::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::QueryError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ServerError::QueryError(e) => query_error_status(e),
+            ServerError::CreateUserError(CreateUserError::QueryError(e)) => query_error_status(e),
             ServerError::CreateUserError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ServerError::UpdatePasswordError(UpdatePasswordError::AuthenticationError) => {
+                StatusCode::FORBIDDEN
+            }
+            ServerError::UpdatePasswordError(UpdatePasswordError::QueryError(e)) => {
+                query_error_status(e)
+            }
             ServerError::UpdatePasswordError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ServerError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ServerError::AdminOnly => StatusCode::FORBIDDEN,
+            ServerError::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ServerError::BadRequest(_, _) => StatusCode::BAD_REQUEST,
+            ServerError::Forbidden(_, _) => StatusCode::FORBIDDEN,
+            ServerError::NotFound(_, _) => StatusCode::NOT_FOUND,
+            ServerError::Conflict(_, _, _) => StatusCode::CONFLICT,
+            ServerError::Unprocessable(_, _, _) => StatusCode::UNPROCESSABLE_ENTITY,
+            ServerError::TooManyRequests(_, _, _) => StatusCode::TOO_MANY_REQUESTS,
         }
     }
 }
 
+fn transition_error(e: TransitionError, s: &Schedule, action: Action) -> ServerError {
+    match e {
+        TransitionError::NotPermitted => ServerError::Forbidden(
+            "action_not_permitted",
+            format!("you may not {:?} this schedule", action).to_lowercase(),
+        ),
+        TransitionError::InvalidState => ServerError::Conflict(
+            "invalid_schedule_state",
+            "schedule cannot be changed in its current state".to_string(),
+            json!({"status": s.status, "action": action}),
+        ),
+    }
+}
+
+/// Runs `f` on actix's blocking thread pool with a pooled connection, so
+/// diesel never blocks the worker that serves requests. When no connection
+/// frees up within the pool's checkout timeout the request gets a 503.
+async fn db<F, T>(
+    pool: &r2d2::Pool<ConnectionManager<PgConnection>>,
+    f: F,
+) -> Result<T, ServerError>
+where
+    F: FnOnce(&PgConnection) -> Result<T, ServerError> + Send + 'static,
+    T: Send + 'static,
+{
+    let pool = pool.clone();
+    web::block(move || {
+        let conn = pool.get().map_err(|_| ServerError::Unavailable)?;
+        f(&conn)
+    })
+    .await
+    .map_err(|e| match e {
+        BlockingError::Error(e) => e,
+        BlockingError::Canceled => ServerError::InternalError,
+    })
+}
+
 async fn login_api(
     user: web::Json<UserPass>,
     conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
-) -> Result<HttpResponse, error::Error> {
-    conn.get()
-        .ok()
-        .ok_or(error::Error::from(ServerError::InternalError))
-        .and_then(|conn| {
-            login(&conn, &user.id, &user.pass)
-                .ok_or(error::ErrorUnauthorized("unauthorized error"))
-                .map(|t| HttpResponse::Ok().json(json!({ "token": t, "token_type": "bearer" })))
+) -> Result<HttpResponse, ServerError> {
+    let user = user.into_inner();
+    let t = db(&conn, move |conn| {
+        login(conn, &user.id, &user.pass).ok_or(ServerError::Unauthorized)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(json!({ "token": t, "token_type": "bearer" })))
+}
+
+fn ensure_open(conn: &PgConnection, times: &[&chrono::NaiveDateTime]) -> Result<(), ServerError> {
+    let now = Utc::now().naive_utc();
+    for t in times {
+        if period::is_locked(conn, t, &now).map_err(ServerError::QueryError)? {
+            return Err(ServerError::Forbidden(
+                "period_closed",
+                "submission period is closed".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// attendance in a closed period is final
+fn ensure_attendance_open(
+    conn: &PgConnection,
+    times: &[&chrono::NaiveDateTime],
+) -> Result<(), ServerError> {
+    let now = Utc::now().naive_utc();
+    for t in times {
+        if period::is_closed(conn, t, &now).map_err(ServerError::QueryError)? {
+            return Err(ServerError::Forbidden(
+                "period_closed",
+                "attendance period is closed".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// no shifts on the store's closed days
+fn ensure_store_open(conn: &PgConnection, time: &NaiveDateTime) -> Result<(), ServerError> {
+    let date = to_local(time).date();
+    let closed = calendar::closed(conn, date, date.succ()).map_err(ServerError::QueryError)?;
+    if closed.contains(&date) {
+        return Err(ServerError::Conflict(
+            "store_closed",
+            format!("the store is closed on {}", date),
+            json!({ "date": date }),
+        ));
+    }
+    Ok(())
+}
+
+/// Runs `f` in a transaction that is committed when it returns Ok and rolled
+/// back otherwise, so no error path leaves a pooled connection mid-transaction.
+fn transaction<T, F>(conn: &PgConnection, f: F) -> Result<T, ServerError>
+where
+    F: FnOnce() -> Result<T, ServerError>,
+{
+    let tm = diesel::Connection::transaction_manager(conn);
+    tm.begin_transaction(conn)
+        .map_err(ServerError::QueryError)?;
+    match f() {
+        Ok(x) => match tm.commit_transaction(conn) {
+            Ok(_) => Ok(x),
+            Err(e) => {
+                let _ = tm.rollback_transaction(conn);
+                Err(ServerError::QueryError(e))
+            }
+        },
+        Err(e) => {
+            let _ = tm.rollback_transaction(conn);
+            Err(e)
+        }
+    }
+}
+
+/// The user behind the bearer token. The role is read from the database on
+/// every request, so promotions and deactivations apply to tokens already
+/// handed out.
+#[derive(Debug)]
+struct Auth {
+    id: String,
+    isadmin: bool,
+}
+
+impl Auth {
+    fn actor(&self) -> Actor<'_> {
+        Actor {
+            username: &self.id,
+            isadmin: self.isadmin,
+        }
+    }
+}
+
+impl FromRequest for Auth {
+    type Error = ServerError;
+    type Future = LocalBoxFuture<'static, Result<Auth, ServerError>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let user = req
+            .headers()
+            .get("Authorization")
+            .and_then(|x| x.to_str().ok())
+            .and_then(decode)
+            .map(|t| t.claims.user);
+        let pool = req
+            .app_data::<web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>>()
+            .cloned();
+        Box::pin(async move {
+            let user = user.ok_or(ServerError::Unauthorized)?;
+            let pool = pool.ok_or(ServerError::InternalError)?;
+            let u = db(&pool, move |conn| {
+                get_user(conn, &user).ok_or(ServerError::Unauthorized)
+            })
+            .await?;
+            Ok(Auth {
+                id: u.id,
+                isadmin: u.isadmin,
+            })
+        })
+    }
+}
+
+/// The kiosk behind the `X-Kiosk-Token` header. Revoked devices are refused.
+#[derive(Debug)]
+struct KioskDevice(Kiosk);
+
+impl FromRequest for KioskDevice {
+    type Error = ServerError;
+    type Future = LocalBoxFuture<'static, Result<KioskDevice, ServerError>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let token = req
+            .headers()
+            .get("X-Kiosk-Token")
+            .and_then(|x| x.to_str().ok())
+            .map(|x| x.trim().to_string());
+        let pool = req
+            .app_data::<web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>>()
+            .cloned();
+        Box::pin(async move {
+            let token = token.ok_or(ServerError::Unauthorized)?;
+            let pool = pool.ok_or(ServerError::InternalError)?;
+            db(&pool, move |conn| {
+                kiosk::by_token(conn, &token)
+                    .map_err(ServerError::QueryError)?
+                    .map(KioskDevice)
+                    .ok_or(ServerError::Unauthorized)
+            })
+            .await
+        })
+    }
+}
+
+/// Guards admin-only routes: an authenticated user who is not an admin gets
+/// 403 before the handler runs.
+#[derive(Debug)]
+struct Admin(Auth);
+
+impl std::ops::Deref for Admin {
+    type Target = Auth;
+
+    fn deref(&self) -> &Auth {
+        &self.0
+    }
+}
+
+impl FromRequest for Admin {
+    type Error = ServerError;
+    type Future = LocalBoxFuture<'static, Result<Admin, ServerError>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let auth = Auth::from_request(req, payload);
+        Box::pin(async move {
+            let u = auth.await?;
+            if u.isadmin {
+                Ok(Admin(u))
+            } else {
+                Err(ServerError::AdminOnly)
+            }
         })
+    }
+}
+
+const SCHEDULE_PAGE_LIMIT: i64 = 500;
+
+// keyset cursor: start time in microseconds and id of the last row of the previous page
+fn parse_cursor(c: &str) -> Option<(NaiveDateTime, i64)> {
+    let mut it = c.splitn(2, ':');
+    let t = it.next()?.parse::<i64>().ok()?;
+    let id = it.next()?.parse::<i64>().ok()?;
+    let secs = t.div_euclid(1_000_000);
+    let nanos = t.rem_euclid(1_000_000) as u32 * 1000;
+    NaiveDateTime::from_timestamp_opt(secs, nanos).map(|x| (x, id))
 }
 
-fn auth(req: &HttpRequest) -> Option<String> {
-    let t = req.headers().get("Authorization")?.to_str().ok()?;
-    let t = decode(t)?;
-    Some(t.claims.user)
+fn format_cursor(s: &Schedule) -> String {
+    let t =
+        s.start_time.timestamp() * 1_000_000 + i64::from(s.start_time.timestamp_subsec_micros());
+    format!("{}:{}", t, s.id)
 }
 
 async fn get_schedules(
+    user: Auth,
     req: HttpRequest,
     conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
-) -> Result<HttpResponse, error::Error> {
-    use diesel::query_dsl::filter_dsl::OrFilterDsl;
-    use diesel::query_dsl::methods::OrderDsl;
-    use diesel::ExpressionMethods;
+) -> Result<HttpResponse, ServerError> {
+    use diesel::query_dsl::methods::{LimitDsl, OrderDsl};
+    use diesel::{BoolExpressionMethods, ExpressionMethods};
     use qstring::QString;
     use schema::schedules;
 
-    let _ = auth(&req).ok_or(error::ErrorUnauthorized("unauthorized error"))?;
     let qs = QString::from(req.query_string());
+    let invalid =
+        |k: &str| ServerError::BadRequest("invalid_parameter", format!("cannot parse {}", k));
     let today = Local::now().naive_local().date();
-    let start_date = today.checked_sub_signed(Duration::days(
-        today.weekday().num_days_from_sunday().into(),
-    ));
-    let end_date = start_date
-        .as_ref()
-        .and_then(|x| x.checked_add_signed(Duration::days(7)));
-    let start = qs
-        .get("start")
+    let start_date = today - Duration::days(today.weekday().num_days_from_sunday().into());
+    let date = |k: &str, d: NaiveDate| {
+        qs.get(k)
+            .map(|x| NaiveDate::parse_from_str(x, "%Y-%m-%d").map_err(|_| invalid(k)))
+            .unwrap_or(Ok(d))
+    };
+    let start = date("start", start_date)?;
+    let end = date("end", start_date + Duration::days(7))?;
+    let statuses = qs
+        .get("status")
         .map(|x| {
-            NaiveDate::parse_from_str(x, "%Y-%m-%d")
-                .or_else(|_| Err(error::Error::from(ServerError::InternalError)))
+            x.split(',')
+                .map(|s| Status::parse(s).ok_or_else(|| invalid("status")))
+                .collect::<Result<Vec<_>, _>>()
         })
-        .unwrap_or_else(|| start_date.ok_or(error::Error::from(ServerError::InternalError)))
-        .map_err(|x| error::ErrorBadRequest(format!("cannot parse start: {}", x)))?;
-    let end = qs
-        .get("end")
+        .transpose()?;
+    let desc = match qs.get("sort").unwrap_or("-start_time") {
+        "start_time" => false,
+        "-start_time" => true,
+        _ => return Err(invalid("sort")),
+    };
+    let limit = qs
+        .get("limit")
         .map(|x| {
-            NaiveDate::parse_from_str(x, "%Y-%m-%d")
-                .or_else(|_| Err(error::Error::from(ServerError::InternalError)))
-        })
-        .unwrap_or_else(|| end_date.ok_or(error::Error::from(ServerError::InternalError)))
-        .map_err(|x| error::ErrorBadRequest(format!("cannot parse end: {}", x)))?;
-    conn.get()
-        .ok()
-        .ok_or(error::Error::from(ServerError::InternalError))
-        .and_then(|conn| {
-            schedules::table
-                .order(schedules::start_time.desc())
-                .filter(schedules::end_time.gt(start.and_hms(0, 0, 0)))
-                .or_filter(schedules::start_time.lt(end.and_hms(0, 0, 0)))
-                .get_results::<Schedule>(&conn)
-                .map_err(|x| error::Error::from(ServerError::QueryError(x)))
-                .map(|x| HttpResponse::Ok().json(x))
+            x.parse::<i64>()
+                .ok()
+                .filter(|x| (1..=SCHEDULE_PAGE_LIMIT).contains(x))
+                .ok_or_else(|| invalid("limit"))
         })
+        .unwrap_or(Ok(SCHEDULE_PAGE_LIMIT))?;
+    let cursor = qs
+        .get("cursor")
+        .map(|x| parse_cursor(x).ok_or_else(|| invalid("cursor")))
+        .transpose()?;
+    let username = qs.get("username").map(|x| x.to_string());
+    let created_by = qs.get("created_by").map(|x| x.to_string());
+    let mut ss = db(&conn, move |conn| {
+        let shared =
+            settings::get_bool(conn, settings::SHARED_ROSTER).map_err(ServerError::QueryError)?;
+        let mut q = diesel::QueryDsl::into_boxed(schedules::table)
+            .filter(schedules::start_time.lt(from_local(&end.and_hms(0, 0, 0))))
+            .filter(schedules::end_time.gt(from_local(&start.and_hms(0, 0, 0))));
+        if !user.isadmin && !shared {
+            q = q.filter(
+                schedules::username
+                    .eq(user.id.clone())
+                    .or(schedules::created_by.eq(user.id.clone()))
+                    .or(schedules::status.eq(Status::Approved)),
+            );
+        }
+        if let Some(x) = username {
+            q = q.filter(schedules::username.eq(x));
+        }
+        if let Some(x) = created_by {
+            q = q.filter(schedules::created_by.eq(x));
+        }
+        if let Some(x) = statuses {
+            q = q.filter(schedules::status.eq_any(x));
+        }
+        if let Some((t, id)) = cursor {
+            q = if desc {
+                q.filter(
+                    schedules::start_time
+                        .lt(t)
+                        .or(schedules::start_time.eq(t).and(schedules::id.lt(id))),
+                )
+            } else {
+                q.filter(
+                    schedules::start_time
+                        .gt(t)
+                        .or(schedules::start_time.eq(t).and(schedules::id.gt(id))),
+                )
+            };
+        }
+        q = if desc {
+            q.order((schedules::start_time.desc(), schedules::id.desc()))
+        } else {
+            q.order((schedules::start_time.asc(), schedules::id.asc()))
+        };
+        q.limit(limit + 1)
+            .get_results::<Schedule>(conn)
+            .map_err(ServerError::QueryError)
+    })
+    .await?;
+    let mut resp = HttpResponse::Ok();
+    if ss.len() as i64 > limit {
+        ss.truncate(limit as usize);
+        if let Some(last) = ss.last() {
+            resp.header("X-Next-Cursor", format_cursor(last));
+        }
+    }
+    Ok(resp.json(ss))
 }
 
 async fn get_users(
-    req: HttpRequest,
+    _: Admin,
     conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
-) -> Result<HttpResponse, error::Error> {
+) -> Result<HttpResponse, ServerError> {
     use schema::users;
 
-    let ansi = AnsiTransactionManager::new();
-    let user = auth(&req).ok_or(error::ErrorUnauthorized("unauthorized error"))?;
-    conn.get()
-        .ok()
-        .ok_or(error::Error::from(ServerError::InternalError))
-        .and_then(|conn| {
-            let _ = ansi
-                .begin_transaction(&conn)
-                .map_err(|x| error::Error::from(ServerError::QueryError(x)))?;
-            let u = get_user(&conn, &user).ok_or(error::Error::from(ServerError::InternalError))?;
-            if !u.isadmin {
-                Err(error::ErrorForbidden("method is allowed for only admin"))
-            } else {
-                let ret = users::table
-                    .get_results::<User>(&conn)
-                    .map_err(|x| error::Error::from(ServerError::QueryError(x)))
-                    .map(|x| {
-                        HttpResponse::Ok().json(
-                            x.iter()
-                                .map(|u| json!({"id": u.id, "isadmin": u.isadmin}))
-                                .collect::<Value>(),
-                        )
-                    })?;
-                let _ = ansi
-                    .commit_transaction(&conn)
-                    .map_err(|x| error::Error::from(ServerError::QueryError(x)))?;
-                Ok(ret)
-            }
-        })
+    let us = db(&conn, |conn| {
+        users::table
+            .get_results::<User>(conn)
+            .map_err(ServerError::QueryError)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(
+        us.iter()
+            .map(|u| {
+                json!({
+                    "id": u.id,
+                    "isadmin": u.isadmin,
+                    "active": u.deactivated_at.is_none(),
+                })
+            })
+            .collect::<Value>(),
+    ))
 }
 
 async fn add_user(
-    req: HttpRequest,
+    _: Admin,
     nu: web::Json<UserName>,
     conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
     pg: web::Data<PasswordGenerator>,
-) -> Result<HttpResponse, error::Error> {
-    let ansi = AnsiTransactionManager::new();
-    let user = auth(&req).ok_or(error::ErrorUnauthorized("unauthorized error"))?;
-    conn.get()
-        .ok()
-        .ok_or(error::Error::from(ServerError::InternalError))
-        .and_then(|conn| {
-            let _ = ansi
-                .begin_transaction(&conn)
-                .map_err(|x| error::Error::from(ServerError::QueryError(x)))?;
-            let u = get_user(&conn, &user).ok_or(error::Error::from(ServerError::InternalError))?;
-            if !u.isadmin {
-                Err(error::ErrorForbidden("method is allowed for only admin"))
-            } else {
-                let np = pg
-                    .generate_one()
-                    .map_err(|_| error::Error::from(ServerError::InternalError))?;
-                let ret = create_user(&conn, &nu.id, &np, &nu.isadmin, None, None)
-                    .map_err(|x| error::Error::from(ServerError::CreateUserError(x)))
-                    .map(|u| {
-                        HttpResponse::Ok()
-                            .json(json!({"id": u.id, "isadmin": u.isadmin, "pass": np}))
-                    })?;
-                let _ = ansi
-                    .commit_transaction(&conn)
-                    .map_err(|x| error::Error::from(ServerError::QueryError(x)))?;
-                Ok(ret)
+) -> Result<HttpResponse, ServerError> {
+    let np = pg.generate_one().map_err(|_| ServerError::InternalError)?;
+    let nu = nu.into_inner();
+    let pass = np.clone();
+    let u = db(&conn, move |conn| {
+        create_user(conn, &nu.id, &pass, &nu.isadmin, None, None)
+            .map_err(ServerError::CreateUserError)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(json!({"id": u.id, "isadmin": u.isadmin, "pass": np})))
+}
+
+async fn import_users(
+    user: Admin,
+    body: web::Bytes,
+    opts: web::Query<ImportOptions>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+    pg: web::Data<PasswordGenerator>,
+) -> Result<HttpResponse, ServerError> {
+    let text = employees::decode(&body);
+    let dry_run = opts.dry_run;
+    let (es, rows, creds) = db(&conn, move |conn| {
+        // a dry run only reads, so committing it changes nothing
+        transaction(conn, || {
+            let (es, rows) = employees::validate(conn, &text)
+                .map_err(|x| ServerError::BadRequest("invalid_csv", x))?;
+            if rows.iter().any(|r| !r.errors.is_empty()) {
+                return Err(ServerError::Unprocessable(
+                    "invalid_rows",
+                    "some rows are invalid; nothing was imported".to_string(),
+                    json!({ "rows": rows }),
+                ));
             }
+            if dry_run {
+                return Ok((es, rows, vec![]));
+            }
+            let passwords = pg
+                .generate(es.len())
+                .map_err(|_| ServerError::InternalError)?;
+            let creds = employees::import(conn, &es, &passwords, &user.id)
+                .map_err(ServerError::CreateUserError)?;
+            Ok((es, rows, creds))
         })
+    })
+    .await?;
+    if dry_run {
+        Ok(HttpResponse::Ok().json(json!({
+            "dry_run": true,
+            "rows": rows,
+            "employees": es,
+        })))
+    } else if opts.format.as_deref() == Some("csv") {
+        let file = employees::credentials_csv(&creds).map_err(|_| ServerError::InternalError)?;
+        Ok(HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .header(
+                "Content-Disposition",
+                "attachment; filename=\"credentials.csv\"",
+            )
+            .body(file))
+    } else {
+        Ok(HttpResponse::Ok().json(json!({
+            "dry_run": false,
+            "rows": rows,
+            "credentials": creds,
+        })))
+    }
 }
 
 async fn update_password(
-    req: HttpRequest,
+    user: Auth,
     p: web::Json<Passwords>,
     conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
-) -> Result<HttpResponse, error::Error> {
-    let user = auth(&req).ok_or(error::ErrorUnauthorized("unauthorized error"))?;
+) -> Result<HttpResponse, ServerError> {
     // -- for demo --
-    if user == "root" {
-        return Err(error::ErrorForbidden("cannot change password of root"));
+    if user.id == "root" {
+        return Err(ServerError::Forbidden(
+            "root_protected",
+            "cannot change password of root".to_string(),
+        ));
     }
     // -- for demo --
-    conn.get()
-        .ok()
-        .ok_or(error::Error::from(ServerError::InternalError))
-        .and_then(|conn| {
-            kintai::update_password(&conn, &user, &p.old, &p.new)
-                .map_err(|x| error::Error::from(ServerError::UpdatePasswordError(x)))
-                .and_then(|s| {
-                    if s == 1 {
-                        Ok(HttpResponse::Ok().json("ok"))
-                    } else {
-                        Err(error::Error::from(ServerError::InternalError))
-                    }
-                })
-        })
+    db(&conn, move |conn| {
+        kintai::update_password(conn, &user.id, &p.old, &p.new)
+            .map_err(ServerError::UpdatePasswordError)
+            .and_then(|s| {
+                if s == 1 {
+                    Ok("ok")
+                } else {
+                    Err(ServerError::InternalError)
+                }
+            })
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
 }
 
 async fn delete_user(
-    req: HttpRequest,
+    user: Admin,
     web::Path(id): web::Path<String>,
     conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
-) -> Result<HttpResponse, error::Error> {
+) -> Result<HttpResponse, ServerError> {
     use diesel::ExpressionMethods;
     use schema::users;
 
-    let ansi = AnsiTransactionManager::new();
-    let user = auth(&req).ok_or(error::ErrorUnauthorized("unauthorized error"))?;
     // -- for demo --
     if id == "root" {
-        return Err(error::ErrorForbidden("cannot remove root"));
+        return Err(ServerError::Forbidden(
+            "root_protected",
+            "cannot remove root".to_string(),
+        ));
     }
     // -- for demo --
-    conn.get()
-        .ok()
-        .ok_or(error::Error::from(ServerError::InternalError))
-        .and_then(|conn| {
-            let _ = ansi
-                .begin_transaction(&conn)
-                .map_err(|x| error::Error::from(ServerError::QueryError(x)))?;
-            let u = get_user(&conn, &user).ok_or(error::Error::from(ServerError::InternalError))?;
-            if !u.isadmin {
-                Err(error::ErrorForbidden("method is allowed for only admin"))
-            } else {
-                let t =
-                    get_user(&conn, &id).ok_or(error::Error::from(ServerError::InternalError))?;
-                if !t.isadmin || id == user {
-                    let ret = diesel::delete(users::table.filter(users::id.eq(id)))
-                        .execute(&conn)
-                        .map_err(|x| error::Error::from(ServerError::QueryError(x)))
-                        .and_then(|s| {
-                            if s == 1 {
-                                Ok(HttpResponse::Ok().json("ok"))
-                            } else {
-                                Err(error::Error::from(ServerError::InternalError))
-                            }
-                        })?;
-                    let _ = ansi
-                        .commit_transaction(&conn)
-                        .map_err(|x| error::Error::from(ServerError::QueryError(x)))?;
-                    Ok(ret)
-                } else {
-                    Err(error::ErrorForbidden(
-                        "can not remove admin account except own",
-                    ))
-                }
+    db(&conn, move |conn| {
+        transaction(conn, || {
+            // deactivated users can still be removed for good
+            let t = users::table
+                .filter(users::id.eq(&id))
+                .get_result::<User>(conn)
+                .ok()
+                .ok_or_else(|| {
+                    ServerError::NotFound("user_not_found", format!("user {} does not exist", id))
+                })?;
+            if t.isadmin && id != user.id {
+                return Err(ServerError::Forbidden(
+                    "admin_protected",
+                    "can not remove admin account except own".to_string(),
+                ));
             }
+            diesel::delete(users::table.filter(users::id.eq(&id)))
+                .execute(conn)
+                .map_err(ServerError::QueryError)
+                .and_then(|s| {
+                    if s == 1 {
+                        Ok("ok")
+                    } else {
+                        Err(ServerError::InternalError)
+                    }
+                })
         })
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
 }
 
 async fn update_schedule(
-    req: HttpRequest,
+    user: Auth,
     web::Path(id): web::Path<i64>,
     se: web::Json<StartEnd>,
     conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
-) -> Result<HttpResponse, error::Error> {
+) -> Result<HttpResponse, ServerError> {
     use diesel::ExpressionMethods;
     use schema::schedules;
 
-    let user = auth(&req).ok_or(error::ErrorUnauthorized("unauthorized error"))?;
-    conn.get()
-        .ok()
-        .ok_or(error::Error::from(ServerError::InternalError))
-        .and_then(|conn| {
-            diesel::update(
-                schedules::table
-                    .filter(schedules::created_by.eq(user))
-                    .filter(schedules::id.eq(id))
-                    .filter(schedules::permitted.eq(false))
-                    .filter(schedules::absent.eq(false))
-                    .filter(schedules::enable.eq(true)),
+    db(&conn, move |conn| {
+        transaction(conn, || {
+            let s = diesel::QueryDsl::for_update(schedules::table.filter(schedules::id.eq(id)))
+                .get_result::<Schedule>(conn)
+                .map_err(ServerError::QueryError)?;
+            schedule::transition(&s, &user.actor(), Action::Edit)
+                .map_err(|x| transition_error(x, &s, Action::Edit))?;
+            if !user.isadmin {
+                ensure_open(conn, &[&s.start_time, &se.start_time])?;
+            }
+            ensure_store_open(conn, &se.start_time)?;
+            diesel::update(schedules::table.filter(schedules::id.eq(id)))
+                .set((
+                    schedules::start_time.eq(se.start_time),
+                    schedules::end_time.eq(se.end_time),
+                ))
+                .execute(conn)
+                .map_err(ServerError::QueryError)?;
+            schedule::record(
+                conn,
+                &NewScheduleEvent {
+                    schedule_id: id,
+                    actor: &user.id,
+                    action: Action::Edit.as_str(),
+                    old_start_time: Some(s.start_time),
+                    old_end_time: Some(s.end_time),
+                    new_start_time: Some(se.start_time),
+                    new_end_time: Some(se.end_time),
+                    ..Default::default()
+                },
             )
+            .map_err(ServerError::QueryError)?;
+            Ok("ok")
+        })
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+// applies the first of `actions` the user may take on the schedule; `reason`
+// goes with absences and keeps the one already given when None
+fn apply_action(
+    conn: &PgConnection,
+    id: i64,
+    user: &Auth,
+    actions: &[Action],
+    reason: Option<Reason>,
+) -> Result<Schedule, ServerError> {
+    use diesel::ExpressionMethods;
+    use schema::schedules;
+
+    transaction(conn, || {
+        let s = diesel::QueryDsl::for_update(schedules::table.filter(schedules::id.eq(id)))
+            .get_result::<Schedule>(conn)
+            .map_err(ServerError::QueryError)?;
+        let actor = user.actor();
+        let (action, to) = actions
+            .iter()
+            .map(|a| (*a, schedule::transition(&s, &actor, *a)))
+            .find(|(_, r)| r.is_ok())
+            .unwrap_or_else(|| (actions[0], schedule::transition(&s, &actor, actions[0])));
+        let to = to.map_err(|x| transition_error(x, &s, action))?;
+        if reason == Some(Reason::CompanyRest) && !user.isadmin {
+            return Err(ServerError::Forbidden(
+                "action_not_permitted",
+                "only a manager can order company rest".to_string(),
+            ));
+        }
+        let reason = match action {
+            Action::RequestAbsence | Action::ApproveAbsence => reason.or(s.absence_reason),
+            Action::Excuse => Some(reason.ok_or_else(|| {
+                ServerError::BadRequest(
+                    "invalid_body",
+                    "a reason is required to excuse a shift".to_string(),
+                )
+            })?),
+            Action::RejectAbsence => None,
+            _ if reason.is_some() => {
+                return Err(ServerError::BadRequest(
+                    "invalid_body",
+                    "a reason only goes with an absence".to_string(),
+                ))
+            }
+            _ => s.absence_reason,
+        };
+        let ret = diesel::update(schedules::table.filter(schedules::id.eq(id)))
             .set((
-                schedules::start_time.eq(se.start_time),
-                schedules::end_time.eq(se.end_time),
+                schedules::status.eq(to),
+                schedules::absence_reason.eq(reason),
             ))
-            .execute(&conn)
-            .map_err(|x| error::Error::from(ServerError::QueryError(x)))
-            .and_then(|s| {
-                if s == 1 {
-                    Ok(HttpResponse::Ok().json("ok"))
-                } else {
-                    Err(error::Error::from(ServerError::InternalError))
-                }
-            })
-        })
+            .get_result::<Schedule>(conn)
+            .map_err(ServerError::QueryError)?;
+        schedule::record(
+            conn,
+            &NewScheduleEvent {
+                schedule_id: id,
+                actor: &user.id,
+                action: action.as_str(),
+                from_status: Some(s.status),
+                to_status: Some(to),
+                ..Default::default()
+            },
+        )
+        .map_err(ServerError::QueryError)?;
+        Ok(ret)
+    })
 }
 
-async fn permit_schedule(
-    req: HttpRequest,
+async fn get_schedule(
+    user: Auth,
     web::Path(id): web::Path<i64>,
     conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
-) -> Result<HttpResponse, error::Error> {
+) -> Result<HttpResponse, ServerError> {
     use diesel::ExpressionMethods;
     use schema::schedules;
 
-    let ansi = AnsiTransactionManager::new();
-    let user = auth(&req).ok_or(error::ErrorUnauthorized("unauthorized error"))?;
-    conn.get()
-        .ok()
-        .ok_or(error::Error::from(ServerError::InternalError))
-        .and_then(|conn| {
-            let _ = ansi
-                .begin_transaction(&conn)
-                .map_err(|x| error::Error::from(ServerError::QueryError(x)))?;
-            let u = get_user(&conn, &user).ok_or(error::Error::from(ServerError::InternalError))?;
-            let ret = if u.isadmin {
-                diesel::update(
-                    schedules::table
-                        .filter(schedules::username.eq(schedules::created_by))
-                        .filter(schedules::id.eq(id))
-                        .filter(schedules::permitted.eq(false))
-                        .filter(schedules::absent.eq(false))
-                        .filter(schedules::enable.eq(true)),
-                )
-                .set(schedules::permitted.eq(true))
-                .execute(&conn)
-                .map_err(|x| error::Error::from(ServerError::QueryError(x)))
-                .and_then(|s| {
-                    if s == 1 {
-                        Ok(HttpResponse::Ok().json("ok"))
-                    } else {
-                        Err(error::Error::from(ServerError::InternalError))
-                    }
-                })
-            } else {
-                diesel::update(
-                    schedules::table
-                        .filter(schedules::created_by.ne(user.clone()))
-                        .filter(schedules::username.eq(user))
-                        .filter(schedules::id.eq(id))
-                        .filter(schedules::permitted.eq(false))
-                        .filter(schedules::absent.eq(false))
-                        .filter(schedules::enable.eq(true)),
-                )
-                .set(schedules::permitted.eq(true))
-                .execute(&conn)
-                .map_err(|x| error::Error::from(ServerError::QueryError(x)))
-                .and_then(|s| {
-                    if s == 1 {
-                        Ok(HttpResponse::Ok().json("ok"))
-                    } else {
-                        Err(error::Error::from(ServerError::InternalError))
-                    }
-                })
-            };
-            let _ = ansi
-                .commit_transaction(&conn)
-                .map_err(|x| error::Error::from(ServerError::QueryError(x)))?;
-            ret
-        })
+    db(&conn, move |conn| {
+        let s = schedules::table
+            .filter(schedules::id.eq(id))
+            .get_result::<Schedule>(conn)
+            .map_err(ServerError::QueryError)?;
+        let shared =
+            settings::get_bool(conn, settings::SHARED_ROSTER).map_err(ServerError::QueryError)?;
+        // answer as if it did not exist rather than confirm someone else's shift
+        if !schedule::visible(&s, &user.actor(), shared) {
+            return Err(ServerError::QueryError(diesel::result::Error::NotFound));
+        }
+        let history = schedule::history(conn, id).map_err(ServerError::QueryError)?;
+        let created = history.iter().find(|e| e.action == schedule::CREATE);
+        let approved = history
+            .iter()
+            .rev()
+            .find(|e| e.action == Action::Approve.as_str());
+        let absence: Vec<_> = history
+            .iter()
+            .filter(|e| {
+                [
+                    Action::RequestAbsence,
+                    Action::ApproveAbsence,
+                    Action::RejectAbsence,
+                    Action::Excuse,
+                ]
+                .iter()
+                .any(|a| a.as_str() == e.action)
+            })
+            .collect();
+        let revisions: Vec<_> = history
+            .iter()
+            .filter(|e| e.action == Action::Edit.as_str())
+            .collect();
+        Ok(json!({
+            "id": s.id,
+            "username": s.username,
+            "start_time": s.start_time,
+            "end_time": s.end_time,
+            "status": s.status,
+            "absence_reason": s.absence_reason,
+            "created_by": s.created_by,
+            "created_at": created.map(|e| e.created_at),
+            "approved_by": approved.map(|e| &e.actor),
+            "approved_at": approved.map(|e| e.created_at),
+            "absence": absence,
+            "revisions": revisions,
+            "history": history,
+        }))
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn transition_schedule(
+    user: Auth,
+    web::Path(id): web::Path<i64>,
+    ta: web::Json<TransitionAction>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    if ta.action == Action::Edit || ta.action == Action::Delete {
+        return Err(ServerError::BadRequest(
+            "invalid_action",
+            "use PATCH duration or DELETE to edit or delete".to_string(),
+        ));
+    }
+    db(&conn, move |conn| {
+        apply_action(conn, id, &user, &[ta.action], ta.reason)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn get_schedule_actions(
+    user: Auth,
+    web::Path(id): web::Path<i64>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::ExpressionMethods;
+    use schema::schedules;
+
+    db(&conn, move |conn| {
+        let s = schedules::table
+            .filter(schedules::id.eq(id))
+            .get_result::<Schedule>(conn)
+            .map_err(ServerError::QueryError)?;
+        let shared =
+            settings::get_bool(conn, settings::SHARED_ROSTER).map_err(ServerError::QueryError)?;
+        if !schedule::visible(&s, &user.actor(), shared) {
+            return Err(ServerError::QueryError(diesel::result::Error::NotFound));
+        }
+        Ok(json!({
+            "id": s.id,
+            "status": s.status,
+            "actions": schedule::allowed_actions(&s, &user.actor()),
+        }))
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn permit_schedule(
+    user: Auth,
+    web::Path(id): web::Path<i64>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    db(&conn, move |conn| {
+        apply_action(conn, id, &user, &[Action::Approve], None)
+    })
+    .await
+    .map(|_| HttpResponse::Ok().json("ok"))
 }
 
 async fn absent_schedule(
-    req: HttpRequest,
+    user: Auth,
+    web::Path(id): web::Path<i64>,
+    // the body is optional; without one the reason is left open
+    ar: Option<web::Json<AbsenceRequest>>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    let reason = ar.and_then(|x| x.reason);
+    db(&conn, move |conn| {
+        apply_action(conn, id, &user, &[Action::RequestAbsence], reason)
+    })
+    .await
+    .map(|_| HttpResponse::Ok().json("ok"))
+}
+
+async fn disable_schedule(
+    user: Auth,
     web::Path(id): web::Path<i64>,
     conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
-) -> Result<HttpResponse, error::Error> {
+) -> Result<HttpResponse, ServerError> {
+    db(&conn, move |conn| {
+        apply_action(
+            conn,
+            id,
+            &user,
+            &[Action::ApproveAbsence, Action::Reject, Action::Cancel],
+            None,
+        )
+    })
+    .await
+    .map(|_| HttpResponse::Ok().json("ok"))
+}
+
+async fn add_schedule(
+    user: Auth,
+    se: web::Json<StartEndWithUser>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
     use diesel::ExpressionMethods;
     use schema::schedules;
 
-    let ansi = AnsiTransactionManager::new();
-    let user = auth(&req).ok_or(error::ErrorUnauthorized("unauthorized error"))?;
-    conn.get()
-        .ok()
-        .ok_or(error::Error::from(ServerError::InternalError))
-        .and_then(|conn| {
-            let _ = ansi
-                .begin_transaction(&conn)
-                .map_err(|x| error::Error::from(ServerError::QueryError(x)))?;
-            let u = get_user(&conn, &user).ok_or(error::Error::from(ServerError::InternalError))?;
-            let ret2 = if u.isadmin {
-                diesel::update(
-                    schedules::table
-                        .filter(schedules::id.eq(id))
-                        .filter(schedules::permitted.eq(true))
-                        .filter(schedules::absent.eq(true))
-                        .filter(schedules::enable.eq(true)),
-                )
-                .set(schedules::absent.eq(false))
-                .execute(&conn)
-                .map_err(|x| error::Error::from(ServerError::QueryError(x)))
-                .and_then(|s| {
-                    if s == 1 {
-                        Ok(HttpResponse::Ok().json("ok"))
-                    } else {
-                        Err(error::Error::from(ServerError::InternalError))
-                    }
-                })
-                // todo: record the fact of rejected.
-            } else {
-                Err(error::Error::from(ServerError::InternalError))
-            };
-            let ret1 = if !ret2.is_ok() {
-                diesel::update(
-                    schedules::table
-                        .filter(schedules::username.eq(user))
-                        .filter(schedules::id.eq(id))
-                        .filter(schedules::permitted.eq(true))
-                        .filter(schedules::absent.eq(false))
-                        .filter(schedules::enable.eq(true)),
-                )
-                .set(schedules::absent.eq(true))
-                .execute(&conn)
-                .map_err(|x| error::Error::from(ServerError::QueryError(x)))
-                .and_then(|s| {
-                    if s == 1 {
-                        Ok(HttpResponse::Ok().json("ok"))
+    db(&conn, move |conn| {
+        transaction(conn, || {
+            if !user.isadmin {
+                ensure_open(conn, &[&se.start_time])?;
+            }
+            if se.username != user.id && !user.isadmin {
+                return Err(ServerError::Forbidden(
+                    "not_schedule_owner",
+                    "cannot add schedule for other users".to_string(),
+                ));
+            }
+            ensure_store_open(conn, &se.start_time)?;
+            let ret = diesel::insert_into(schedules::table)
+                .values((
+                    schedules::username.eq(&se.username),
+                    schedules::created_by.eq(&user.id),
+                    schedules::start_time.eq(&se.start_time),
+                    schedules::end_time.eq(&se.end_time),
+                    schedules::status.eq(if se.username == user.id {
+                        Status::Requested
                     } else {
-                        Err(error::Error::from(ServerError::InternalError))
-                    }
-                })
-            } else {
-                Err(error::Error::from(ServerError::InternalError))
-            };
-            let _ = ansi
-                .commit_transaction(&conn)
-                .map_err(|x| error::Error::from(ServerError::QueryError(x)))?;
-            ret2.or(ret1)
+                        Status::Assigned
+                    }),
+                ))
+                .get_result::<Schedule>(conn)
+                .map_err(ServerError::QueryError)?;
+            schedule::record_created(conn, std::slice::from_ref(&ret), &user.id)
+                .map_err(ServerError::QueryError)?;
+            Ok(ret)
         })
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
 }
 
-async fn disable_schedule(
-    req: HttpRequest,
+async fn delete_schedule(
+    user: Auth,
     web::Path(id): web::Path<i64>,
     conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
-) -> Result<HttpResponse, error::Error> {
+) -> Result<HttpResponse, ServerError> {
     use diesel::ExpressionMethods;
     use schema::schedules;
 
-    let ansi = AnsiTransactionManager::new();
-    let user = auth(&req).ok_or(error::ErrorUnauthorized("unauthorized error"))?;
-    conn.get()
-        .ok()
-        .ok_or(error::Error::from(ServerError::InternalError))
-        .and_then(|conn| {
-            let _ = ansi
-                .begin_transaction(&conn)
-                .map_err(|x| error::Error::from(ServerError::QueryError(x)))?;
-            let u = get_user(&conn, &user).ok_or(error::Error::from(ServerError::InternalError))?;
-            let ret2 = if u.isadmin {
-                diesel::update(
-                    schedules::table
-                        .filter(schedules::id.eq(id))
-                        .filter(schedules::permitted.eq(true))
-                        .filter(schedules::absent.eq(true))
-                        .filter(schedules::enable.eq(true)),
-                )
-                .set(schedules::enable.eq(false))
-                .execute(&conn)
-                .map_err(|x| error::Error::from(ServerError::QueryError(x)))
+    db(&conn, move |conn| {
+        transaction(conn, || {
+            let s = diesel::QueryDsl::for_update(schedules::table.filter(schedules::id.eq(id)))
+                .get_result::<Schedule>(conn)
+                .map_err(ServerError::QueryError)?;
+            schedule::transition(&s, &user.actor(), Action::Delete)
+                .map_err(|x| transition_error(x, &s, Action::Delete))?;
+            if !user.isadmin {
+                ensure_open(conn, &[&s.start_time])?;
+            }
+            diesel::delete(schedules::table.filter(schedules::id.eq(id)))
+                .execute(conn)
+                .map_err(ServerError::QueryError)
                 .and_then(|s| {
                     if s == 1 {
-                        Ok(HttpResponse::Ok().json("ok"))
+                        Ok("ok")
                     } else {
-                        Err(error::Error::from(ServerError::InternalError))
+                        Err(ServerError::InternalError)
                     }
                 })
-            } else {
-                Err(error::Error::from(ServerError::InternalError))
-            };
-            let ret1 = diesel::update(
-                schedules::table
-                    .filter(schedules::username.eq(user))
-                    .filter(schedules::id.eq(id))
-                    .filter(schedules::permitted.eq(false))
-                    .filter(schedules::absent.eq(false))
-                    .filter(schedules::enable.eq(true)),
-            )
-            .set(schedules::enable.eq(false))
-            .execute(&conn)
-            .map_err(|x| error::Error::from(ServerError::QueryError(x)))
-            .and_then(|s| {
-                if s == 1 {
-                    Ok(HttpResponse::Ok().json("ok"))
-                } else {
-                    Err(error::Error::from(ServerError::InternalError))
-                }
-            });
-            let _ = ansi
-                .commit_transaction(&conn)
-                .map_err(|x| error::Error::from(ServerError::QueryError(x)))?;
-            ret2.or(ret1)
         })
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
 }
 
-async fn add_schedule(
-    req: HttpRequest,
-    se: web::Json<StartEndWithUser>,
+async fn generate_schedules(
+    user: Admin,
+    rr: web::Json<RosterRequest>,
     conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
-) -> Result<HttpResponse, error::Error> {
+) -> Result<HttpResponse, ServerError> {
     use diesel::ExpressionMethods;
     use schema::schedules;
 
-    let ansi = AnsiTransactionManager::new();
-    let user = auth(&req).ok_or(error::ErrorUnauthorized("unauthorized error"))?;
-    conn.get()
-        .ok()
-        .ok_or(error::Error::from(ServerError::InternalError))
-        .and_then(|conn| {
-            let _ = ansi
-                .begin_transaction(&conn)
-                .map_err(|x| error::Error::from(ServerError::QueryError(x)))?;
-            let u = get_user(&conn, &user).ok_or(error::Error::from(ServerError::InternalError))?;
-            if se.username == user || u.isadmin {
-                let ret = diesel::insert_into(schedules::table)
-                    .values((
-                        schedules::username.eq(&se.username),
-                        schedules::created_by.eq(&user),
-                        schedules::start_time.eq(&se.start_time),
-                        schedules::end_time.eq(&se.end_time),
-                        schedules::permitted.eq(&false),
-                        schedules::absent.eq(&false),
-                        schedules::enable.eq(&true),
-                    ))
-                    .get_result::<Schedule>(&conn)
-                    .map_err(|x| error::Error::from(ServerError::QueryError(x)))
-                    .map(|x| HttpResponse::Ok().json(x))?;
-                let _ = ansi
-                    .commit_transaction(&conn)
-                    .map_err(|x| error::Error::from(ServerError::QueryError(x)))?;
-                Ok(ret)
+    let start = from_local(&rr.week_start.and_hms(0, 0, 0));
+    let end = from_local(&roster::week_end(rr.week_start).and_hms(0, 0, 0));
+    if rr
+        .requirements
+        .iter()
+        .any(|r| r.start_time < start || r.end_time > end || r.end_time <= r.start_time)
+    {
+        return Err(ServerError::BadRequest(
+            "invalid_requirement",
+            "requirements must lie within the week".to_string(),
+        ));
+    }
+    db(&conn, move |conn| {
+        transaction(conn, || {
+            let fixed = schedules::table
+                .filter(schedules::start_time.lt(end))
+                .filter(schedules::end_time.gt(start))
+                .get_results::<Schedule>(conn)
+                .map_err(ServerError::QueryError)?
+                .into_iter()
+                .filter(|s| s.status.is_active())
+                .map(|s| Assignment {
+                    username: s.username,
+                    start_time: s.start_time,
+                    end_time: s.end_time,
+                })
+                .collect();
+            let availability = if rr.availability.is_empty() {
+                availability::load(conn, rr.week_start, roster::week_end(rr.week_start), None)
+                    .map_err(ServerError::QueryError)?
+                    .into_iter()
+                    .filter(|w| w.kind != Kind::Unavailable)
+                    .map(|w| roster::Availability {
+                        username: w.username,
+                        start_time: w.start_time,
+                        end_time: w.end_time,
+                        preferred: w.kind == Kind::Preferred,
+                    })
+                    .collect()
+            } else {
+                rr.availability.clone()
+            };
+            let closed = calendar::closed(conn, rr.week_start, roster::week_end(rr.week_start))
+                .map_err(ServerError::QueryError)?;
+            let (requirements, dropped): (Vec<_>, Vec<_>) = rr
+                .requirements
+                .iter()
+                .cloned()
+                .partition(|r| !closed.contains(&to_local(&r.start_time).date()));
+            let input = RosterInput {
+                availability,
+                requirements,
+                caps: rr.caps.clone(),
+                fixed,
+            };
+            let r = roster::generate(&input, rr.seed);
+            let created = if rr.dry_run || r.assignments.is_empty() {
+                vec![]
             } else {
-                Err(error::Error::from(ServerError::InternalError))
+                diesel::insert_into(schedules::table)
+                    .values(
+                        r.assignments
+                            .iter()
+                            .map(|a| {
+                                (
+                                    schedules::username.eq(&a.username),
+                                    schedules::created_by.eq(&user.id),
+                                    schedules::start_time.eq(&a.start_time),
+                                    schedules::end_time.eq(&a.end_time),
+                                    schedules::status.eq(Status::Assigned),
+                                )
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                    .get_results::<Schedule>(conn)
+                    .map_err(ServerError::QueryError)?
+            };
+            schedule::record_created(conn, &created, &user.id).map_err(ServerError::QueryError)?;
+            Ok(json!({
+                "assignments": r.assignments,
+                "unfilled": r.unfilled,
+                // requirements on closed days, left out
+                "closed": dropped,
+                "schedules": created,
+            }))
+        })
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn import_schedules(
+    user: Admin,
+    body: web::Bytes,
+    opts: web::Query<ScheduleImportOptions>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    let layout = match opts.layout.as_deref() {
+        None => None,
+        Some(x) => Some(import::Format::parse(x).ok_or_else(|| {
+            ServerError::BadRequest("invalid_parameter", format!("unknown layout: {}", x))
+        })?),
+    };
+    let text = employees::decode(&body);
+    db(&conn, move |conn| {
+        transaction(conn, || {
+            let rows = import::validate(conn, &text, layout)
+                .map_err(|x| ServerError::BadRequest("invalid_csv", x))?;
+            if rows.iter().any(|r| !r.errors.is_empty()) {
+                return Err(ServerError::Unprocessable(
+                    "invalid_rows",
+                    "some shifts are invalid; nothing was imported".to_string(),
+                    json!({ "rows": rows }),
+                ));
+            }
+            if opts.dry_run {
+                return Ok(json!({
+                    "dry_run": true,
+                    "rows": rows,
+                    "schedules": [],
+                }));
             }
+            let created = import::import(conn, &rows, &user.id).map_err(ServerError::QueryError)?;
+            Ok(json!({
+                "dry_run": false,
+                "rows": rows,
+                "schedules": created,
+            }))
         })
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
 }
 
-async fn delete_schedule(
+fn parse_range(req: &HttpRequest) -> Result<(NaiveDate, NaiveDate), ServerError> {
+    use qstring::QString;
+
+    let qs = QString::from(req.query_string());
+    let parse = |k: &str| {
+        qs.get(k)
+            .ok_or_else(|| {
+                ServerError::BadRequest("invalid_parameter", format!("{} is required", k))
+            })
+            .and_then(|x| {
+                NaiveDate::parse_from_str(x, "%Y-%m-%d").map_err(|x| {
+                    ServerError::BadRequest(
+                        "invalid_parameter",
+                        format!("cannot parse {}: {}", k, x),
+                    )
+                })
+            })
+    };
+    Ok((parse("start")?, parse("end")?))
+}
+
+async fn add_availability(
+    user: Auth,
+    na: web::Json<NewAvailability>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::ExpressionMethods;
+    use schema::availabilities;
+
+    let username = na.username.clone().unwrap_or_else(|| user.id.clone());
+    if username != user.id && !user.isadmin {
+        return Err(ServerError::AdminOnly);
+    }
+    db(&conn, move |conn| {
+        transaction(conn, || {
+            if !user.isadmin {
+                ensure_open(conn, &[&na.start_time])?;
+            }
+            diesel::insert_into(availabilities::table)
+                .values((
+                    availabilities::username.eq(&username),
+                    availabilities::start_time.eq(&na.start_time),
+                    availabilities::end_time.eq(&na.end_time),
+                    availabilities::kind.eq(na.kind.as_str()),
+                ))
+                .get_result::<Availability>(conn)
+                .map_err(ServerError::QueryError)
+        })
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn get_availabilities(
+    user: Auth,
     req: HttpRequest,
-    web::Path(id): web::Path<i64>,
     conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
-) -> Result<HttpResponse, error::Error> {
+) -> Result<HttpResponse, ServerError> {
+    use diesel::query_dsl::methods::OrderDsl;
     use diesel::ExpressionMethods;
-    use schema::schedules;
+    use qstring::QString;
+    use schema::availabilities;
+
+    let (start, end) = parse_range(&req)?;
+    let username = QString::from(req.query_string())
+        .get("username")
+        .map(|x| x.to_string());
+    db(&conn, move |conn| {
+        let mut q = diesel::QueryDsl::into_boxed(availabilities::table)
+            .order(availabilities::start_time.asc())
+            .filter(availabilities::start_time.ge(kintai::from_local(&start.and_hms(0, 0, 0))))
+            .filter(availabilities::start_time.lt(kintai::from_local(&end.and_hms(0, 0, 0))));
+        if !user.isadmin {
+            q = q.filter(availabilities::username.eq(user.id));
+        } else if let Some(username) = username {
+            q = q.filter(availabilities::username.eq(username));
+        }
+        q.get_results::<Availability>(conn)
+            .map_err(ServerError::QueryError)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
 
-    let ansi = AnsiTransactionManager::new();
-    let user = auth(&req).ok_or(error::ErrorUnauthorized("unauthorized error"))?;
-    conn.get()
-        .ok()
-        .ok_or(error::Error::from(ServerError::InternalError))
-        .and_then(|conn| {
-            let _ = ansi
-                .begin_transaction(&conn)
-                .map_err(|x| error::Error::from(ServerError::QueryError(x)))?;
-            let s = schedules::table
-                .filter(schedules::id.eq(id))
-                .filter(schedules::created_by.eq(user.clone()))
-                .get_result::<Schedule>(&conn)
-                .map_err(|x| error::Error::from(ServerError::QueryError(x)))?;
-            if !s.permitted {
-                let ret = diesel::delete(schedules::table.filter(schedules::id.eq(id)))
-                    .execute(&conn)
-                    .map_err(|x| error::Error::from(ServerError::QueryError(x)))
-                    .and_then(|s| {
-                        if s == 1 {
-                            Ok(HttpResponse::Ok().json("ok"))
-                        } else {
-                            Err(error::Error::from(ServerError::InternalError))
-                        }
-                    })?;
-                let _ = ansi
-                    .commit_transaction(&conn)
-                    .map_err(|x| error::Error::from(ServerError::QueryError(x)))?;
-                Ok(ret)
-            } else {
-                Err(error::Error::from(ServerError::InternalError))
+async fn delete_availability(
+    user: Auth,
+    web::Path(id): web::Path<i64>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::ExpressionMethods;
+    use schema::availabilities;
+
+    db(&conn, move |conn| {
+        transaction(conn, || {
+            let a = availabilities::table
+                .filter(availabilities::id.eq(id))
+                .get_result::<Availability>(conn)
+                .map_err(ServerError::QueryError)?;
+            if a.username != user.id && !user.isadmin {
+                return Err(ServerError::AdminOnly);
             }
+            if !user.isadmin {
+                ensure_open(conn, &[&a.start_time])?;
+            }
+            diesel::delete(availabilities::table.filter(availabilities::id.eq(id)))
+                .execute(conn)
+                .map_err(ServerError::QueryError)
+                .map(|_| "ok")
         })
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
 }
 
-fn config(cfg: &mut web::ServiceConfig) {
-    cfg.service(web::resource("/"))
-        .route("/api/login", web::post().to(login_api))
-        .service(web::resource("/api/schedules/{id}").route(web::delete().to(delete_schedule)))
-        .service(
-            web::resource("/api/schedules/{id}/duration").route(web::patch().to(update_schedule)),
-        )
-        .service(
-            web::resource("/api/schedules/{id}/permission").route(web::patch().to(permit_schedule)),
-        )
-        .service(
-            web::resource("/api/schedules/{id}/absence").route(web::patch().to(absent_schedule)),
-        )
-        .service(
-            web::resource("/api/schedules/{id}/availability")
-                .route(web::patch().to(disable_schedule)),
-        )
-        .route("/api/schedules", web::post().to(add_schedule))
-        .route("/api/schedules", web::get().to(get_schedules))
-        .service(web::resource("/api/users/{id}").route(web::delete().to(delete_user)))
-        .route("/api/users/me/password", web::patch().to(update_password))
-        .route("/api/users", web::post().to(add_user))
-        .route("/api/users", web::get().to(get_users));
+async fn get_weekly_availabilities(
+    user: Auth,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::query_dsl::methods::OrderDsl;
+    use diesel::ExpressionMethods;
+    use schema::weekly_availabilities;
+
+    db(&conn, move |conn| {
+        weekly_availabilities::table
+            .order((
+                weekly_availabilities::weekday.asc(),
+                weekly_availabilities::start_time.asc(),
+            ))
+            .filter(weekly_availabilities::username.eq(user.id))
+            .get_results::<WeeklyAvailability>(conn)
+            .map_err(ServerError::QueryError)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    std::env::set_var("RUST_LOG", "actix_web=info");
-    let port = env::var("PORT")
-        .unwrap_or_else(|_| "8080".to_string())
-        .parse()
-        .expect("PORT must be a number");
-    let pool = establish_connection().unwrap();
-    let pg = create_pg();
-    HttpServer::new(move || {
-        App::new()
-            .route("/", web::get().to(index))
-            .configure(config)
-            .service(
-                Files::new("/asset-manifest.json", "./build/asset-manifest.json").prefer_utf8(true),
+async fn put_weekly_availabilities(
+    user: Auth,
+    ws: web::Json<Vec<WeeklyEntry>>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::ExpressionMethods;
+    use schema::weekly_availabilities;
+
+    db(&conn, move |conn| {
+        transaction(conn, || {
+            diesel::delete(
+                weekly_availabilities::table.filter(weekly_availabilities::username.eq(&user.id)),
             )
-            .service(Files::new("/favicon.ico", "./build/favicon.ico").prefer_utf8(true))
-            .service(Files::new("/index.html", "./build/index.html").prefer_utf8(true))
-            .service(Files::new("/logo192.png", "./build/logo192.png").prefer_utf8(true))
-            .service(Files::new("/logo512.png", "./build/logo512.png").prefer_utf8(true))
-            .service(Files::new("/manifest.json", "./build/manifest.json").prefer_utf8(true))
-            .service(Files::new("/robots.txt", "./build/robots.txt").prefer_utf8(true))
-            .service(Files::new("/static", "./build/static").prefer_utf8(true))
-            .default_service(web::route().to(index))
-            .data(pool.clone())
-            .data(pg.clone())
+            .execute(conn)
+            .map_err(ServerError::QueryError)?;
+            diesel::insert_into(weekly_availabilities::table)
+                .values(
+                    ws.iter()
+                        .map(|w| {
+                            (
+                                weekly_availabilities::username.eq(&user.id),
+                                weekly_availabilities::weekday.eq(w.weekday),
+                                weekly_availabilities::start_time.eq(w.start_time),
+                                weekly_availabilities::end_time.eq(w.end_time),
+                                weekly_availabilities::kind.eq(w.kind.as_str()),
+                            )
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .get_results::<WeeklyAvailability>(conn)
+                .map_err(ServerError::QueryError)
+        })
     })
-    .bind(("0.0.0.0", port))?
-    .run()
     .await
+    .map(|x| HttpResponse::Ok().json(x))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use actix_web::{
-        http::{header, StatusCode},
-        test::{self, read_body_json},
-        App,
-    };
-    use chrono::Duration;
-    use kintai::establish_connection;
-    use serde_json::{json, Value};
-    use uuid::Uuid;
+async fn add_period(
+    user: Admin,
+    np: web::Json<NewPeriod>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::ExpressionMethods;
+    use schema::periods;
+
+    db(&conn, move |conn| {
+        diesel::insert_into(periods::table)
+            .values((
+                periods::start_date.eq(&np.start_date),
+                periods::end_date.eq(&np.end_date),
+                periods::deadline.eq(&np.deadline),
+                periods::created_by.eq(&user.id),
+                periods::opens_at.eq(&np.opens_at),
+            ))
+            .get_result::<Period>(conn)
+            .map_err(ServerError::QueryError)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
 
-    #[actix_rt::test]
-    async fn test_absent() {
-        let pool = establish_connection().unwrap();
-        let pg = create_pg();
-        let mut app = test::init_service(
-            App::new()
-                .configure(config)
-                .data(pool.clone())
-                .data(pg.clone()),
-        )
-        .await;
+async fn get_periods(
+    _: Auth,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::query_dsl::methods::OrderDsl;
+    use diesel::ExpressionMethods;
+    use schema::periods;
 
-        let resp = test::TestRequest::post()
-            .uri("/api/login")
-            .header(header::CONTENT_TYPE, "application/json")
-            .set_payload(r#"{"id":"root", "pass":"pass"}"#.as_bytes())
-            .send_request(&mut app)
-            .await;
+    db(&conn, move |conn| {
+        periods::table
+            .order(periods::start_date.desc())
+            .get_results::<Period>(conn)
+            .map_err(ServerError::QueryError)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
 
-        assert_eq!(resp.status(), StatusCode::OK);
+async fn get_period_availabilities(
+    _: Admin,
+    web::Path(id): web::Path<i64>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::query_dsl::methods::OrderDsl;
+    use diesel::ExpressionMethods;
+    use schema::{periods, users};
+
+    db(&conn, move |conn| {
+        let p = periods::table
+            .filter(periods::id.eq(id))
+            .get_result::<Period>(conn)
+            .map_err(ServerError::QueryError)?;
+        let ws = availability::load(conn, p.start_date, p.end_date.succ(), None)
+            .map_err(ServerError::QueryError)?;
+        let staff = users::table
+            .order(users::id.asc())
+            .filter(users::isadmin.eq(false))
+            .filter(users::deactivated_at.is_null())
+            .get_results::<User>(conn)
+            .map_err(ServerError::QueryError)?;
+        Ok(json!({
+            "period": p,
+            "users": staff
+                .iter()
+                .map(|s| {
+                    let mine = ws.iter().filter(|w| w.username == s.id).collect::<Vec<_>>();
+                    json!({
+                        "username": s.id,
+                        "submitted": mine.iter().any(|w| !w.weekly),
+                        "windows": mine,
+                    })
+                })
+                .collect::<Value>(),
+        }))
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn update_period(
+    _: Admin,
+    web::Path(id): web::Path<i64>,
+    pc: web::Json<PeriodChanges>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::ExpressionMethods;
+    use schema::periods;
+
+    if pc.is_empty() {
+        return Err(ServerError::BadRequest(
+            "invalid_parameter",
+            "no changes given".to_string(),
+        ));
+    }
+    db(&conn, move |conn| {
+        diesel::update(periods::table.filter(periods::id.eq(id)))
+            .set(&pc.into_inner())
+            .get_result::<Period>(conn)
+            .map_err(ServerError::QueryError)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn get_unsubmitted(
+    _: Admin,
+    web::Path(id): web::Path<i64>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::ExpressionMethods;
+    use schema::periods;
+
+    db(&conn, move |conn| {
+        let p = periods::table
+            .filter(periods::id.eq(id))
+            .get_result::<Period>(conn)
+            .map_err(ServerError::QueryError)?;
+        period::unsubmitted(conn, &p)
+            .map_err(ServerError::QueryError)
+            .map(|x| {
+                x.iter()
+                    .map(|u| {
+                        json!({
+                            "id": u.id,
+                            "first_name": u.first_name,
+                            "last_name": u.last_name,
+                        })
+                    })
+                    .collect::<Value>()
+            })
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+// how far back calendar feeds reach; older shifts stay in the apps that already synced them
+const FEED_DAYS: i64 = 90;
+
+fn shifts_calendar(conn: &PgConnection, username: &str) -> Result<String, ServerError> {
+    use diesel::ExpressionMethods;
+    use schema::schedules;
+
+    let now = Utc::now().naive_utc();
+    let ss = schedules::table
+        .filter(schedules::username.eq(username))
+        .filter(schedules::end_time.gt(now - Duration::days(FEED_DAYS)))
+        .get_results::<Schedule>(conn)
+        .map_err(ServerError::QueryError)?;
+    Ok(ical::render(&ss, &now))
+}
+
+fn calendar_response(body: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .body(body)
+}
+
+async fn get_shifts_ics(
+    user: Auth,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    db(&conn, move |conn| shifts_calendar(conn, &user.id))
+        .await
+        .map(calendar_response)
+}
+
+// polled by calendar apps, so the token in the path stands in for the bearer header
+async fn get_feed_ics(
+    web::Path(token): web::Path<String>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    db(&conn, move |conn| {
+        let user = ical::feed_owner(conn, &token)
+            .map_err(ServerError::QueryError)?
+            .ok_or_else(|| {
+                ServerError::NotFound(
+                    "feed_not_found",
+                    "calendar feed does not exist or was revoked".to_string(),
+                )
+            })?;
+        shifts_calendar(conn, &user)
+    })
+    .await
+    .map(calendar_response)
+}
+
+async fn regenerate_feed(
+    user: Auth,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    db(&conn, move |conn| {
+        ical::regenerate(conn, &user.id)
+            .map_err(ServerError::QueryError)
+            .map(|token| {
+                json!({
+                    "token": token,
+                    "path": format!("/api/feeds/{}/shifts.ics", token),
+                })
+            })
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn revoke_feed(
+    user: Auth,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    db(&conn, move |conn| {
+        ical::revoke(conn, &user.id)
+            .map_err(ServerError::QueryError)
+            .map(|_| "ok")
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+// the request's peer and X-Forwarded-For, read before the handler moves to the pool
+struct Origin {
+    peer: Option<IpAddr>,
+    forwarded_for: Option<String>,
+}
+
+impl Origin {
+    fn of(req: &HttpRequest) -> Origin {
+        Origin {
+            peer: req.peer_addr().map(|a| a.ip()),
+            forwarded_for: req
+                .headers()
+                .get("X-Forwarded-For")
+                .and_then(|x| x.to_str().ok())
+                .map(|x| x.to_string()),
+        }
+    }
+}
+
+// the client address of a punch and whether it is outside the store's networks;
+// an error when the store rejects such punches
+fn check_network(
+    conn: &PgConnection,
+    origin: &Origin,
+) -> Result<(Option<String>, bool), ServerError> {
+    let policy = network::Policy::load(conn).map_err(ServerError::QueryError)?;
+    let ip = policy.client_ip(origin.peer, origin.forwarded_for.as_deref());
+    let outside = !policy.allows(ip.as_ref());
+    if outside && policy.outside == network::Outside::Reject {
+        return Err(ServerError::Forbidden(
+            "outside_network",
+            "punches are only accepted on the store's network".to_string(),
+        ));
+    }
+    Ok((ip.map(|x| x.to_string()), outside))
+}
+
+// records a punch for an active user, checking it fits their previous one
+fn record_punch(
+    conn: &PgConnection,
+    username: &str,
+    kind: attendance::Kind,
+    kiosk_id: Option<i64>,
+    (client_ip, off_network): (Option<String>, bool),
+) -> Result<Punch, ServerError> {
+    use diesel::ExpressionMethods;
+    use schema::{punches, users};
+
+    transaction(conn, || {
+        // serializes punches of the same user
+        let n = diesel::QueryDsl::for_update(
+            users::table
+                .filter(users::id.eq(username))
+                .filter(users::deactivated_at.is_null()),
+        )
+        .execute(conn)
+        .map_err(ServerError::QueryError)?;
+        if n == 0 {
+            return Err(ServerError::Unauthorized);
+        }
+        let last = attendance::last(conn, username).map_err(ServerError::QueryError)?;
+        let last_kind = last.as_ref().and_then(|p| attendance::Kind::parse(&p.kind));
+        if !kind.may_follow(last_kind) {
+            return Err(ServerError::Conflict(
+                "invalid_punch_sequence",
+                format!("cannot {} now", kind.as_str()),
+                json!({ "last": last_kind, "kind": kind }),
+            ));
+        }
+        diesel::insert_into(punches::table)
+            .values((
+                punches::username.eq(username),
+                punches::kind.eq(kind.as_str()),
+                punches::punched_at.eq(Utc::now().naive_utc()),
+                punches::kiosk_id.eq(kiosk_id),
+                punches::client_ip.eq(client_ip),
+                punches::off_network.eq(off_network),
+            ))
+            .get_result::<Punch>(conn)
+            .map_err(ServerError::QueryError)
+    })
+}
+
+async fn add_punch(
+    user: Auth,
+    req: HttpRequest,
+    np: web::Json<NewPunch>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    let origin = Origin::of(&req);
+    db(&conn, move |conn| {
+        let network = check_network(conn, &origin)?;
+        record_punch(conn, &user.id, np.kind, None, network)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+// the PIN is checked outside the punch's transaction so failures are counted
+async fn add_kiosk_punch(
+    device: KioskDevice,
+    req: HttpRequest,
+    kp: web::Json<KioskPunch>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    let now = Utc::now().naive_utc();
+    let origin = Origin::of(&req);
+    db(&conn, move |conn| {
+        let k = &device.0;
+        // before the PIN, so PINs cannot be tried from outside
+        let network = check_network(conn, &origin)?;
+        let username = match kiosk::check_pin(conn, k, &kp.employee_number, &kp.pin, now)
+            .map_err(ServerError::QueryError)?
+        {
+            kiosk::Check::Accepted(u) => u,
+            kiosk::Check::Rejected => {
+                return Err(ServerError::Forbidden(
+                    "invalid_pin",
+                    "employee number or PIN is wrong".to_string(),
+                ))
+            }
+            kiosk::Check::Locked(t) => {
+                return Err(ServerError::TooManyRequests(
+                    "pin_locked",
+                    "too many wrong PINs, try again later".to_string(),
+                    json!({ "locked_until": t }),
+                ))
+            }
+        };
+        record_punch(conn, &username, kp.kind, Some(k.id), network)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn get_qr_code(device: KioskDevice) -> Result<HttpResponse, ServerError> {
+    qr::issue(device.0.id, &Utc::now().naive_utc())
+        .ok_or(ServerError::InternalError)
+        .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn add_qr_punch(
+    user: Auth,
+    req: HttpRequest,
+    qp: web::Json<QrPunch>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::ExpressionMethods;
+    use schema::kiosks;
+
+    let kiosk_id = qr::verify(&qp.payload, &Utc::now().naive_utc()).map_err(|e| match e {
+        qr::QrError::Invalid => {
+            ServerError::Forbidden("invalid_qr", "the code is not a store code".to_string())
+        }
+        qr::QrError::Expired => ServerError::Forbidden(
+            "qr_expired",
+            "the code has expired, scan it again".to_string(),
+        ),
+    })?;
+    let origin = Origin::of(&req);
+    db(&conn, move |conn| {
+        let network = check_network(conn, &origin)?;
+        // codes of a revoked display stop working at once
+        let n = diesel::QueryDsl::filter(kiosks::table, kiosks::id.eq(kiosk_id))
+            .filter(kiosks::revoked_at.is_null())
+            .execute(conn)
+            .map_err(ServerError::QueryError)?;
+        if n == 0 {
+            return Err(ServerError::Forbidden(
+                "invalid_qr",
+                "the code is not a store code".to_string(),
+            ));
+        }
+        record_punch(conn, &user.id, qp.kind, Some(kiosk_id), network)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn get_kiosks(
+    _: Admin,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::query_dsl::methods::OrderDsl;
+    use diesel::ExpressionMethods;
+    use schema::kiosks;
+
+    db(&conn, move |conn| {
+        kiosks::table
+            .order(kiosks::id.asc())
+            .get_results::<Kiosk>(conn)
+            .map_err(ServerError::QueryError)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn add_kiosk(
+    user: Admin,
+    nk: web::Json<NewKiosk>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    if nk.name.trim().is_empty() {
+        return Err(ServerError::BadRequest(
+            "invalid_parameter",
+            "name must not be empty".to_string(),
+        ));
+    }
+    db(&conn, move |conn| {
+        kiosk::register(conn, nk.name.trim(), &user.id)
+            .map_err(ServerError::QueryError)
+            // the only time the device token is shown
+            .map(|k| json!({ "token": k.token, "kiosk": k }))
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn revoke_kiosk(
+    _: Admin,
+    web::Path(id): web::Path<i64>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    db(&conn, move |conn| {
+        kiosk::revoke(conn, id).map_err(ServerError::QueryError)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+fn ensure_valid_pin(pin: &str) -> Result<(), ServerError> {
+    if kiosk::valid_pin(pin) {
+        Ok(())
+    } else {
+        Err(ServerError::BadRequest(
+            "invalid_parameter",
+            "pin must be 4 to 8 digits".to_string(),
+        ))
+    }
+}
+
+async fn update_my_pin(
+    user: Auth,
+    np: web::Json<NewPin>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    ensure_valid_pin(&np.pin)?;
+    db(&conn, move |conn| {
+        let password = np.password.as_deref().unwrap_or_default();
+        kintai::validate_user(conn, &user.id, password).ok_or(ServerError::UpdatePasswordError(
+            UpdatePasswordError::AuthenticationError,
+        ))?;
+        kiosk::set_pin(conn, &user.id, &np.pin)
+            .map_err(ServerError::CreateUserError)
+            .map(|_| "ok")
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn update_user_pin(
+    _: Admin,
+    web::Path(id): web::Path<String>,
+    np: web::Json<NewPin>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    ensure_valid_pin(&np.pin)?;
+    db(&conn, move |conn| {
+        get_user(conn, &id).ok_or_else(|| {
+            ServerError::NotFound("user_not_found", format!("user {} does not exist", id))
+        })?;
+        kiosk::set_pin(conn, &id, &np.pin)
+            .map_err(ServerError::CreateUserError)
+            .map(|_| "ok")
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+// the user whose data may be read: anyone for admins, otherwise only oneself
+fn target_user(user: &Auth, req: &HttpRequest) -> Result<Option<String>, ServerError> {
+    use qstring::QString;
+
+    let qs = QString::from(req.query_string());
+    match qs.get("username") {
+        Some(x) if user.isadmin || x == user.id => Ok(Some(x.to_string())),
+        Some(_) => Err(ServerError::AdminOnly),
+        None if user.isadmin => Ok(None),
+        None => Ok(Some(user.id.clone())),
+    }
+}
+
+async fn get_worktime(
+    user: Auth,
+    req: HttpRequest,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    let (start, end) = parse_range(&req)?;
+    let target = target_user(&user, &req)?;
+    db(&conn, move |conn| {
+        attendance::load(conn, start, end, target.as_deref()).map_err(ServerError::QueryError)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn get_punch_corrections(
+    user: Auth,
+    req: HttpRequest,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::query_dsl::methods::OrderDsl;
+    use diesel::ExpressionMethods;
+    use qstring::QString;
+    use schema::punch_corrections;
+
+    let (start, end) = parse_range(&req)?;
+    let target = target_user(&user, &req)?;
+    let status = match QString::from(req.query_string()).get("status") {
+        Some(x) => Some(corrections::Status::parse(x).ok_or_else(|| {
+            ServerError::BadRequest("invalid_parameter", format!("unknown status: {}", x))
+        })?),
+        None => None,
+    };
+    db(&conn, move |conn| {
+        let mut q = diesel::QueryDsl::into_boxed(punch_corrections::table)
+            .filter(punch_corrections::punched_at.ge(from_local(&start.and_hms(0, 0, 0))))
+            .filter(punch_corrections::punched_at.lt(from_local(&end.and_hms(0, 0, 0))));
+        if let Some(u) = &target {
+            q = q.filter(punch_corrections::username.eq(u.clone()));
+        }
+        if let Some(s) = status {
+            q = q.filter(punch_corrections::status.eq(s.as_str()));
+        }
+        q.order((
+            punch_corrections::punched_at.asc(),
+            punch_corrections::id.asc(),
+        ))
+        .get_results::<PunchCorrection>(conn)
+        .map_err(ServerError::QueryError)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+// approving or cancelling a correction must leave punches that could have been
+// made in that order
+fn ensure_punch_sequence(conn: &PgConnection, c: &PunchCorrection) -> Result<(), ServerError> {
+    match corrections::out_of_sequence(conn, c).map_err(ServerError::QueryError)? {
+        None => Ok(()),
+        Some((last, p)) => Err(ServerError::Unprocessable(
+            "invalid_punch_sequence",
+            format!("the corrected punches would have a {} out of order", p.kind),
+            json!({ "last": last, "kind": p.kind, "punched_at": p.punched_at }),
+        )),
+    }
+}
+
+async fn add_punch_correction(
+    user: Auth,
+    nc: web::Json<NewPunchCorrection>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::ExpressionMethods;
+    use schema::{punch_corrections, punches};
+
+    let nc = nc.into_inner();
+    let username = nc.username.clone().unwrap_or_else(|| user.id.clone());
+    if username != user.id && !user.isadmin {
+        return Err(ServerError::AdminOnly);
+    }
+    let designated = username != user.id;
+    let now = Utc::now().naive_utc();
+    if nc.punched_at > now {
+        return Err(ServerError::BadRequest(
+            "invalid_parameter",
+            "punched_at must not be in the future".to_string(),
+        ));
+    }
+    if nc.reason.trim().is_empty() {
+        return Err(ServerError::BadRequest(
+            "invalid_parameter",
+            "reason must not be empty".to_string(),
+        ));
+    }
+    if nc.punch_id.is_some() == nc.kind.is_some() {
+        return Err(ServerError::BadRequest(
+            "invalid_parameter",
+            "give either punch_id or kind".to_string(),
+        ));
+    }
+    db(&conn, move |conn| {
+        transaction(conn, || {
+            let original = match nc.punch_id {
+                Some(id) => Some(
+                    diesel::QueryDsl::for_update(
+                        punches::table
+                            .filter(punches::id.eq(id))
+                            .filter(punches::username.eq(&username)),
+                    )
+                    .get_result::<Punch>(conn)
+                    .map_err(|_| {
+                        ServerError::NotFound(
+                            "punch_not_found",
+                            format!("punch {} does not exist", id),
+                        )
+                    })?,
+                ),
+                None => None,
+            };
+            let mut times = vec![&nc.punched_at];
+            times.extend(original.as_ref().map(|p| &p.punched_at));
+            ensure_attendance_open(conn, &times)?;
+            let status = if designated {
+                corrections::Status::Approved
+            } else {
+                corrections::Status::Requested
+            };
+            let c = diesel::insert_into(punch_corrections::table)
+                .values((
+                    punch_corrections::username.eq(&username),
+                    punch_corrections::punch_id.eq(nc.punch_id),
+                    punch_corrections::kind.eq(nc.kind.map(|k| k.as_str())),
+                    punch_corrections::punched_at.eq(nc.punched_at),
+                    punch_corrections::reason.eq(nc.reason.trim()),
+                    punch_corrections::status.eq(status.as_str()),
+                    punch_corrections::decided_by.eq(Some(&user.id).filter(|_| designated)),
+                    punch_corrections::decided_at.eq(Some(now).filter(|_| designated)),
+                ))
+                .get_result::<PunchCorrection>(conn)
+                .map_err(ServerError::QueryError)?;
+            if designated {
+                ensure_punch_sequence(conn, &c)?;
+            }
+            Ok(c)
+        })
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn update_punch_correction(
+    user: Auth,
+    web::Path(id): web::Path<i64>,
+    cd: web::Json<CorrectionDecision>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::ExpressionMethods;
+    use schema::{punch_corrections, punches};
+
+    db(&conn, move |conn| {
+        transaction(conn, || {
+            let c = diesel::QueryDsl::for_update(
+                punch_corrections::table.filter(punch_corrections::id.eq(id)),
+            )
+            .get_result::<PunchCorrection>(conn)
+            .map_err(ServerError::QueryError)?;
+            if !user.isadmin && c.username != user.id {
+                return Err(ServerError::NotFound(
+                    "punch_correction_not_found",
+                    format!("punch correction {} does not exist", id),
+                ));
+            }
+            let to =
+                corrections::transition(&c, &user.actor(), cd.status).map_err(|e| match e {
+                    TransitionError::NotPermitted => ServerError::Forbidden(
+                        "action_not_permitted",
+                        format!("you may not make this correction {}", cd.status.as_str()),
+                    ),
+                    TransitionError::InvalidState => ServerError::Conflict(
+                        "invalid_correction_state",
+                        "punch correction cannot be changed in its current state".to_string(),
+                        json!({ "status": c.status, "to": cd.status }),
+                    ),
+                })?;
+            // approving or taking back an approval changes the attendance record
+            if c.status == corrections::Status::Approved.as_str()
+                || to == corrections::Status::Approved
+            {
+                let original = match c.punch_id {
+                    Some(p) => Some(
+                        diesel::QueryDsl::select(
+                            punches::table.filter(punches::id.eq(p)),
+                            punches::punched_at,
+                        )
+                        .get_result::<NaiveDateTime>(conn)
+                        .map_err(ServerError::QueryError)?,
+                    ),
+                    None => None,
+                };
+                let mut times = vec![&c.punched_at];
+                times.extend(original.as_ref());
+                ensure_attendance_open(conn, &times)?;
+            }
+            let ret = diesel::update(punch_corrections::table.filter(punch_corrections::id.eq(id)))
+                .set((
+                    punch_corrections::status.eq(to.as_str()),
+                    punch_corrections::decided_by.eq(&user.id),
+                    punch_corrections::decided_at.eq(Utc::now().naive_utc()),
+                ))
+                .get_result::<PunchCorrection>(conn)
+                .map_err(ServerError::QueryError)?;
+            if c.status == corrections::Status::Approved.as_str()
+                || to == corrections::Status::Approved
+            {
+                ensure_punch_sequence(conn, &ret)?;
+            }
+            Ok(ret)
+        })
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+const EXPORT_CHUNK: i64 = 500;
+
+fn export_encoding(req: &HttpRequest) -> Result<export::Encoding, ServerError> {
+    use qstring::QString;
+
+    let qs = QString::from(req.query_string());
+    qs.get("encoding")
+        .map(|x| {
+            export::Encoding::parse(x).ok_or_else(|| {
+                ServerError::BadRequest(
+                    "invalid_parameter",
+                    "encoding must be utf-8 or shift_jis".to_string(),
+                )
+            })
+        })
+        .unwrap_or(Ok(export::Encoding::Utf8))
+}
+
+fn export_names(conn: &PgConnection) -> Result<HashMap<String, String>, ServerError> {
+    use schema::users;
+
+    users::table
+        .get_results::<User>(conn)
+        .map_err(ServerError::QueryError)
+        .map(|us| us.iter().map(|u| (u.id.clone(), export::name(u))).collect())
+}
+
+/// How many of the exported users have an id or name that `enc` cannot
+/// represent, so their rows carry `export::REPLACEMENT`.
+fn export_replaced(
+    enc: export::Encoding,
+    names: &HashMap<String, String>,
+    target: Option<&str>,
+) -> usize {
+    names
+        .iter()
+        .filter(|(id, _)| target.iter().all(|t| t == id))
+        .filter(|(id, name)| !enc.represents(id) || !enc.represents(name))
+        .count()
+}
+
+/// Streams a CSV: the header first, then whatever `next` returns until it returns
+/// None. Each call gets a pooled connection of its own, so nothing is held between
+/// chunks and a year of data never sits in memory at once. `replaced` users with
+/// unrepresentable characters are reported in X-Export-Replaced-Users, since the
+/// body has started by the time their rows are written.
+fn csv_response<F>(
+    pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    enc: export::Encoding,
+    replaced: usize,
+    filename: &str,
+    header: &'static [&'static str],
+    next: F,
+) -> Result<HttpResponse, ServerError>
+where
+    F: FnMut(&PgConnection) -> Result<Option<Vec<Vec<String>>>, ServerError> + Send + 'static,
+{
+    let mut head = enc.preamble().to_vec();
+    head.extend(
+        export::chunk(enc, std::iter::once(header)).map_err(|_| ServerError::InternalError)?,
+    );
+    let rest = futures::stream::unfold(Some((pool, next)), move |state| async move {
+        let (pool, mut next) = state?;
+        // `next` goes to the blocking pool with each chunk and comes back with the rows
+        let step = db(&pool, move |conn| {
+            let rows = next(conn);
+            Ok((rows, next))
+        })
+        .await;
+        let (rows, next) = match step {
+            Ok(x) => x,
+            Err(e) => return Some((Err(e), None)),
+        };
+        match rows {
+            Ok(Some(rows)) => Some((
+                export::chunk(enc, rows)
+                    .map(web::Bytes::from)
+                    .map_err(|_| ServerError::InternalError),
+                Some((pool, next)),
+            )),
+            Ok(None) => None,
+            Err(e) => Some((Err(e), None)),
+        }
+    });
+    let body = futures::stream::once(async move { Ok(web::Bytes::from(head)) }).chain(rest);
+    let body = Box::pin(body);
+    let mut resp = HttpResponse::Ok();
+    resp.content_type(enc.content_type()).header(
+        "Content-Disposition",
+        format!("attachment; filename=\"{}\"", filename),
+    );
+    if replaced > 0 {
+        resp.header("X-Export-Replaced-Users", replaced.to_string());
+    }
+    Ok(resp.streaming(body))
+}
+
+async fn export_schedules(
+    user: Auth,
+    req: HttpRequest,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::query_dsl::methods::{LimitDsl, OrderDsl};
+    use diesel::{BoolExpressionMethods, ExpressionMethods};
+    use schema::schedules;
+
+    let (start, end) = parse_range(&req)?;
+    let enc = export_encoding(&req)?;
+    let target = target_user(&user, &req)?;
+    let names = db(&conn, export_names).await?;
+    let replaced = export_replaced(enc, &names, target.as_deref());
+    let (start, end) = (
+        from_local(&start.and_hms(0, 0, 0)),
+        from_local(&end.and_hms(0, 0, 0)),
+    );
+    let mut cursor: Option<(NaiveDateTime, i64)> = None;
+    let mut done = false;
+    csv_response(
+        conn.get_ref().clone(),
+        enc,
+        replaced,
+        "schedules.csv",
+        &export::SCHEDULE_HEADER,
+        move |conn| {
+            if done {
+                return Ok(None);
+            }
+            let mut q = diesel::QueryDsl::into_boxed(schedules::table)
+                .filter(schedules::start_time.ge(start))
+                .filter(schedules::start_time.lt(end));
+            if let Some(u) = &target {
+                q = q.filter(schedules::username.eq(u.clone()));
+            }
+            if let Some((t, id)) = cursor {
+                q = q.filter(
+                    schedules::start_time
+                        .gt(t)
+                        .or(schedules::start_time.eq(t).and(schedules::id.gt(id))),
+                );
+            }
+            let ss = q
+                .order((schedules::start_time.asc(), schedules::id.asc()))
+                .limit(EXPORT_CHUNK)
+                .get_results::<Schedule>(conn)
+                .map_err(ServerError::QueryError)?;
+            done = (ss.len() as i64) < EXPORT_CHUNK;
+            cursor = ss.last().map(|s| (s.start_time, s.id));
+            if ss.is_empty() {
+                return Ok(None);
+            }
+            Ok(Some(
+                ss.iter()
+                    .map(|s| {
+                        let name = names.get(&s.username).map(|x| x.as_str());
+                        export::schedule_record(s, name.unwrap_or(""))
+                    })
+                    .collect(),
+            ))
+        },
+    )
+}
+
+async fn export_worktime(
+    user: Auth,
+    req: HttpRequest,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    let (start, end) = parse_range(&req)?;
+    let enc = export_encoding(&req)?;
+    let target = target_user(&user, &req)?;
+    let names = db(&conn, export_names).await?;
+    let replaced = export_replaced(enc, &names, target.as_deref());
+    // one user per chunk; punches of a single user over a year are small
+    let mut users: Vec<String> = match target {
+        Some(u) => vec![u],
+        None => names.keys().cloned().collect(),
+    };
+    users.sort();
+    users.reverse();
+    csv_response(
+        conn.get_ref().clone(),
+        enc,
+        replaced,
+        "worktime.csv",
+        &export::WORKTIME_HEADER,
+        move |conn| {
+            while let Some(u) = users.pop() {
+                let ds = attendance::load(conn, start, end, Some(&u))
+                    .map_err(ServerError::QueryError)?;
+                if !ds.is_empty() {
+                    let name = names.get(&u).map(|x| x.as_str()).unwrap_or("");
+                    return Ok(Some(
+                        ds.iter()
+                            .map(|d| export::worktime_record(d, name))
+                            .collect(),
+                    ));
+                }
+            }
+            Ok(None)
+        },
+    )
+}
+
+fn local_today() -> NaiveDate {
+    to_local(&Utc::now().naive_utc()).date()
+}
+
+async fn get_leave_balances(
+    user: Auth,
+    req: HttpRequest,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    let target = target_user(&user, &req)?;
+    let today = local_today();
+    db(&conn, move |conn| {
+        leave::balances(conn, target.as_deref(), today).map_err(ServerError::QueryError)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn get_leave_requests(
+    user: Auth,
+    req: HttpRequest,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::query_dsl::methods::OrderDsl;
+    use diesel::ExpressionMethods;
+    use qstring::QString;
+    use schema::leave_requests;
+
+    let (start, end) = parse_range(&req)?;
+    let target = target_user(&user, &req)?;
+    let status = match QString::from(req.query_string()).get("status") {
+        Some(x) => Some(leave::Status::parse(x).ok_or_else(|| {
+            ServerError::BadRequest("invalid_parameter", format!("unknown status: {}", x))
+        })?),
+        None => None,
+    };
+    db(&conn, move |conn| {
+        let mut q = diesel::QueryDsl::into_boxed(leave_requests::table)
+            .filter(leave_requests::leave_date.ge(start))
+            .filter(leave_requests::leave_date.lt(end));
+        if let Some(u) = &target {
+            q = q.filter(leave_requests::username.eq(u.clone()));
+        }
+        if let Some(s) = status {
+            q = q.filter(leave_requests::status.eq(s.as_str()));
+        }
+        q.order((leave_requests::leave_date.asc(), leave_requests::id.asc()))
+            .get_results::<LeaveRequest>(conn)
+            .map_err(ServerError::QueryError)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn add_leave_request(
+    user: Auth,
+    nl: web::Json<NewLeaveRequest>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::ExpressionMethods;
+    use schema::{leave_requests, users};
+
+    let nl = nl.into_inner();
+    let username = nl.username.clone().unwrap_or_else(|| user.id.clone());
+    if username != user.id && !user.isadmin {
+        return Err(ServerError::AdminOnly);
+    }
+    if nl.minutes.is_some() && !user.isadmin {
+        return Err(ServerError::AdminOnly);
+    }
+    let designated = username != user.id;
+    let today = local_today();
+    db(&conn, move |conn| {
+        transaction(conn, || {
+            // serializes requests against the same balance
+            let n = diesel::QueryDsl::for_update(
+                users::table
+                    .filter(users::id.eq(&username))
+                    .filter(users::deactivated_at.is_null()),
+            )
+            .execute(conn)
+            .map_err(ServerError::QueryError)?;
+            if n == 0 {
+                return Err(ServerError::NotFound(
+                    "user_not_found",
+                    format!("user {} does not exist", username),
+                ));
+            }
+            leave::accrue(conn, Some(&username), today).map_err(ServerError::QueryError)?;
+            let minutes = match nl.minutes {
+                Some(m) => m,
+                None => leave::default_minutes(conn, &username, nl.date)
+                    .map_err(ServerError::QueryError)?
+                    .ok_or_else(|| {
+                        ServerError::BadRequest(
+                            "invalid_parameter",
+                            "minutes is required when no shift is planned".to_string(),
+                        )
+                    })?,
+            };
+            let status = if designated {
+                leave::Status::Approved
+            } else {
+                leave::Status::Requested
+            };
+            let r = diesel::insert_into(leave_requests::table)
+                .values((
+                    leave_requests::username.eq(&username),
+                    leave_requests::leave_date.eq(nl.date),
+                    leave_requests::status.eq(status.as_str()),
+                    leave_requests::minutes.eq(minutes),
+                    leave_requests::note.eq(&nl.note),
+                    leave_requests::decided_by.eq(Some(&user.id).filter(|_| designated)),
+                    leave_requests::decided_at
+                        .eq(Some(Utc::now().naive_utc()).filter(|_| designated)),
+                ))
+                .get_result::<LeaveRequest>(conn)
+                .map_err(ServerError::QueryError)?;
+            let b = leave::balances(conn, Some(&username), today)
+                .map_err(ServerError::QueryError)?
+                .pop()
+                .ok_or(ServerError::InternalError)?;
+            if b.uncovered.contains(&r.leave_date) {
+                return Err(ServerError::Unprocessable(
+                    "insufficient_leave",
+                    format!("no paid leave left for {}", r.leave_date),
+                    json!({ "remaining": b.remaining }),
+                ));
+            }
+            Ok(r)
+        })
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn update_leave_request(
+    user: Auth,
+    web::Path(id): web::Path<i64>,
+    ld: web::Json<LeaveDecision>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::ExpressionMethods;
+    use schema::leave_requests;
+
+    if ld.minutes.is_some() && !user.isadmin {
+        return Err(ServerError::AdminOnly);
+    }
+    let today = local_today();
+    db(&conn, move |conn| {
+        transaction(conn, || {
+            let r = diesel::QueryDsl::for_update(
+                leave_requests::table.filter(leave_requests::id.eq(id)),
+            )
+            .get_result::<LeaveRequest>(conn)
+            .map_err(ServerError::QueryError)?;
+            if !user.isadmin && r.username != user.id {
+                return Err(ServerError::NotFound(
+                    "leave_request_not_found",
+                    format!("leave request {} does not exist", id),
+                ));
+            }
+            let to =
+                leave::transition(&r, &user.actor(), ld.status, today).map_err(|e| match e {
+                    TransitionError::NotPermitted => ServerError::Forbidden(
+                        "action_not_permitted",
+                        format!("you may not make this request {}", ld.status.as_str()),
+                    ),
+                    TransitionError::InvalidState => ServerError::Conflict(
+                        "invalid_leave_state",
+                        "leave request cannot be changed in its current state".to_string(),
+                        json!({ "status": r.status, "to": ld.status }),
+                    ),
+                })?;
+            diesel::update(leave_requests::table.filter(leave_requests::id.eq(id)))
+                .set((
+                    leave_requests::status.eq(to.as_str()),
+                    leave_requests::minutes.eq(ld.minutes.unwrap_or(r.minutes)),
+                    leave_requests::decided_by.eq(&user.id),
+                    leave_requests::decided_at.eq(Utc::now().naive_utc()),
+                ))
+                .get_result::<LeaveRequest>(conn)
+                .map_err(ServerError::QueryError)
+        })
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn get_leave_obligations(
+    _: Admin,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    let today = local_today();
+    db(&conn, move |conn| {
+        leave::obligations(conn, today).map_err(ServerError::QueryError)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn run_leave_accrual(
+    _: Admin,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    let today = local_today();
+    db(&conn, move |conn| {
+        leave::accrue(conn, None, today).map_err(ServerError::QueryError)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn get_payroll(
+    user: Auth,
+    req: HttpRequest,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    let (start, end) = parse_range(&req)?;
+    let target = target_user(&user, &req)?;
+    db(&conn, move |conn| {
+        payroll::calculate(conn, start, end, target.as_deref()).map_err(ServerError::QueryError)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn get_calendar(
+    _: Auth,
+    req: HttpRequest,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    let (start, end) = parse_range(&req)?;
+    if (end - start).num_days() > 366 * 5 {
+        return Err(ServerError::BadRequest(
+            "invalid_parameter",
+            "the range may span at most five years".to_string(),
+        ));
+    }
+    db(&conn, move |conn| {
+        calendar::load(conn, start, end).map_err(ServerError::QueryError)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn put_store_day(
+    user: Admin,
+    web::Path(day): web::Path<NaiveDate>,
+    nd: web::Json<NewStoreDay>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::ExpressionMethods;
+    use schema::store_days;
+
+    if nd.kind == calendar::Kind::NationalHoliday {
+        return Err(ServerError::BadRequest(
+            "invalid_body",
+            "national holidays are computed; use closed or special".to_string(),
+        ));
+    }
+    let d = StoreDay {
+        day,
+        kind: nd.kind.as_str().to_string(),
+        name: nd.name.clone(),
+        created_by: user.id.clone(),
+    };
+    db(&conn, move |conn| {
+        diesel::insert_into(store_days::table)
+            .values(&d)
+            .on_conflict(store_days::day)
+            .do_update()
+            .set((
+                store_days::kind.eq(&d.kind),
+                store_days::name.eq(&d.name),
+                store_days::created_by.eq(&d.created_by),
+            ))
+            .get_result::<StoreDay>(conn)
+            .map_err(ServerError::QueryError)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn delete_store_day(
+    _: Admin,
+    web::Path(day): web::Path<NaiveDate>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use diesel::ExpressionMethods;
+    use schema::store_days;
+
+    db(&conn, move |conn| {
+        let n = diesel::delete(store_days::table.filter(store_days::day.eq(day)))
+            .execute(conn)
+            .map_err(ServerError::QueryError)?;
+        if n == 0 {
+            return Err(ServerError::QueryError(diesel::result::Error::NotFound));
+        }
+        Ok("ok")
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn get_exceptions(
+    _: Admin,
+    req: HttpRequest,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    use qstring::QString;
+
+    let qs = QString::from(req.query_string());
+    let date = match qs.get("date") {
+        Some(x) => NaiveDate::parse_from_str(x, "%Y-%m-%d").map_err(|x| {
+            ServerError::BadRequest("invalid_parameter", format!("cannot parse date: {}", x))
+        })?,
+        None => local_today(),
+    };
+    let unresolved = qs.get("unresolved") == Some("true");
+    db(&conn, move |conn| {
+        exceptions::detect(conn, date, Utc::now().naive_utc())
+            .map_err(ServerError::QueryError)
+            .map(|es| {
+                es.into_iter()
+                    .filter(|e| !unresolved || e.resolution.is_none())
+                    .collect::<Vec<_>>()
+            })
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn resolve_exception(
+    user: Admin,
+    nr: web::Json<NewResolution>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    if nr.note.trim().is_empty() {
+        return Err(ServerError::BadRequest(
+            "invalid_body",
+            "a note is required".to_string(),
+        ));
+    }
+    db(&conn, move |conn| {
+        let found = exceptions::detect(conn, nr.date, Utc::now().naive_utc())
+            .map_err(ServerError::QueryError)?
+            .into_iter()
+            .any(|e| e.username == nr.username && e.kind == nr.kind);
+        if !found {
+            return Err(ServerError::NotFound(
+                "exception_not_found",
+                format!(
+                    "no {} exception for {} on {}",
+                    nr.kind.as_str(),
+                    nr.username,
+                    nr.date
+                ),
+            ));
+        }
+        exceptions::resolve(
+            conn,
+            &nr.username,
+            nr.date,
+            nr.kind,
+            nr.note.trim(),
+            &user.id,
+        )
+        .map_err(ServerError::QueryError)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn get_settings(
+    _: Admin,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    db(&conn, move |conn| {
+        settings::all(conn).map_err(ServerError::QueryError)
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+async fn put_setting(
+    _: Admin,
+    web::Path(key): web::Path<String>,
+    sv: web::Json<SettingValue>,
+    conn: web::Data<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ServerError> {
+    settings::validate(&key, &sv.value)
+        .map_err(|x| ServerError::BadRequest("invalid_setting", format!("{}: {}", key, x)))?;
+    db(&conn, move |conn| {
+        settings::set(conn, &key, &sv.value)
+            .map_err(ServerError::QueryError)
+            .map(|_| json!({ "key": key, "value": sv.value }))
+    })
+    .await
+    .map(|x| HttpResponse::Ok().json(x))
+}
+
+fn config(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::JsonConfig::default().error_handler(|x, _| {
+        error::Error::from(ServerError::BadRequest("invalid_body", x.to_string()))
+    }))
+    .app_data(web::PathConfig::default().error_handler(|x, _| {
+        error::Error::from(ServerError::NotFound("not_found", x.to_string()))
+    }))
+    .app_data(web::QueryConfig::default().error_handler(|x, _| {
+        error::Error::from(ServerError::BadRequest("invalid_parameter", x.to_string()))
+    }))
+    .service(web::resource("/"))
+    .route("/api/login", web::post().to(login_api))
+    .route(
+        "/api/schedules/generation",
+        web::post().to(generate_schedules),
+    )
+    .route("/api/schedules/import", web::post().to(import_schedules))
+    .service(
+        web::resource("/api/schedules/{id}")
+            .route(web::get().to(get_schedule))
+            .route(web::delete().to(delete_schedule)),
+    )
+    .service(web::resource("/api/schedules/{id}/duration").route(web::patch().to(update_schedule)))
+    .service(
+        web::resource("/api/schedules/{id}/permission").route(web::patch().to(permit_schedule)),
+    )
+    .service(web::resource("/api/schedules/{id}/absence").route(web::patch().to(absent_schedule)))
+    .service(
+        web::resource("/api/schedules/{id}/availability").route(web::patch().to(disable_schedule)),
+    )
+    .service(
+        web::resource("/api/schedules/{id}/transitions").route(web::post().to(transition_schedule)),
+    )
+    .service(
+        web::resource("/api/schedules/{id}/actions").route(web::get().to(get_schedule_actions)),
+    )
+    .service(web::resource("/api/availabilities/{id}").route(web::delete().to(delete_availability)))
+    .route("/api/availabilities", web::post().to(add_availability))
+    .route("/api/availabilities", web::get().to(get_availabilities))
+    .service(
+        web::resource("/api/periods/{id}/availabilities")
+            .route(web::get().to(get_period_availabilities)),
+    )
+    .service(web::resource("/api/periods/{id}/unsubmitted").route(web::get().to(get_unsubmitted)))
+    .service(web::resource("/api/periods/{id}").route(web::patch().to(update_period)))
+    .route("/api/periods", web::post().to(add_period))
+    .route("/api/periods", web::get().to(get_periods))
+    .route("/api/schedules", web::post().to(add_schedule))
+    .route("/api/schedules", web::get().to(get_schedules))
+    .route("/api/settings", web::get().to(get_settings))
+    .route("/api/leave/balances", web::get().to(get_leave_balances))
+    .route(
+        "/api/leave/obligations",
+        web::get().to(get_leave_obligations),
+    )
+    .route("/api/leave/accrual", web::post().to(run_leave_accrual))
+    .service(web::resource("/api/leave/requests/{id}").route(web::patch().to(update_leave_request)))
+    .route("/api/leave/requests", web::post().to(add_leave_request))
+    .route("/api/leave/requests", web::get().to(get_leave_requests))
+    .route("/api/payroll", web::get().to(get_payroll))
+    .route("/api/calendar", web::get().to(get_calendar))
+    .route("/api/exceptions", web::get().to(get_exceptions))
+    .route(
+        "/api/exceptions/resolutions",
+        web::post().to(resolve_exception),
+    )
+    .service(
+        web::resource("/api/calendar/{date}")
+            .route(web::put().to(put_store_day))
+            .route(web::delete().to(delete_store_day)),
+    )
+    .route("/api/users/me/punches", web::post().to(add_punch))
+    .route("/api/kiosk/punches", web::post().to(add_kiosk_punch))
+    .route("/api/kiosk/qr", web::get().to(get_qr_code))
+    .route("/api/users/me/punches/qr", web::post().to(add_qr_punch))
+    .service(web::resource("/api/kiosks/{id}").route(web::delete().to(revoke_kiosk)))
+    .route("/api/kiosks", web::post().to(add_kiosk))
+    .route("/api/kiosks", web::get().to(get_kiosks))
+    .service(
+        web::resource("/api/punch-corrections/{id}")
+            .route(web::patch().to(update_punch_correction)),
+    )
+    .route(
+        "/api/punch-corrections",
+        web::post().to(add_punch_correction),
+    )
+    .route(
+        "/api/punch-corrections",
+        web::get().to(get_punch_corrections),
+    )
+    .route("/api/worktime", web::get().to(get_worktime))
+    .route(
+        "/api/exports/schedules.csv",
+        web::get().to(export_schedules),
+    )
+    .route("/api/exports/worktime.csv", web::get().to(export_worktime))
+    .service(web::resource("/api/settings/{key}").route(web::put().to(put_setting)))
+    .route("/api/users/import", web::post().to(import_users))
+    .route("/api/users/me/pin", web::put().to(update_my_pin))
+    .service(web::resource("/api/users/{id}/pin").route(web::put().to(update_user_pin)))
+    .service(web::resource("/api/users/{id}").route(web::delete().to(delete_user)))
+    .route("/api/users/me/password", web::patch().to(update_password))
+    .route("/api/users/me/shifts.ics", web::get().to(get_shifts_ics))
+    .service(
+        web::resource("/api/users/me/calendar-feed")
+            .route(web::post().to(regenerate_feed))
+            .route(web::delete().to(revoke_feed)),
+    )
+    .service(web::resource("/api/feeds/{token}/shifts.ics").route(web::get().to(get_feed_ics)))
+    .route(
+        "/api/users/me/weekly-availabilities",
+        web::get().to(get_weekly_availabilities),
+    )
+    .route(
+        "/api/users/me/weekly-availabilities",
+        web::put().to(put_weekly_availabilities),
+    )
+    .route("/api/users", web::post().to(add_user))
+    .route("/api/users", web::get().to(get_users));
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "actix_web=info");
+    let port = env::var("PORT")
+        .unwrap_or_else(|_| "8080".to_string())
+        .parse()
+        .expect("PORT must be a number");
+    if let Some(e) = qr::misconfigured() {
+        panic!("{}", e);
+    }
+    let pool = establish_connection().unwrap();
+    let pg = create_pg();
+    HttpServer::new(move || {
+        App::new()
+            .route("/", web::get().to(index))
+            .configure(config)
+            .service(
+                Files::new("/asset-manifest.json", "./build/asset-manifest.json").prefer_utf8(true),
+            )
+            .service(Files::new("/favicon.ico", "./build/favicon.ico").prefer_utf8(true))
+            .service(Files::new("/index.html", "./build/index.html").prefer_utf8(true))
+            .service(Files::new("/logo192.png", "./build/logo192.png").prefer_utf8(true))
+            .service(Files::new("/logo512.png", "./build/logo512.png").prefer_utf8(true))
+            .service(Files::new("/manifest.json", "./build/manifest.json").prefer_utf8(true))
+            .service(Files::new("/robots.txt", "./build/robots.txt").prefer_utf8(true))
+            .service(Files::new("/static", "./build/static").prefer_utf8(true))
+            .default_service(web::route().to(index))
+            .data(pool.clone())
+            .data(pg.clone())
+    })
+    .bind(("0.0.0.0", port))?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod test_support;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{
+        http::{header, StatusCode},
+        test::{self, read_body_json},
+        App,
+    };
+    use chrono::Duration;
+    use serde_json::{json, Value};
+    use test_support::TestDb;
+    use uuid::Uuid;
+
+    #[actix_rt::test]
+    async fn test_absent() {
+        let db = TestDb::new();
+        let pool = db.pool.clone();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(r#"{"id":"root", "pass":"pass"}"#.as_bytes())
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_root = ts["token"].as_str().unwrap();
+        let user_id = Uuid::new_v4();
+        let resp = test::TestRequest::post()
+            .uri("/api/users")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({"id": user_id, "isadmin": false }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ps: Value = read_body_json(resp).await;
+        let test_pass = ps["pass"].as_str().unwrap();
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_json(&json!({"id": user_id, "pass": test_pass }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_test = ts["token"].as_str().unwrap();
+        let today = Local::now().naive_local();
+        let resp = test::TestRequest::post()
+            .uri("/api/schedules")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .set_json(&StartEndWithUser {
+                username: user_id.to_string(),
+                start_time: today,
+                end_time: today.checked_add_signed(Duration::days(1)).unwrap(),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ps: Value = read_body_json(resp).await;
+        let sid = ps["id"].as_i64().unwrap();
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/permission", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/absence", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::get()
+            .uri("/api/schedules")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ss: Vec<Schedule> = read_body_json(resp).await;
+        let s = ss.iter().find(|x| x.id == sid).unwrap();
+
+        assert_eq!(s.status, Status::AbsenceRequested);
+
+        let today = Local::now().naive_local();
+        let resp = test::TestRequest::post()
+            .uri("/api/schedules")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&StartEndWithUser {
+                username: "root".to_string(),
+                start_time: today,
+                end_time: today.checked_add_signed(Duration::days(1)).unwrap(),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ps: Value = read_body_json(resp).await;
+        let sid = ps["id"].as_i64().unwrap();
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/permission", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/absence", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::get()
+            .uri("/api/schedules")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ss: Vec<Schedule> = read_body_json(resp).await;
+        let s = ss.iter().find(|x| x.id == sid).unwrap();
+
+        assert_eq!(s.status, Status::AbsenceRequested);
+    }
+
+    #[actix_rt::test]
+    async fn test_normal_system() {
+        let db = TestDb::new();
+        let pool = db.pool.clone();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(r#"{"id":"root", "pass":"pass"}"#.as_bytes())
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_root = ts["token"].as_str().unwrap();
+        let user_id = Uuid::new_v4();
+        let resp = test::TestRequest::post()
+            .uri("/api/users")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({"id": user_id, "isadmin": false }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ps: Value = read_body_json(resp).await;
+        let test_pass = ps["pass"].as_str().unwrap();
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_json(&json!({"id": user_id, "pass": test_pass }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_test = ts["token"].as_str().unwrap();
+        let today = Local::now().naive_local();
+        let resp = test::TestRequest::post()
+            .uri("/api/schedules")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .set_json(&StartEndWithUser {
+                username: user_id.to_string(),
+                start_time: today,
+                end_time: today.checked_add_signed(Duration::days(1)).unwrap(),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ps: Value = read_body_json(resp).await;
+        let sid = ps["id"].as_i64().unwrap();
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/duration", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&StartEnd {
+                start_time: today,
+                end_time: today.checked_add_signed(Duration::days(2)).unwrap(),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_ne!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/duration", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .set_json(&StartEnd {
+                start_time: today,
+                end_time: today.checked_add_signed(Duration::days(2)).unwrap(),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/absence", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_ne!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/absence", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_ne!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/permission", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_ne!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/permission", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/duration", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .set_json(&StartEnd {
+                start_time: today,
+                end_time: today.checked_add_signed(Duration::days(2)).unwrap(),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_ne!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/duration", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&StartEnd {
+                start_time: today,
+                end_time: today.checked_add_signed(Duration::days(2)).unwrap(),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_ne!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/absence", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_ne!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/absence", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/availability", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_ne!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/availability", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::post()
+            .uri("/api/schedules")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&StartEndWithUser {
+                username: user_id.to_string(),
+                start_time: today,
+                end_time: today.checked_add_signed(Duration::days(1)).unwrap(),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ps: Value = read_body_json(resp).await;
+        let sid = ps["id"].as_i64().unwrap();
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/duration", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .set_json(&StartEnd {
+                start_time: today,
+                end_time: today.checked_add_signed(Duration::days(2)).unwrap(),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_ne!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/duration", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&StartEnd {
+                start_time: today,
+                end_time: today.checked_add_signed(Duration::days(2)).unwrap(),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/absence", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_ne!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/absence", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_ne!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/permission", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_ne!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/permission", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/duration", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .set_json(&StartEnd {
+                start_time: today,
+                end_time: today.checked_add_signed(Duration::days(2)).unwrap(),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_ne!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/duration", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&StartEnd {
+                start_time: today,
+                end_time: today.checked_add_signed(Duration::days(2)).unwrap(),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_ne!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/absence", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_ne!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/absence", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/availability", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_ne!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/availability", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn test_delete_user() {
+        let db = TestDb::new();
+        let pool = db.pool.clone();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(r#"{"id":"root", "pass":"pass"}"#.as_bytes())
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_root = ts["token"].as_str().unwrap();
+        let user_id = Uuid::new_v4();
+        let resp = test::TestRequest::post()
+            .uri("/api/users")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({"id": user_id, "isadmin": false }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ps: Value = read_body_json(resp).await;
+        let test_pass = ps["pass"].as_str().unwrap();
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_json(&json!({"id": user_id, "pass": test_pass }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_test = ts["token"].as_str().unwrap();
+        let today = Local::now().naive_local();
+        let resp = test::TestRequest::post()
+            .uri("/api/schedules")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .set_json(&StartEndWithUser {
+                username: user_id.to_string(),
+                start_time: today,
+                end_time: today.checked_add_signed(Duration::days(1)).unwrap(),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::delete()
+            .uri(&format!("/api/users/{}", user_id))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = test::TestRequest::delete()
+            .uri(&format!("/api/users/{}", user_id))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn test_generate_schedules() {
+        let db = TestDb::new();
+        let pool = db.pool.clone();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(r#"{"id":"root", "pass":"pass"}"#.as_bytes())
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_root = ts["token"].as_str().unwrap();
+        let mut users = vec![];
+        for _ in 0..2 {
+            let user_id = Uuid::new_v4().to_string();
+            let resp = test::TestRequest::post()
+                .uri("/api/users")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Authorization", format!("bearer {}", token_root))
+                .set_json(&json!({"id": user_id, "isadmin": false }))
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), StatusCode::OK);
+            users.push(user_id);
+        }
+
+        let day = NaiveDate::from_ymd(2030, 1, 6);
+        let at = |d: i64, h: u32| (day + Duration::days(d)).and_hms(h, 0, 0);
+        let body = json!({
+            "week_start": day,
+            "seed": 7,
+            "dry_run": true,
+            "requirements": [
+                {"start_time": at(0, 9), "end_time": at(0, 17), "headcount": 3},
+                {"start_time": at(1, 9), "end_time": at(1, 13), "headcount": 1},
+            ],
+            "availability": users.iter().map(|u| json!({
+                "username": u, "start_time": at(0, 0), "end_time": at(7, 0)
+            })).collect::<Vec<_>>(),
+        });
+        let resp = test::TestRequest::post()
+            .uri("/api/schedules/generation")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&body)
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let first: Value = read_body_json(resp).await;
+        assert_eq!(first["assignments"].as_array().unwrap().len(), 3);
+        assert_eq!(first["unfilled"][0]["missing"], 1);
+        assert_eq!(first["schedules"].as_array().unwrap().len(), 0);
+
+        let mut body = body;
+        body["dry_run"] = json!(false);
+        let resp = test::TestRequest::post()
+            .uri("/api/schedules/generation")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&body)
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let second: Value = read_body_json(resp).await;
+        assert_eq!(first["assignments"], second["assignments"]);
+        assert_eq!(second["schedules"].as_array().unwrap().len(), 3);
+
+        // 07:00-12:00 and 13:00-18:00 local on the first day of the next week
+        // fall on two UTC dates but still add up to more than 8 hours
+        let next = day + Duration::days(7);
+        let local = |h: u32| from_local(&next.and_hms(h, 0, 0));
+        let resp = test::TestRequest::post()
+            .uri("/api/schedules/generation")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({
+                "week_start": next,
+                    "seed": 7,
+                "dry_run": true,
+                "requirements": [
+                    {"start_time": local(7), "end_time": local(12), "headcount": 1},
+                    {"start_time": local(13), "end_time": local(18), "headcount": 1},
+                ],
+                "availability": [{
+                    "username": users[0],
+                    "start_time": local(0),
+                    "end_time": from_local(&(next + Duration::days(1)).and_hms(0, 0, 0)),
+                }],
+            }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let third: Value = read_body_json(resp).await;
+        assert_eq!(third["assignments"].as_array().unwrap().len(), 1);
+        assert_eq!(third["unfilled"].as_array().unwrap().len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_availability() {
+        let db = TestDb::new();
+        let pool = db.pool.clone();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(r#"{"id":"root", "pass":"pass"}"#.as_bytes())
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_root = ts["token"].as_str().unwrap();
+        let user_id = Uuid::new_v4();
+        let resp = test::TestRequest::post()
+            .uri("/api/users")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({"id": user_id, "isadmin": false }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ps: Value = read_body_json(resp).await;
+        let test_pass = ps["pass"].as_str().unwrap();
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_json(&json!({"id": user_id, "pass": test_pass }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_test = ts["token"].as_str().unwrap();
+        let resp = test::TestRequest::put()
+            .uri("/api/users/me/weekly-availabilities")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .set_json(&json!([
+                {"weekday": 1, "start_time": "09:00:00", "end_time": "18:00:00", "kind": "available"},
+            ]))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let open = |start: &str, end: &str, deadline: &str| json!({"start_date": start, "end_date": end, "deadline": deadline});
+        let resp = test::TestRequest::post()
+            .uri("/api/periods")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .set_json(&open("2031-03-01", "2031-03-15", "2031-02-20T00:00:00"))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = test::TestRequest::post()
+            .uri("/api/periods")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&open("2031-03-01", "2031-03-15", "2031-02-20T00:00:00"))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let p: Value = read_body_json(resp).await;
+        let pid = p["id"].as_i64().unwrap();
+        // 2031-03-04 is a Tuesday, so it does not collide with the Monday default
+        let resp = test::TestRequest::post()
+            .uri("/api/availabilities")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .set_json(&json!({
+                "start_time": "2031-03-04T01:00:00",
+                "end_time": "2031-03-04T06:00:00",
+                "kind": "preferred",
+            }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::get()
+            .uri(&format!("/api/periods/{}/availabilities", pid))
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let v: Value = read_body_json(resp).await;
+        let mine = v["users"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|u| u["username"] == json!(user_id))
+            .unwrap()
+            .clone();
+        assert_eq!(mine["submitted"], true);
+        // two Mondays from the weekly default plus the explicit entry
+        assert_eq!(mine["windows"].as_array().unwrap().len(), 3);
+
+        let resp = test::TestRequest::post()
+            .uri("/api/periods")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&open("2031-04-01", "2031-04-15", "2021-01-01T00:00:00"))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::post()
+            .uri("/api/availabilities")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .set_json(&json!({
+                "start_time": "2031-04-02T01:00:00",
+                "end_time": "2031-04-02T06:00:00",
+                "kind": "unavailable",
+            }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_rt::test]
+    async fn test_request_period() {
+        let db = TestDb::new();
+        let pool = db.pool.clone();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(r#"{"id":"root", "pass":"pass"}"#.as_bytes())
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_root = ts["token"].as_str().unwrap();
+        let user_id = Uuid::new_v4();
+        let resp = test::TestRequest::post()
+            .uri("/api/users")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({"id": user_id, "isadmin": false }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ps: Value = read_body_json(resp).await;
+        let test_pass = ps["pass"].as_str().unwrap();
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_json(&json!({"id": user_id, "pass": test_pass }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_test = ts["token"].as_str().unwrap();
+        let year = 2100;
+        let resp = test::TestRequest::post()
+            .uri("/api/periods")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({
+                "start_date": format!("{}-05-01", year),
+                "end_date": format!("{}-05-15", year),
+                "opens_at": format!("{}-04-01T00:00:00", year),
+                "deadline": format!("{}-04-20T00:00:00", year),
+            }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let p: Value = read_body_json(resp).await;
+        let pid = p["id"].as_i64().unwrap();
+        let start = NaiveDate::from_ymd(year, 5, 3).and_hms(0, 0, 0);
+        let resp = test::TestRequest::post()
+            .uri("/api/schedules")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .set_json(&StartEndWithUser {
+                username: user_id.to_string(),
+                start_time: start,
+                end_time: start + Duration::hours(8),
+            })
+            .send_request(&mut app)
+            .await;
+
+        // not opened yet
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/periods/{}", pid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({"opens_at": "2021-01-01T00:00:00"}))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::get()
+            .uri(&format!("/api/periods/{}/unsubmitted", pid))
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let us: Value = read_body_json(resp).await;
+        assert!(us
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|u| u["id"] == json!(user_id)));
+
+        let resp = test::TestRequest::post()
+            .uri("/api/schedules")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .set_json(&StartEndWithUser {
+                username: user_id.to_string(),
+                start_time: start,
+                end_time: start + Duration::hours(8),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ps: Value = read_body_json(resp).await;
+        let sid = ps["id"].as_i64().unwrap();
+        let resp = test::TestRequest::get()
+            .uri(&format!("/api/periods/{}/unsubmitted", pid))
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let us: Value = read_body_json(resp).await;
+        assert!(!us
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|u| u["id"] == json!(user_id)));
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/periods/{}", pid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({"deadline": "2021-01-02T00:00:00"}))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/duration", sid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .set_json(&StartEnd {
+                start_time: start,
+                end_time: start + Duration::hours(6),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = test::TestRequest::delete()
+            .uri(&format!("/api/schedules/{}", sid))
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/periods/{}", pid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({}))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/periods/{}", pid))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({"opens_at": null}))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let p: Value = read_body_json(resp).await;
+        assert_eq!(p["opens_at"], Value::Null);
+        assert_eq!(p["deadline"], "2021-01-02T00:00:00");
+    }
+
+    #[actix_rt::test]
+    async fn test_error_response() {
+        let db = TestDb::new();
+        let pool = db.pool.clone();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(r#"{"id":"root", "pass":"pass"}"#.as_bytes())
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_root = ts["token"].as_str().unwrap();
+        let user_id = Uuid::new_v4();
+        let resp = test::TestRequest::post()
+            .uri("/api/users")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({"id": user_id, "isadmin": false }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ps: Value = read_body_json(resp).await;
+        let test_pass = ps["pass"].as_str().unwrap();
+        let resp = test::TestRequest::post()
+            .uri("/api/users")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({"id": user_id, "isadmin": false }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        let e: Value = read_body_json(resp).await;
+        assert_eq!(e["error"]["code"], "user_already_exists");
+        assert_eq!(e["error"]["details"]["table"], "users");
+
+        let resp = test::TestRequest::post()
+            .uri("/api/users")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_payload(r#"{"id": 1}"#.as_bytes())
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let e: Value = read_body_json(resp).await;
+        assert_eq!(e["error"]["code"], "invalid_body");
+
+        let resp = test::TestRequest::get()
+            .uri("/api/schedules/abc")
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let e: Value = read_body_json(resp).await;
+        assert_eq!(e["error"]["code"], "not_found");
+
+        let resp = test::TestRequest::post()
+            .uri("/api/users/import?dry_run=maybe")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_payload("")
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let e: Value = read_body_json(resp).await;
+        assert_eq!(e["error"]["code"], "invalid_parameter");
+
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_json(&json!({"id": user_id, "pass": test_pass }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_test = ts["token"].as_str().unwrap();
+        let resp = test::TestRequest::patch()
+            .uri("/api/users/me/password")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .set_json(&json!({"old": "wrong", "new": "new" }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let e: Value = read_body_json(resp).await;
+        assert_eq!(e["error"]["code"], "wrong_password");
+
+        let today = Local::now().naive_local();
+        let resp = test::TestRequest::post()
+            .uri("/api/schedules")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_test))
+            .set_json(&StartEndWithUser {
+                username: user_id.to_string(),
+                start_time: today,
+                end_time: today.checked_add_signed(Duration::days(1)).unwrap(),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ps: Value = read_body_json(resp).await;
+        let sid = ps["id"].as_i64().unwrap();
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/permission", sid))
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let e: Value = read_body_json(resp).await;
+        assert_eq!(e["error"]["code"], "action_not_permitted");
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/permission", sid))
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/permission", sid))
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        let e: Value = read_body_json(resp).await;
+        assert_eq!(e["error"]["code"], "invalid_schedule_state");
+        assert_eq!(e["error"]["details"]["status"], "approved");
+
+        let resp = test::TestRequest::delete()
+            .uri(&format!("/api/schedules/{}", sid))
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        let resp = test::TestRequest::delete()
+            .uri(&format!("/api/schedules/{}", -sid))
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let e: Value = read_body_json(resp).await;
+        assert_eq!(e["error"]["code"], "not_found");
+    }
+
+    #[actix_rt::test]
+    async fn test_schedule_transitions() {
+        let db = TestDb::new();
+        let pool = db.pool.clone();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(r#"{"id":"root", "pass":"pass"}"#.as_bytes())
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_root = ts["token"].as_str().unwrap();
+        let user_id = Uuid::new_v4();
+        let resp = test::TestRequest::post()
+            .uri("/api/users")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({"id": user_id, "isadmin": false }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ps: Value = read_body_json(resp).await;
+        let test_pass = ps["pass"].as_str().unwrap();
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_json(&json!({"id": user_id, "pass": test_pass }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_test = ts["token"].as_str().unwrap();
+        let today = Local::now().naive_local();
+        let resp = test::TestRequest::post()
+            .uri("/api/schedules")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&StartEndWithUser {
+                username: user_id.to_string(),
+                start_time: today,
+                end_time: today.checked_add_signed(Duration::hours(4)).unwrap(),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let s: Schedule = read_body_json(resp).await;
+        assert_eq!(s.status, Status::Assigned);
+
+        let resp = test::TestRequest::get()
+            .uri(&format!("/api/schedules/{}/actions", s.id))
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let a: Value = read_body_json(resp).await;
+        assert_eq!(a["status"], "assigned");
+        assert_eq!(a["actions"], json!(["approve", "reject"]));
+
+        // someone else's shift that is not approved yet stays hidden
+        let other_id = Uuid::new_v4();
+        let resp = test::TestRequest::post()
+            .uri("/api/users")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({"id": other_id, "isadmin": false }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::post()
+            .uri("/api/schedules")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&StartEndWithUser {
+                username: other_id.to_string(),
+                start_time: today,
+                end_time: today.checked_add_signed(Duration::hours(4)).unwrap(),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let other: Schedule = read_body_json(resp).await;
+        let resp = test::TestRequest::get()
+            .uri(&format!("/api/schedules/{}/actions", other.id))
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let e: Value = read_body_json(resp).await;
+        assert_eq!(e["error"]["code"], "not_found");
+
+        let resp = test::TestRequest::post()
+            .uri(&format!("/api/schedules/{}/transitions", s.id))
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({"action": "approve"}))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/schedules/{}/duration", s.id))
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&StartEnd {
+                start_time: s.start_time,
+                end_time: s.end_time + Duration::hours(1),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        for (token, action, status) in [
+            (token_test, "approve", "approved"),
+            (token_test, "request_absence", "absence_requested"),
+            (token_root, "approve_absence", "absent_approved"),
+        ]
+        .iter()
+        {
+            let resp = test::TestRequest::post()
+                .uri(&format!("/api/schedules/{}/transitions", s.id))
+                .header("Authorization", format!("bearer {}", token))
+                .set_json(&json!({ "action": action }))
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            let t: Value = read_body_json(resp).await;
+            assert_eq!(t["status"], *status);
+        }
+
+        let resp = test::TestRequest::post()
+            .uri(&format!("/api/schedules/{}/transitions", s.id))
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({"action": "cancel"}))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        let e: Value = read_body_json(resp).await;
+        assert_eq!(e["error"]["code"], "invalid_schedule_state");
+        assert_eq!(e["error"]["details"]["status"], "absent_approved");
+
+        let resp = test::TestRequest::get()
+            .uri(&format!("/api/schedules/{}", s.id))
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let d: Value = read_body_json(resp).await;
+        assert_eq!(d["status"], "absent_approved");
+        assert_eq!(d["created_by"], "root");
+        assert_eq!(d["approved_by"], user_id.to_string());
+        assert_eq!(d["absence"].as_array().unwrap().len(), 2);
+        assert_eq!(d["revisions"].as_array().unwrap().len(), 1);
+        assert_eq!(d["revisions"][0]["old_end_time"], json!(s.end_time));
+        assert_eq!(
+            d["revisions"][0]["new_end_time"],
+            json!(s.end_time + Duration::hours(1))
+        );
+        let actions: Vec<_> = d["history"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["action"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                "create",
+                "edit",
+                "approve",
+                "request_absence",
+                "approve_absence"
+            ]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_schedule_queries() {
+        let db = TestDb::new();
+        let pool = db.pool.clone();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(r#"{"id":"root", "pass":"pass"}"#.as_bytes())
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_root = ts["token"].as_str().unwrap().to_string();
+        let mut users = Vec::new();
+        for _ in 0..2 {
+            let user_id = Uuid::new_v4();
+            let resp = test::TestRequest::post()
+                .uri("/api/users")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Authorization", format!("bearer {}", token_root))
+                .set_json(&json!({"id": user_id, "isadmin": false }))
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            let ps: Value = read_body_json(resp).await;
+            let resp = test::TestRequest::post()
+                .uri("/api/login")
+                .header(header::CONTENT_TYPE, "application/json")
+                .set_json(&json!({"id": user_id, "pass": ps["pass"] }))
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            let ts: Value = read_body_json(resp).await;
+            users.push((
+                user_id.to_string(),
+                ts["token"].as_str().unwrap().to_string(),
+            ));
+        }
+        let (user_a, token_a) = &users[0];
+        let (_, token_b) = &users[1];
+
+        let year = 2300;
+        let mut ids = Vec::new();
+        for d in 5..8 {
+            let start_time = NaiveDate::from_ymd(year, 1, d).and_hms(1, 0, 0);
+            let resp = test::TestRequest::post()
+                .uri("/api/schedules")
+                .header("Authorization", format!("bearer {}", token_root))
+                .set_json(&StartEndWithUser {
+                    username: user_a.clone(),
+                    start_time,
+                    end_time: start_time + Duration::hours(4),
+                })
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            let s: Schedule = read_body_json(resp).await;
+            ids.push(s.id);
+        }
+        let range = format!("start={}-01-01&end={}-01-08", year, year);
+
+        let resp = test::TestRequest::get()
+            .uri(&format!("/api/schedules?{}&username={}", range, user_a))
+            .header("Authorization", format!("bearer {}", token_b))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ss: Vec<Schedule> = read_body_json(resp).await;
+        assert!(ss.is_empty());
+
+        let resp = test::TestRequest::post()
+            .uri(&format!("/api/schedules/{}/transitions", ids[0]))
+            .header("Authorization", format!("bearer {}", token_a))
+            .set_json(&json!({"action": "approve"}))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::get()
+            .uri(&format!("/api/schedules?{}&username={}", range, user_a))
+            .header("Authorization", format!("bearer {}", token_b))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ss: Vec<Schedule> = read_body_json(resp).await;
+        assert_eq!(ss.iter().map(|s| s.id).collect::<Vec<_>>(), vec![ids[0]]);
+
+        let resp = test::TestRequest::get()
+            .uri(&format!("/api/schedules/{}", ids[1]))
+            .header("Authorization", format!("bearer {}", token_b))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = test::TestRequest::get()
+            .uri(&format!(
+                "/api/schedules?start={}-01-06&end={}-01-07&username={}",
+                year, year, user_a
+            ))
+            .header("Authorization", format!("bearer {}", token_a))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ss: Vec<Schedule> = read_body_json(resp).await;
+        assert_eq!(ss.iter().map(|s| s.id).collect::<Vec<_>>(), vec![ids[1]]);
+
+        let resp = test::TestRequest::get()
+            .uri(&format!(
+                "/api/schedules?{}&username={}&status=assigned,requested",
+                range, user_a
+            ))
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ss: Vec<Schedule> = read_body_json(resp).await;
+        assert_eq!(
+            ss.iter().map(|s| s.id).collect::<Vec<_>>(),
+            vec![ids[2], ids[1]]
+        );
+
+        let mut seen = Vec::new();
+        let mut uri = format!(
+            "/api/schedules?{}&username={}&sort=start_time&limit=2",
+            range, user_a
+        );
+        loop {
+            let resp = test::TestRequest::get()
+                .uri(&uri)
+                .header("Authorization", format!("bearer {}", token_root))
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            let next = resp
+                .headers()
+                .get("X-Next-Cursor")
+                .map(|x| x.to_str().unwrap().to_string());
+            let ss: Vec<Schedule> = read_body_json(resp).await;
+            seen.extend(ss.iter().map(|s| s.id));
+            match next {
+                Some(c) => uri = format!("{}&cursor={}", uri, c),
+                None => break,
+            }
+        }
+        assert_eq!(seen, ids);
+
+        let resp = test::TestRequest::get()
+            .uri("/api/schedules?sort=end_time")
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = test::TestRequest::put()
+            .uri("/api/settings/shared_roster")
+            .header("Authorization", format!("bearer {}", token_b))
+            .set_json(&json!({"value": "true"}))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = test::TestRequest::put()
+            .uri("/api/settings/shared_roster")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({"value": "yes"}))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn test_calendar_feed() {
+        let db = TestDb::new();
+        let pool = db.pool.clone();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(r#"{"id":"root", "pass":"pass"}"#.as_bytes())
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_root = ts["token"].as_str().unwrap();
+        let user_id = Uuid::new_v4();
+        let resp = test::TestRequest::post()
+            .uri("/api/users")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({"id": user_id, "isadmin": false }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ps: Value = read_body_json(resp).await;
+        let test_pass = ps["pass"].as_str().unwrap();
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_json(&json!({"id": user_id, "pass": test_pass }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_test = ts["token"].as_str().unwrap();
+        let mut ids = Vec::new();
+        for d in 1..3 {
+            let start_time = Utc::now().naive_utc().date().and_hms(0, 0, 0) + Duration::days(d);
+            let resp = test::TestRequest::post()
+                .uri("/api/schedules")
+                .header("Authorization", format!("bearer {}", token_root))
+                .set_json(&StartEndWithUser {
+                    username: user_id.to_string(),
+                    start_time,
+                    end_time: start_time + Duration::hours(4),
+                })
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            let s: Schedule = read_body_json(resp).await;
+            ids.push(s.id);
+        }
+
+        let resp = test::TestRequest::post()
+            .uri(&format!("/api/schedules/{}/transitions", ids[0]))
+            .header("Authorization", format!("bearer {}", token_test))
+            .set_json(&json!({"action": "approve"}))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::get()
+            .uri("/api/users/me/shifts.ics")
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("text/calendar"));
+
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(body.ends_with("END:VCALENDAR\r\n"));
+        // only the approved shift is published
+        assert!(body.contains(&format!("UID:schedule-{}@kintai\r\n", ids[0])));
+        assert!(!body.contains(&format!("UID:schedule-{}@kintai\r\n", ids[1])));
+
+        let resp = test::TestRequest::post()
+            .uri("/api/users/me/calendar-feed")
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let f: Value = read_body_json(resp).await;
+        let old_path = f["path"].as_str().unwrap().to_string();
+        let resp = test::TestRequest::get()
+            .uri(&old_path)
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let feed = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(feed.contains(&format!("UID:schedule-{}@kintai\r\n", ids[0])));
+
+        let resp = test::TestRequest::post()
+            .uri("/api/users/me/calendar-feed")
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let f: Value = read_body_json(resp).await;
+        let path = f["path"].as_str().unwrap().to_string();
+        assert_ne!(path, old_path);
+
+        let resp = test::TestRequest::get()
+            .uri(&old_path)
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = test::TestRequest::delete()
+            .uri("/api/users/me/calendar-feed")
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::get()
+            .uri(&path)
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn test_exports() {
+        use diesel::ExpressionMethods;
+        use schema::{punches, users};
+
+        let db = TestDb::new();
+        let pool = db.pool.clone();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(r#"{"id":"root", "pass":"pass"}"#.as_bytes())
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_root = ts["token"].as_str().unwrap();
+        let user_id = Uuid::new_v4();
+        let resp = test::TestRequest::post()
+            .uri("/api/users")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({"id": user_id, "isadmin": false }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ps: Value = read_body_json(resp).await;
+        let test_pass = ps["pass"].as_str().unwrap();
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_json(&json!({"id": user_id, "pass": test_pass }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_test = ts["token"].as_str().unwrap();
+
+        for (kind, status) in [
+            ("clock_in", StatusCode::OK),
+            ("break_end", StatusCode::CONFLICT),
+            ("break_start", StatusCode::OK),
+            ("break_end", StatusCode::OK),
+            ("clock_out", StatusCode::OK),
+        ]
+        .iter()
+        {
+            let resp = test::TestRequest::post()
+                .uri("/api/users/me/punches")
+                .header("Authorization", format!("bearer {}", token_test))
+                .set_json(&json!({ "kind": kind }))
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), *status);
+        }
+
+        let year = 2600;
+        let local =
+            |d: u32, h: u32, m: u32| from_local(&NaiveDate::from_ymd(year, 3, d).and_hms(h, m, 0));
+        diesel::insert_into(punches::table)
+            .values(
+                vec![
+                    ("clock_in", local(2, 21, 0)),
+                    ("break_start", local(2, 23, 0)),
+                    ("break_end", local(2, 23, 30)),
+                    ("clock_out", local(3, 7, 30)),
+                ]
+                .into_iter()
+                .map(|(k, t)| {
+                    (
+                        punches::username.eq(user_id.to_string()),
+                        punches::kind.eq(k),
+                        punches::punched_at.eq(t),
+                    )
+                })
+                .collect::<Vec<_>>(),
+            )
+            .execute(&pool.get().unwrap())
+            .unwrap();
+
+        let resp = test::TestRequest::get()
+            .uri(&format!(
+                "/api/exports/worktime.csv?start={}-03-01&end={}-03-03",
+                year, year
+            ))
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let body = test::read_body(resp).await;
+        assert!(body.starts_with(b"\xef\xbb\xbf"));
+        let body = String::from_utf8(body[3..].to_vec()).unwrap();
+        let lines: Vec<_> = body.split("\r\n").collect();
+        assert_eq!(lines[0], export::WORKTIME_HEADER.join(","));
+        // 21:00-07:30 with a 30 minute break; 22:00-05:00 is late night
+        assert_eq!(
+            lines[1],
+            format!(
+                "{},,{}-03-02,21:00,07:30,30,8.00,2.00,6.50,10.00,21:00,07:30,10.00",
+                user_id, year
+            )
+        );
+        assert_eq!(lines.len(), 3);
+
+        let resp = test::TestRequest::get()
+            .uri(&format!(
+                "/api/exports/worktime.csv?start={}-03-01&end={}-03-03&encoding=shift_jis",
+                year, year
+            ))
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        assert!(resp.headers().get("X-Export-Replaced-Users").is_none());
+
+        let body = test::read_body(resp).await;
+        let (text, _, errors) = encoding_rs::SHIFT_JIS.decode(&body);
+        assert!(!errors);
+        assert!(text.starts_with(&export::WORKTIME_HEADER.join(",")));
+
+        // no Shift_JIS code for an emoji
+        diesel::update(users::table.filter(users::id.eq(user_id.to_string())))
+            .set(users::last_name.eq("山田😀"))
+            .execute(&pool.get().unwrap())
+            .unwrap();
+        let resp = test::TestRequest::get()
+            .uri(&format!(
+                "/api/exports/worktime.csv?start={}-03-01&end={}-03-03&encoding=shift_jis",
+                year, year
+            ))
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("X-Export-Replaced-Users").unwrap(), "1");
+
+        let body = test::read_body(resp).await;
+        let (text, _, errors) = encoding_rs::SHIFT_JIS.decode(&body);
+        assert!(!errors);
+        assert!(text.contains(&format!("{},山田〓,", user_id)));
+        assert!(!text.contains("&#"));
+
+        let resp = test::TestRequest::get()
+            .uri(&format!(
+                "/api/exports/worktime.csv?start={}-03-01&end={}-03-03&username=root",
+                year, year
+            ))
+            .header("Authorization", format!("bearer {}", token_test))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = test::TestRequest::post()
+            .uri("/api/schedules")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&StartEndWithUser {
+                username: user_id.to_string(),
+                start_time: local(5, 9, 0),
+                end_time: local(5, 18, 0),
+            })
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let s: Schedule = read_body_json(resp).await;
+        let resp = test::TestRequest::get()
+            .uri(&format!(
+                "/api/exports/schedules.csv?start={}-03-01&end={}-04-01&username={}",
+                year, year, user_id
+            ))
+            .header("Authorization", format!("bearer {}", token_root))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let body = test::read_body(resp).await;
+        let body = String::from_utf8(body[3..].to_vec()).unwrap();
+        assert_eq!(
+            body,
+            format!(
+                "{}\r\n{},{},山田😀,{}-03-05,09:00,18:00,60,8.00,0.00,0.00,assigned,\r\n",
+                export::SCHEDULE_HEADER.join(","),
+                s.id,
+                user_id,
+                year
+            )
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_import_users() {
+        let db = TestDb::new();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(db.pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+        let token_root = db.admin().token;
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let valid = format!(
+            "id,last_name,first_name,employee_number,hire_date,hourly_wage\n\
+             {},山田,太郎,{},2021-04-01,1100\n\
+             {},鈴木,花子,,2021/05/10,\n",
+            a,
+            a.to_simple(),
+            b
+        );
+
+        let resp = test::TestRequest::post()
+            .uri("/api/users/import?dry_run=true")
+            .header(header::CONTENT_TYPE, "text/csv")
+            .header("Authorization", format!("bearer {}", db.staff().token))
+            .set_payload(valid.clone())
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = test::TestRequest::post()
+            .uri("/api/users/import?dry_run=true")
+            .header(header::CONTENT_TYPE, "text/csv")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_payload(valid.clone())
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let report: Value = read_body_json(resp).await;
+        assert_eq!(report["dry_run"], json!(true));
+        assert_eq!(report["rows"].as_array().unwrap().len(), 2);
+        assert_eq!(report["employees"][0]["hire_date"], json!("2021-04-01"));
+
+        let invalid = format!("{}{},,,,yesterday,-5\n", valid, a);
+        let resp = test::TestRequest::post()
+            .uri("/api/users/import")
+            .header(header::CONTENT_TYPE, "text/csv")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_payload(invalid)
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let err: Value = read_body_json(resp).await;
+        assert_eq!(err["error"]["code"], json!("invalid_rows"));
+        let rows = err["error"]["details"]["rows"].as_array().unwrap();
+        assert!(rows[0]["errors"].as_array().unwrap().is_empty());
+        assert_eq!(rows[2]["line"], json!(4));
+        assert_eq!(rows[2]["errors"].as_array().unwrap().len(), 2);
+
+        let resp = test::TestRequest::post()
+            .uri("/api/users/import?format=csv")
+            .header(header::CONTENT_TYPE, "text/csv")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_payload(valid.clone())
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp
+            .headers()
+            .get("Content-Disposition")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .contains("credentials.csv"));
+
+        let body = test::read_body(resp).await;
+        let text = employees::decode(&body);
+        let creds = csv::Reader::from_reader(text.as_bytes())
+            .records()
+            .map(|r| r.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(creds.len(), 2);
+        let pass = &creds[0][1];
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_json(&json!({"id": a, "pass": pass }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // the same file again conflicts with the users it just created
+        let resp = test::TestRequest::post()
+            .uri("/api/users/import")
+            .header(header::CONTENT_TYPE, "text/csv")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_payload(valid)
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[actix_rt::test]
+    async fn test_import_schedules() {
+        let db = TestDb::new();
+        let pool = db.pool.clone();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(r#"{"id":"root", "pass":"pass"}"#.as_bytes())
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let ts: Value = read_body_json(resp).await;
+        let token_root = ts["token"].as_str().unwrap().to_string();
+        let mut users = Vec::new();
+        for _ in 0..2 {
+            let user_id = Uuid::new_v4();
+            let resp = test::TestRequest::post()
+                .uri("/api/users")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Authorization", format!("bearer {}", token_root))
+                .set_json(&json!({"id": user_id, "isadmin": false }))
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), StatusCode::OK);
+            users.push(user_id.to_string());
+        }
+        let (user_a, user_b) = (&users[0], &users[1]);
+
+        let year = 2300;
+        let local = |d: u32, h: u32| from_local(&NaiveDate::from_ymd(year, 8, d).and_hms(h, 0, 0));
+        let resp = test::TestRequest::post()
+            .uri("/api/schedules")
+            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({
+                "username": user_a,
+                "start_time": local(2, 9),
+                "end_time": local(2, 13),
+            }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let post = |uri: &str, body: String| {
+            test::TestRequest::post()
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "text/csv")
+                .header("Authorization", format!("bearer {}", token_root))
+                .set_payload(body)
+                .to_request()
+        };
+        let grid = format!(
+            "スタッフ,{y}/08/02(月),{y}/08/03(火)\n{a},,9:30-17\n{b},22-6,休\n",
+            y = year,
+            a = user_a,
+            b = user_b
+        );
+        let resp = test::call_service(
+            &mut app,
+            post("/api/schedules/import?dry_run=true", grid.clone()),
+        )
+        .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let report: Value = read_body_json(resp).await;
+        let rows = report["rows"].as_array().unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["column"], json!(3));
+        assert_eq!(
+            rows[1]["end_time"],
+            json!(local(3, 6).format("%Y-%m-%dT%H:%M:%S").to_string())
+        );
+        assert!(report["schedules"].as_array().unwrap().is_empty());
+
+        // neither a shift nor a day off, and a cell with no date above it
+        let stray = format!("スタッフ,{y}/08/05\n{a},遅番,9-12\n", y = year, a = user_a);
+        let resp =
+            test::call_service(&mut app, post("/api/schedules/import?dry_run=true", stray)).await;
+
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let err: Value = read_body_json(resp).await;
+        let rows = err["error"]["details"]["rows"].as_array().unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["errors"], json!(["cannot parse shift: 遅番"]));
+        assert_eq!(rows[1]["column"], json!(3));
+        assert_eq!(rows[1]["errors"], json!(["no date for column 3: 9-12"]));
+
+        let overlapping = format!(
+            "username,start,end\n{a},{y}-08-02 12:00,{y}-08-02 15:00\n\
+             {b},{y}-08-04 09:00,{y}-08-04 12:00\n{b},{y}-08-04 11:00,{y}-08-04 13:00\n\
+             nobody,{y}-08-04 09:00,{y}-08-04 08:00\n",
+            y = year,
+            a = user_a,
+            b = user_b
+        );
+        let resp = test::call_service(&mut app, post("/api/schedules/import", overlapping)).await;
+
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let err: Value = read_body_json(resp).await;
+        let rows = err["error"]["details"]["rows"].as_array().unwrap();
+        let errors = |i: usize| rows[i]["errors"].as_array().unwrap().clone();
+        assert_eq!(errors(0).len(), 1);
+        assert!(errors(1).is_empty());
+        assert_eq!(errors(2), vec![json!("overlaps line 3 in this file")]);
+        assert_eq!(errors(3).len(), 2);
+
+        let resp = test::call_service(
+            &mut app,
+            post("/api/schedules/import?layout=csv", grid.clone()),
+        )
+        .await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = test::call_service(&mut app, post("/api/schedules/import", grid.clone())).await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let report: Value = read_body_json(resp).await;
+        let created = report["schedules"].as_array().unwrap();
+        assert_eq!(created.len(), 2);
+        assert!(created.iter().all(|s| s["status"] == json!("assigned")));
+
+        // importing the same roster twice would double-book everyone
+        let resp = test::call_service(&mut app, post("/api/schedules/import", grid)).await;
+
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[actix_rt::test]
+    async fn test_deactivated_user() {
+        use diesel::ExpressionMethods;
+        use schema::{availabilities, periods, users, weekly_availabilities};
+
+        let db = TestDb::new();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(db.pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+        let root = db.root();
+        let staff = db.staff();
+        let feed = ical::regenerate(&db.conn(), &staff.id).unwrap();
+        let pid = diesel::insert_into(periods::table)
+            .values((
+                periods::start_date.eq(NaiveDate::from_ymd(2100, 1, 1)),
+                periods::end_date.eq(NaiveDate::from_ymd(2100, 1, 31)),
+                periods::deadline.eq(NaiveDate::from_ymd(2099, 12, 1).and_hms(0, 0, 0)),
+                periods::created_by.eq("root"),
+            ))
+            .returning(periods::id)
+            .get_result::<i64>(&db.conn())
+            .unwrap();
+        let week = NaiveDate::from_ymd(2100, 1, 3);
+        let local = |d: i64, h: u32| from_local(&(week + Duration::days(d)).and_hms(h, 0, 0));
+        diesel::insert_into(availabilities::table)
+            .values((
+                availabilities::username.eq(&staff.id),
+                availabilities::start_time.eq(local(0, 9)),
+                availabilities::end_time.eq(local(0, 18)),
+                availabilities::kind.eq("available"),
+            ))
+            .execute(&db.conn())
+            .unwrap();
+        diesel::insert_into(weekly_availabilities::table)
+            .values((
+                weekly_availabilities::username.eq(&staff.id),
+                weekly_availabilities::weekday.eq(1),
+                weekly_availabilities::start_time.eq(NaiveTime::from_hms(9, 0, 0)),
+                weekly_availabilities::end_time.eq(NaiveTime::from_hms(18, 0, 0)),
+                weekly_availabilities::kind.eq("available"),
+            ))
+            .execute(&db.conn())
+            .unwrap();
+
+        diesel::update(users::table.filter(users::id.eq(&staff.id)))
+            .set(users::deactivated_at.eq(Utc::now().naive_utc()))
+            .execute(&db.conn())
+            .unwrap();
+
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_json(&json!({"id": staff.id, "pass": staff.pass }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // tokens issued before the deactivation stop working too
+        let resp = test::TestRequest::post()
+            .uri("/api/users/me/punches")
+            .header("Authorization", format!("bearer {}", staff.token))
+            .set_json(&json!({ "kind": "clock_in" }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = test::TestRequest::get()
+            .uri("/api/users")
+            .header("Authorization", format!("bearer {}", root.token))
+            .send_request(&mut app)
+            .await;
+        let us: Value = read_body_json(resp).await;
+        let u = us
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|u| u["id"] == json!(staff.id))
+            .unwrap();
+        assert_eq!(u["active"], json!(false));
+
+        let resp = test::TestRequest::get()
+            .uri(&format!("/api/feeds/{}/shifts.ics", feed))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = test::TestRequest::get()
+            .uri(&format!("/api/periods/{}/unsubmitted", pid))
+            .header("Authorization", format!("bearer {}", root.token))
+            .send_request(&mut app)
+            .await;
+        let us: Value = read_body_json(resp).await;
+        assert!(us
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|u| u["id"] != json!(staff.id)));
+
+        let resp = test::TestRequest::get()
+            .uri(&format!("/api/periods/{}/availabilities", pid))
+            .header("Authorization", format!("bearer {}", root.token))
+            .send_request(&mut app)
+            .await;
+        let ps: Value = read_body_json(resp).await;
+        assert!(ps["users"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|u| u["username"] != json!(staff.id)));
+
+        // neither their submitted nor their weekly availability is rostered
+        let resp = test::TestRequest::post()
+            .uri("/api/schedules/generation")
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({
+                "week_start": week,
+                "seed": 1,
+                "dry_run": true,
+                "requirements": [
+                    {"start_time": local(0, 10), "end_time": local(0, 14), "headcount": 1},
+                    {"start_time": local(1, 10), "end_time": local(1, 14), "headcount": 1},
+                ],
+            }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let r: Value = read_body_json(resp).await;
+        assert!(r["assignments"].as_array().unwrap().is_empty());
+
+        let resp = test::TestRequest::post()
+            .uri("/api/schedules/import")
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_payload(format!(
+                "username,start,end\n{},2100-01-03 10:00,2100-01-03 14:00\n",
+                staff.id
+            ))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let err: Value = read_body_json(resp).await;
+        assert_eq!(
+            err["error"]["details"]["rows"][0]["errors"],
+            json!([format!("user {} is deactivated", staff.id)])
+        );
+
+        let resp = test::TestRequest::delete()
+            .uri(&format!("/api/users/{}", staff.id))
+            .header("Authorization", format!("bearer {}", root.token))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn test_auth_roles() {
+        use diesel::ExpressionMethods;
+        use schema::{periods, users};
+
+        let db = TestDb::new();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(db.pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+        let staff = db.staff();
+
+        let resp = test::TestRequest::get()
+            .uri("/api/settings")
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = test::TestRequest::get()
+            .uri("/api/settings")
+            .header("Authorization", format!("bearer {}", staff.token))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let err: Value = read_body_json(resp).await;
+        assert_eq!(err["error"]["code"], json!("admin_only"));
+
+        // the role comes from the database, not from the token
+        diesel::update(users::table.filter(users::id.eq(&staff.id)))
+            .set(users::isadmin.eq(true))
+            .execute(&db.conn())
+            .unwrap();
+        let resp = test::TestRequest::get()
+            .uri("/api/settings")
+            .header("Authorization", format!("bearer {}", staff.token))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // an error inside the transaction undoes what came before it
+        let conn = db.conn();
+        let ret: Result<(), ServerError> = transaction(&conn, || {
+            diesel::insert_into(periods::table)
+                .values((
+                    periods::start_date.eq(NaiveDate::from_ymd(2100, 1, 1)),
+                    periods::end_date.eq(NaiveDate::from_ymd(2100, 1, 31)),
+                    periods::deadline.eq(NaiveDate::from_ymd(2099, 12, 1).and_hms(0, 0, 0)),
+                    periods::created_by.eq("root"),
+                ))
+                .execute(&conn)
+                .unwrap();
+            Err(ServerError::InternalError)
+        });
+
+        assert!(ret.is_err());
+        let n: i64 = diesel::QueryDsl::count(periods::table)
+            .get_result(&conn)
+            .unwrap();
+        assert_eq!(n, 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_pool_exhausted() {
+        let db = TestDb::new();
+        let pool = db.pool(1, std::time::Duration::from_millis(200));
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+        let root = db.root();
+
+        let held = pool.get().unwrap();
+        let resp = test::TestRequest::get()
+            .uri("/api/periods")
+            .header("Authorization", format!("bearer {}", root.token))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let err: Value = read_body_json(resp).await;
+        assert_eq!(err["error"]["code"], json!("service_unavailable"));
+
+        drop(held);
+        let resp = test::TestRequest::get()
+            .uri("/api/periods")
+            .header("Authorization", format!("bearer {}", root.token))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn test_paid_leave() {
+        use diesel::ExpressionMethods;
+        use schema::{schedules, users, wages};
+
+        let db = TestDb::new();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(db.pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+        let root = db.root();
+        let staff = db.staff();
+        let conn = db.conn();
+
+        // hired seven months ago, three 4-hour shifts a week since: 5 days at six months
+        let today = local_today();
+        let hire_date = today - Duration::days(213);
+        diesel::update(users::table.filter(users::id.eq(&staff.id)))
+            .set(users::hire_date.eq(hire_date))
+            .execute(&conn)
+            .unwrap();
+        diesel::insert_into(wages::table)
+            .values((
+                wages::username.eq(&staff.id),
+                wages::hourly_wage.eq(1200),
+                wages::effective_from.eq(hire_date),
+                wages::created_by.eq("root"),
+            ))
+            .execute(&conn)
+            .unwrap();
+        let shifts = (0..182)
+            .map(|i| hire_date + Duration::days(i))
+            .filter(|d| d.weekday().num_days_from_monday() % 2 == 0 && d.weekday() != Weekday::Sun)
+            .map(|d| {
+                (
+                    schedules::username.eq(&staff.id),
+                    schedules::start_time.eq(from_local(&d.and_hms(10, 0, 0))),
+                    schedules::end_time.eq(from_local(&d.and_hms(14, 0, 0))),
+                    schedules::created_by.eq("root"),
+                    schedules::status.eq(Status::Approved),
+                )
+            })
+            .collect::<Vec<_>>();
+        diesel::insert_into(schedules::table)
+            .values(&shifts)
+            .execute(&conn)
+            .unwrap();
+
+        // a shift every other week is 26 days a year, below the first band
+        let fortnightly = (0..182)
+            .step_by(14)
+            .map(|i| {
+                let d = hire_date + Duration::days(i);
+                (
+                    schedules::username.eq(&root.id),
+                    schedules::start_time.eq(from_local(&d.and_hms(10, 0, 0))),
+                    schedules::end_time.eq(from_local(&d.and_hms(14, 0, 0))),
+                    schedules::created_by.eq("root"),
+                    schedules::status.eq(Status::Approved),
+                )
+            })
+            .collect::<Vec<_>>();
+        diesel::insert_into(schedules::table)
+            .values(&fortnightly)
+            .execute(&conn)
+            .unwrap();
+        assert_eq!(
+            leave::weekly_days(&conn, &root.id, hire_date, hire_date + Duration::days(182)),
+            Ok(0)
+        );
+
+        let balances = || {
+            test::TestRequest::get()
+                .uri("/api/leave/balances")
+                .header("Authorization", format!("bearer {}", staff.token))
+                .to_request()
+        };
+        // looking does not grant anything
+        let resp = test::call_service(&mut app, balances()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let bs: Value = read_body_json(resp).await;
+        assert_eq!(bs[0]["remaining"], json!(0));
+
+        let resp = test::TestRequest::post()
+            .uri("/api/leave/accrual")
+            .header("Authorization", format!("bearer {}", root.token))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::call_service(&mut app, balances()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let bs: Value = read_body_json(resp).await;
+        assert_eq!(bs[0]["remaining"], json!(5));
+        assert_eq!(bs[0]["grants"][0]["days"], json!(5));
+        assert_eq!(
+            bs[0]["grants"][0]["expires_on"],
+            json!(leave::add_months(hire_date, 30))
+        );
+
+        // no shift that day, so the paid time is the average shift
+        let date = today + Duration::days(10);
+        let resp = test::TestRequest::post()
+            .uri("/api/leave/requests")
+            .header("Authorization", format!("bearer {}", staff.token))
+            .set_json(&json!({ "date": date }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let r: Value = read_body_json(resp).await;
+        assert_eq!(r["status"], json!("requested"));
+        assert_eq!(r["minutes"], json!(240));
+        let id = r["id"].as_i64().unwrap();
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/leave/requests/{}", id))
+            .header("Authorization", format!("bearer {}", staff.token))
+            .set_json(&json!({ "status": "approved" }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/leave/requests/{}", id))
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({ "status": "approved" }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::get()
+            .uri(&format!(
+                "/api/payroll?start={}&end={}",
+                date,
+                date + Duration::days(1)
+            ))
+            .header("Authorization", format!("bearer {}", staff.token))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let ps: Value = read_body_json(resp).await;
+        assert_eq!(ps[0]["leave_days"], json!(1));
+        assert_eq!(ps[0]["leave_pay"], json!(4800));
+        assert_eq!(ps[0]["total"], json!(4800));
+
+        // four days are left; a manager's designation counts against them too
+        for i in 1..5 {
+            let resp = test::TestRequest::post()
+                .uri("/api/leave/requests")
+                .header("Authorization", format!("bearer {}", root.token))
+                .set_json(&json!({
+                    "date": date + Duration::days(i),
+                    "minutes": 240,
+                    "username": staff.id,
+                }))
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), StatusCode::OK);
+            let r: Value = read_body_json(resp).await;
+            assert_eq!(r["status"], json!("approved"));
+        }
+
+        // the paid time is not the worker's to choose
+        let resp = test::TestRequest::post()
+            .uri("/api/leave/requests")
+            .header("Authorization", format!("bearer {}", staff.token))
+            .set_json(&json!({ "date": date + Duration::days(5), "minutes": 1440 }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
 
-        let ts: Value = read_body_json(resp).await;
-        let token_root = ts["token"].as_str().unwrap();
-        let user_id = Uuid::new_v4();
         let resp = test::TestRequest::post()
-            .uri("/api/users")
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
-            .set_json(&json!({"id": user_id, "isadmin": false }))
+            .uri("/api/leave/requests")
+            .header("Authorization", format!("bearer {}", staff.token))
+            .set_json(&json!({ "date": date + Duration::days(5) }))
             .send_request(&mut app)
             .await;
 
-        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let err: Value = read_body_json(resp).await;
+        assert_eq!(err["error"]["code"], json!("insufficient_leave"));
+        assert_eq!(err["error"]["details"]["remaining"], json!(0));
 
-        let ps: Value = read_body_json(resp).await;
-        let test_pass = ps["pass"].as_str().unwrap();
-        let resp = test::TestRequest::post()
-            .uri("/api/login")
-            .header(header::CONTENT_TYPE, "application/json")
-            .set_json(&json!({"id": user_id, "pass": test_pass }))
+        // withdrawing a future day gives it back
+        let resp = test::TestRequest::patch()
+            .uri(&format!("/api/leave/requests/{}", id))
+            .header("Authorization", format!("bearer {}", staff.token))
+            .set_json(&json!({ "status": "cancelled" }))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
 
-        let ts: Value = read_body_json(resp).await;
-        let token_test = ts["token"].as_str().unwrap();
-        let today = Local::now().naive_local();
-        let resp = test::TestRequest::post()
-            .uri("/api/schedules")
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_test))
-            .set_json(&StartEndWithUser {
-                username: user_id.to_string(),
-                start_time: today,
-                end_time: today.checked_add_signed(Duration::days(1)).unwrap(),
-            })
+        let resp = test::TestRequest::get()
+            .uri(&format!("/api/leave/balances?username={}", staff.id))
+            .header("Authorization", format!("bearer {}", root.token))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
+        let bs: Value = read_body_json(resp).await;
+        assert_eq!(bs[0]["remaining"], json!(1));
+        assert_eq!(bs[0]["grants"][0]["used"], json!(4));
+    }
 
-        let ps: Value = read_body_json(resp).await;
-        let sid = ps["id"].as_i64().unwrap();
+    #[actix_rt::test]
+    async fn test_absence_reasons() {
+        use diesel::ExpressionMethods;
+        use schema::{schedules, wages};
+
+        let db = TestDb::new();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(db.pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+        let root = db.root();
+        let staff = db.staff();
+        let conn = db.conn();
+
+        diesel::insert_into(wages::table)
+            .values((
+                wages::username.eq(&staff.id),
+                wages::hourly_wage.eq(1000),
+                wages::effective_from.eq(NaiveDate::from_ymd(2100, 1, 1)),
+                wages::created_by.eq("root"),
+            ))
+            .execute(&conn)
+            .unwrap();
+        // three approved 4-hour shifts
+        let ids: Vec<i64> = diesel::insert_into(schedules::table)
+            .values(
+                (1..4)
+                    .map(|d| {
+                        let d = NaiveDate::from_ymd(2100, 3, d);
+                        (
+                            schedules::username.eq(&staff.id),
+                            schedules::start_time.eq(from_local(&d.and_hms(9, 0, 0))),
+                            schedules::end_time.eq(from_local(&d.and_hms(13, 0, 0))),
+                            schedules::created_by.eq("root"),
+                            schedules::status.eq(Status::Approved),
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .returning(schedules::id)
+            .get_results(&conn)
+            .unwrap();
 
         let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/permission", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
+            .uri(&format!("/api/schedules/{}/absence", ids[0]))
+            .header("Authorization", format!("bearer {}", staff.token))
+            .set_json(&json!({ "reason": "sick" }))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
 
         let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/absence", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_test))
+            .uri(&format!("/api/schedules/{}/availability", ids[0]))
+            .header("Authorization", format!("bearer {}", root.token))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
 
         let resp = test::TestRequest::get()
-            .uri("/api/schedules")
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_test))
+            .uri(&format!("/api/schedules/{}", ids[0]))
+            .header("Authorization", format!("bearer {}", staff.token))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
+        let d: Value = read_body_json(resp).await;
+        assert_eq!(d["status"], json!("absent_approved"));
+        assert_eq!(d["absence_reason"], json!("sick"));
 
-        let ss: Vec<Schedule> = read_body_json(resp).await;
-        let s = ss.iter().find(|x| x.id == sid).unwrap();
+        // company-ordered rest comes from a manager, with a reason
+        let resp = test::TestRequest::post()
+            .uri(&format!("/api/schedules/{}/transitions", ids[1]))
+            .header("Authorization", format!("bearer {}", staff.token))
+            .set_json(&json!({ "action": "request_absence", "reason": "company_rest" }))
+            .send_request(&mut app)
+            .await;
 
-        assert_eq!(s.absent, true);
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
 
-        let today = Local::now().naive_local();
         let resp = test::TestRequest::post()
-            .uri("/api/schedules")
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
-            .set_json(&StartEndWithUser {
-                username: "root".to_string(),
-                start_time: today,
-                end_time: today.checked_add_signed(Duration::days(1)).unwrap(),
-            })
+            .uri(&format!("/api/schedules/{}/transitions", ids[1]))
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({ "action": "excuse" }))
             .send_request(&mut app)
             .await;
 
-        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
 
-        let ps: Value = read_body_json(resp).await;
-        let sid = ps["id"].as_i64().unwrap();
+        let resp = test::TestRequest::post()
+            .uri(&format!("/api/schedules/{}/transitions", ids[1]))
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({ "action": "excuse", "reason": "company_rest" }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let s: Schedule = read_body_json(resp).await;
+        assert_eq!(s.status, Status::AbsentApproved);
+        assert_eq!(s.absence_reason, Some(Reason::CompanyRest));
 
+        // without a body the reason is left to the manager
         let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/permission", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
+            .uri(&format!("/api/schedules/{}/absence", ids[2]))
+            .header("Authorization", format!("bearer {}", staff.token))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
 
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/absence", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
+        let resp = test::TestRequest::post()
+            .uri(&format!("/api/schedules/{}/transitions", ids[2]))
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({ "action": "approve_absence", "reason": "bereavement" }))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
 
+        for (key, value, status) in [
+            ("absence_pay.sick", "50", StatusCode::OK),
+            ("absence_pay.company_rest", "50", StatusCode::BAD_REQUEST),
+            ("absence_pay.unpaid", "101", StatusCode::BAD_REQUEST),
+        ]
+        .iter()
+        {
+            let resp = test::TestRequest::put()
+                .uri(&format!("/api/settings/{}", key))
+                .header("Authorization", format!("bearer {}", root.token))
+                .set_json(&json!({ "value": value }))
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), *status);
+            if *status == StatusCode::BAD_REQUEST && *key == "absence_pay.company_rest" {
+                let e: Value = read_body_json(resp).await;
+                assert_eq!(
+                    e["error"]["message"],
+                    json!("absence_pay.company_rest: must be at least 60")
+                );
+            }
+        }
+
+        // a value written around the API is an error, not 0%
+        settings::set(&db.conn(), "absence_pay.sick", "half").unwrap();
         let resp = test::TestRequest::get()
-            .uri("/api/schedules")
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
+            .uri("/api/payroll?start=2100-03-01&end=2100-03-04")
+            .header("Authorization", format!("bearer {}", staff.token))
             .send_request(&mut app)
             .await;
 
-        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        settings::set(&db.conn(), "absence_pay.sick", "50").unwrap();
 
-        let ss: Vec<Schedule> = read_body_json(resp).await;
-        let s = ss.iter().find(|x| x.id == sid).unwrap();
+        let resp = test::TestRequest::get()
+            .uri("/api/payroll?start=2100-03-01&end=2100-03-04")
+            .header("Authorization", format!("bearer {}", staff.token))
+            .send_request(&mut app)
+            .await;
 
-        assert_eq!(s.absent, true);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let ps: Value = read_body_json(resp).await;
+        let a = &ps[0]["absences"];
+        assert_eq!(a["sick"]["percent"], json!(50));
+        assert_eq!(a["sick"]["pay"], json!(2000));
+        assert_eq!(a["company_rest"]["pay"], json!(2400));
+        assert_eq!(a["bereavement"]["minutes"], json!(240));
+        assert_eq!(a["bereavement"]["pay"], json!(4000));
+        assert_eq!(ps[0]["absence_pay"], json!(8400));
+        assert_eq!(ps[0]["total"], json!(8400));
     }
 
     #[actix_rt::test]
-    async fn test_normal_system() {
-        let pool = establish_connection().unwrap();
+    async fn test_holiday_calendar() {
+        use diesel::ExpressionMethods;
+        use schema::{punches, wages};
+
+        let db = TestDb::new();
         let pg = create_pg();
         let mut app = test::init_service(
             App::new()
                 .configure(config)
-                .data(pool.clone())
+                .data(db.pool.clone())
                 .data(pg.clone()),
         )
         .await;
+        let root = db.root();
+        let staff = db.staff();
+        let conn = db.conn();
+
+        let holidays = |year| {
+            calendar::national_holidays(year)
+                .into_iter()
+                .map(|(d, n)| (d.format("%m-%d").to_string(), n))
+                .collect::<Vec<_>>()
+        };
+        let h2024 = holidays(2024);
+        assert_eq!(h2024.len(), 21);
+        for x in ["02-12", "05-06", "08-12", "09-23", "11-04"].iter() {
+            assert!(h2024.contains(&(x.to_string(), "振替休日")));
+        }
+        assert!(h2024.contains(&("03-20".to_string(), "春分の日")));
+        assert!(holidays(2026).contains(&("09-22".to_string(), "国民の休日")));
+
+        // outside the years the equinoxes are known for, there are none
+        for uri in [
+            "/api/calendar?start=5000-01-01&end=5000-01-02",
+            "/api/payroll?start=5000-01-01&end=5000-01-02",
+        ]
+        .iter()
+        {
+            let resp = test::TestRequest::get()
+                .uri(uri)
+                .header("Authorization", format!("bearer {}", root.token))
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+        assert!(holidays(1979).is_empty());
 
-        let resp = test::TestRequest::post()
-            .uri("/api/login")
-            .header(header::CONTENT_TYPE, "application/json")
-            .set_payload(r#"{"id":"root", "pass":"pass"}"#.as_bytes())
-            .send_request(&mut app)
-            .await;
-
-        assert_eq!(resp.status(), StatusCode::OK);
-
-        let ts: Value = read_body_json(resp).await;
-        let token_root = ts["token"].as_str().unwrap();
-        let user_id = Uuid::new_v4();
-        let resp = test::TestRequest::post()
-            .uri("/api/users")
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
-            .set_json(&json!({"id": user_id, "isadmin": false }))
+        let resp = test::TestRequest::get()
+            .uri("/api/calendar?start=2019-04-27&end=2019-05-08")
+            .header("Authorization", format!("bearer {}", staff.token))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
+        let es: Value = read_body_json(resp).await;
+        let names: Vec<_> = es
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "昭和の日",
+                "国民の休日",
+                "天皇の即位の日",
+                "国民の休日",
+                "憲法記念日",
+                "みどりの日",
+                "こどもの日",
+                "振替休日"
+            ]
+        );
+
+        for (token, day, kind, status) in [
+            (&staff.token, "2100-03-01", "closed", StatusCode::FORBIDDEN),
+            (&root.token, "2100-03-01", "closed", StatusCode::OK),
+            (&root.token, "2100-03-02", "special", StatusCode::OK),
+            (
+                &root.token,
+                "2100-03-03",
+                "national_holiday",
+                StatusCode::BAD_REQUEST,
+            ),
+        ]
+        .iter()
+        {
+            let resp = test::TestRequest::put()
+                .uri(&format!("/api/calendar/{}", day))
+                .header("Authorization", format!("bearer {}", token))
+                .set_json(&json!({ "kind": kind, "name": "棚卸" }))
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), *status);
+        }
 
-        let ps: Value = read_body_json(resp).await;
-        let test_pass = ps["pass"].as_str().unwrap();
+        let local =
+            |m: u32, d: u32, h: u32| from_local(&NaiveDate::from_ymd(2100, m, d).and_hms(h, 0, 0));
         let resp = test::TestRequest::post()
-            .uri("/api/login")
-            .header(header::CONTENT_TYPE, "application/json")
-            .set_json(&json!({"id": user_id, "pass": test_pass }))
+            .uri("/api/schedules")
+            .header("Authorization", format!("bearer {}", staff.token))
+            .set_json(&json!({
+                "username": staff.id,
+                "start_time": local(3, 1, 9),
+                "end_time": local(3, 1, 13),
+            }))
             .send_request(&mut app)
             .await;
 
-        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let err: Value = read_body_json(resp).await;
+        assert_eq!(err["error"]["code"], json!("store_closed"));
 
-        let ts: Value = read_body_json(resp).await;
-        let token_test = ts["token"].as_str().unwrap();
-        let today = Local::now().naive_local();
+        // nor can a shift be moved onto it
         let resp = test::TestRequest::post()
             .uri("/api/schedules")
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_test))
-            .set_json(&StartEndWithUser {
-                username: user_id.to_string(),
-                start_time: today,
-                end_time: today.checked_add_signed(Duration::days(1)).unwrap(),
-            })
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({
+                "username": staff.id,
+                "start_time": local(3, 2, 9),
+                "end_time": local(3, 2, 13),
+            }))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
-
-        let ps: Value = read_body_json(resp).await;
-        let sid = ps["id"].as_i64().unwrap();
+        let s: Value = read_body_json(resp).await;
         let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/duration", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
-            .set_json(&StartEnd {
-                start_time: today,
-                end_time: today.checked_add_signed(Duration::days(2)).unwrap(),
-            })
+            .uri(&format!("/api/schedules/{}/duration", s["id"]))
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({
+                "start_time": local(3, 1, 9),
+                "end_time": local(3, 1, 13),
+            }))
             .send_request(&mut app)
             .await;
 
-        assert_ne!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let err: Value = read_body_json(resp).await;
+        assert_eq!(err["error"]["code"], json!("store_closed"));
+        diesel::delete(
+            schema::schedules::table.filter(schema::schedules::id.eq(s["id"].as_i64().unwrap())),
+        )
+        .execute(&conn)
+        .unwrap();
+
+        // four hours on 元日 and four on the special day
+        for (key, value) in [
+            ("holiday_premium_percent", "35"),
+            ("special_day_premium_percent", "25"),
+        ]
+        .iter()
+        {
+            let resp = test::TestRequest::put()
+                .uri(&format!("/api/settings/{}", key))
+                .header("Authorization", format!("bearer {}", root.token))
+                .set_json(&json!({ "value": value }))
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+        diesel::insert_into(wages::table)
+            .values((
+                wages::username.eq(&staff.id),
+                wages::hourly_wage.eq(1000),
+                wages::effective_from.eq(NaiveDate::from_ymd(2100, 1, 1)),
+                wages::created_by.eq("root"),
+            ))
+            .execute(&conn)
+            .unwrap();
+        diesel::insert_into(punches::table)
+            .values(
+                vec![
+                    ("clock_in", local(1, 1, 9)),
+                    ("clock_out", local(1, 1, 13)),
+                    ("clock_in", local(3, 2, 9)),
+                    ("clock_out", local(3, 2, 13)),
+                ]
+                .into_iter()
+                .map(|(k, t)| {
+                    (
+                        punches::username.eq(&staff.id),
+                        punches::kind.eq(k),
+                        punches::punched_at.eq(t),
+                    )
+                })
+                .collect::<Vec<_>>(),
+            )
+            .execute(&conn)
+            .unwrap();
 
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/duration", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_test))
-            .set_json(&StartEnd {
-                start_time: today,
-                end_time: today.checked_add_signed(Duration::days(2)).unwrap(),
-            })
+        let resp = test::TestRequest::get()
+            .uri("/api/payroll?start=2100-01-01&end=2100-04-01")
+            .header("Authorization", format!("bearer {}", staff.token))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
+        let ps: Value = read_body_json(resp).await;
+        assert_eq!(ps[0]["regular_pay"], json!(8000));
+        assert_eq!(ps[0]["holiday_minutes"], json!(480));
+        assert_eq!(ps[0]["holiday_pay"], json!(2400));
+        assert_eq!(ps[0]["total"], json!(10400));
+    }
 
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/absence", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_test))
-            .send_request(&mut app)
-            .await;
+    #[actix_rt::test]
+    async fn test_attendance_exceptions() {
+        use diesel::ExpressionMethods;
+        use schema::{punches, schedules};
 
-        assert_ne!(resp.status(), StatusCode::OK);
+        let db = TestDb::new();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(db.pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+        let root = db.root();
+        let conn = db.conn();
+        let (a, b, c, d, e) = (db.staff(), db.staff(), db.staff(), db.staff(), db.staff());
+
+        let local = |h: u32, m: u32| from_local(&NaiveDate::from_ymd(2020, 6, 1).and_hms(h, m, 0));
+        diesel::insert_into(schedules::table)
+            .values(
+                vec![
+                    (&a.id, Status::Approved),
+                    (&b.id, Status::Approved),
+                    (&c.id, Status::Approved),
+                    (&e.id, Status::AbsentApproved),
+                ]
+                .into_iter()
+                .map(|(u, status)| {
+                    (
+                        schedules::username.eq(u),
+                        schedules::start_time.eq(local(9, 0)),
+                        schedules::end_time.eq(local(13, 0)),
+                        schedules::created_by.eq("root"),
+                        schedules::status.eq(status),
+                    )
+                })
+                .collect::<Vec<_>>(),
+            )
+            .execute(&conn)
+            .unwrap();
+        diesel::insert_into(punches::table)
+            .values(
+                vec![
+                    (&a.id, "clock_in", local(9, 20)),
+                    (&a.id, "clock_out", local(12, 30)),
+                    (&c.id, "clock_in", local(9, 3)),
+                    (&d.id, "clock_in", local(10, 0)),
+                    (&d.id, "clock_out", local(11, 0)),
+                ]
+                .into_iter()
+                .map(|(u, k, t)| {
+                    (
+                        punches::username.eq(u),
+                        punches::kind.eq(k),
+                        punches::punched_at.eq(t),
+                    )
+                })
+                .collect::<Vec<_>>(),
+            )
+            .execute(&conn)
+            .unwrap();
 
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/absence", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
+        let resp = test::TestRequest::get()
+            .uri("/api/exceptions?date=2020-06-01")
+            .header("Authorization", format!("bearer {}", root.token))
             .send_request(&mut app)
             .await;
 
-        assert_ne!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let es: Value = read_body_json(resp).await;
+        let mut found: Vec<_> = es
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| {
+                (
+                    x["username"].as_str().unwrap().to_string(),
+                    x["kind"].as_str().unwrap().to_string(),
+                    x["minutes"].as_i64(),
+                )
+            })
+            .collect();
+        found.sort();
+        let mut expected = vec![
+            (a.id.clone(), "late".to_string(), Some(20)),
+            (a.id.clone(), "early_leave".to_string(), Some(30)),
+            (b.id.clone(), "no_show".to_string(), None),
+            (c.id.clone(), "missing_clock_out".to_string(), None),
+            (d.id.clone(), "unscheduled".to_string(), None),
+        ];
+        expected.sort();
+        assert_eq!(found, expected);
+
+        for (body, status) in [
+            (
+                json!({ "username": b.id, "date": "2020-06-01", "kind": "no_show", "note": " " }),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                json!({ "username": d.id, "date": "2020-06-01", "kind": "late", "note": "ok" }),
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                json!({ "username": b.id, "date": "2020-06-01", "kind": "no_show", "note": "電話連絡あり" }),
+                StatusCode::OK,
+            ),
+        ]
+        .iter()
+        {
+            let resp = test::TestRequest::post()
+                .uri("/api/exceptions/resolutions")
+                .header("Authorization", format!("bearer {}", root.token))
+                .set_json(body)
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), *status);
+        }
 
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/permission", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_test))
+        let resp = test::TestRequest::put()
+            .uri("/api/settings/exception_grace_minutes")
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({ "value": "25" }))
             .send_request(&mut app)
             .await;
 
-        assert_ne!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::OK);
 
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/permission", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
+        let resp = test::TestRequest::get()
+            .uri("/api/exceptions?date=2020-06-01&unresolved=true")
+            .header("Authorization", format!("bearer {}", root.token))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
+        let es: Value = read_body_json(resp).await;
+        let kinds: Vec<_> = es
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|x| x["username"] == json!(a.id) || x["username"] == json!(b.id))
+            .map(|x| x["kind"].as_str().unwrap())
+            .collect();
+        // 20 minutes late is within the grace now, and the no-show is resolved
+        assert_eq!(kinds, vec!["early_leave"]);
 
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/duration", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_test))
-            .set_json(&StartEnd {
-                start_time: today,
-                end_time: today.checked_add_signed(Duration::days(2)).unwrap(),
-            })
+        let resp = test::TestRequest::get()
+            .uri("/api/exceptions?date=2020-06-01")
+            .header("Authorization", format!("bearer {}", e.token))
             .send_request(&mut app)
             .await;
 
-        assert_ne!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
 
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/duration", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
-            .set_json(&StartEnd {
-                start_time: today,
-                end_time: today.checked_add_signed(Duration::days(2)).unwrap(),
-            })
-            .send_request(&mut app)
-            .await;
+    #[actix_rt::test]
+    async fn test_punch_corrections() {
+        use diesel::ExpressionMethods;
+        use schema::{periods, punches};
 
-        assert_ne!(resp.status(), StatusCode::OK);
+        let db = TestDb::new();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(db.pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+        let root = db.root();
+        let conn = db.conn();
+        let (a, b) = (db.staff(), db.staff());
+
+        let local = |h: u32, m: u32| from_local(&NaiveDate::from_ymd(2020, 6, 1).and_hms(h, m, 0));
+        let clock_in = diesel::insert_into(punches::table)
+            .values((
+                punches::username.eq(&a.id),
+                punches::kind.eq("clock_in"),
+                punches::punched_at.eq(local(9, 0)),
+            ))
+            .get_result::<Punch>(&conn)
+            .unwrap();
+
+        for (body, status) in [
+            (
+                json!({ "kind": "clock_out", "punched_at": local(18, 0), "reason": " " }),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                json!({ "punch_id": clock_in.id, "kind": "clock_in", "punched_at": local(8, 30), "reason": "打刻忘れ" }),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                json!({ "kind": "clock_out", "punched_at": Utc::now().naive_utc() + Duration::hours(1), "reason": "打刻忘れ" }),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                json!({ "punch_id": clock_in.id, "punched_at": local(8, 30), "reason": "打刻忘れ", "username": a.id }),
+                StatusCode::FORBIDDEN,
+            ),
+        ]
+        .iter()
+        {
+            let resp = test::TestRequest::post()
+                .uri("/api/punch-corrections")
+                .header("Authorization", format!("bearer {}", b.token))
+                .set_json(body)
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), *status);
+        }
 
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/absence", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
+        let resp = test::TestRequest::post()
+            .uri("/api/punch-corrections")
+            .header("Authorization", format!("bearer {}", a.token))
+            .set_json(&json!({ "kind": "clock_out", "punched_at": local(18, 0), "reason": "退勤打刻忘れ" }))
             .send_request(&mut app)
             .await;
 
-        assert_ne!(resp.status(), StatusCode::OK);
-
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/absence", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_test))
+        assert_eq!(resp.status(), StatusCode::OK);
+        let c: PunchCorrection = read_body_json(resp).await;
+        assert_eq!(c.status, "requested");
+
+        let worktime = |token: String| {
+            test::TestRequest::get()
+                .uri("/api/worktime?start=2020-06-01&end=2020-06-02")
+                .header("Authorization", format!("bearer {}", token))
+                .to_request()
+        };
+        let resp = test::call_service(&mut app, worktime(a.token.clone())).await;
+        let days: Value = read_body_json(resp).await;
+        assert_eq!(days[0]["clock_out"], Value::Null);
+        assert_eq!(days[0]["split"]["worked"], 0);
+
+        for (token, status) in [
+            (b.token.clone(), StatusCode::NOT_FOUND),
+            (a.token.clone(), StatusCode::FORBIDDEN),
+            (root.token.clone(), StatusCode::OK),
+        ]
+        .iter()
+        {
+            let resp = test::TestRequest::patch()
+                .uri(&format!("/api/punch-corrections/{}", c.id))
+                .header("Authorization", format!("bearer {}", token))
+                .set_json(&json!({ "status": "approved" }))
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), *status);
+        }
+
+        let resp = test::call_service(&mut app, worktime(a.token.clone())).await;
+        let days: Value = read_body_json(resp).await;
+        assert_eq!(days[0]["clock_out"], json!(local(18, 0)));
+        assert_eq!(days[0]["split"]["worked"], 9 * 60);
+
+        // a manager's correction is approved right away; the punch itself is kept
+        let resp = test::TestRequest::post()
+            .uri("/api/punch-corrections")
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({ "punch_id": clock_in.id, "punched_at": local(9, 30), "reason": "遅刻", "username": a.id }))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
+        let moved: PunchCorrection = read_body_json(resp).await;
+        assert_eq!(moved.status, "approved");
+
+        let resp = test::call_service(&mut app, worktime(a.token.clone())).await;
+        let days: Value = read_body_json(resp).await;
+        assert_eq!(days[0]["clock_in"], json!(local(9, 30)));
+        assert_eq!(days[0]["split"]["worked"], 8 * 60 + 30);
+        let kept = diesel::QueryDsl::filter(punches::table, punches::username.eq(&a.id))
+            .get_results::<Punch>(&conn)
+            .unwrap();
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].punched_at, local(9, 0));
+
+        // a second clock in may be requested, but not approved
+        let resp = test::TestRequest::post()
+            .uri("/api/punch-corrections")
+            .header("Authorization", format!("bearer {}", a.token))
+            .set_json(
+                &json!({ "kind": "clock_in", "punched_at": local(10, 0), "reason": "打刻忘れ" }),
+            )
+            .send_request(&mut app)
+            .await;
 
+        assert_eq!(resp.status(), StatusCode::OK);
+        let twice: PunchCorrection = read_body_json(resp).await;
         let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/availability", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_test))
+            .uri(&format!("/api/punch-corrections/{}", twice.id))
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({ "status": "approved" }))
             .send_request(&mut app)
             .await;
 
-        assert_ne!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "invalid_punch_sequence");
+        assert_eq!(body["error"]["details"]["punched_at"], json!(local(10, 0)));
 
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/availability", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
+        let resp = test::TestRequest::post()
+            .uri("/api/punch-corrections")
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({ "kind": "break_end", "punched_at": local(13, 0), "reason": "休憩", "username": a.id }))
             .send_request(&mut app)
             .await;
 
-        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
 
+        // moving the clock out before the clock in is no better
         let resp = test::TestRequest::post()
-            .uri("/api/schedules")
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
-            .set_json(&StartEndWithUser {
-                username: user_id.to_string(),
-                start_time: today,
-                end_time: today.checked_add_signed(Duration::days(1)).unwrap(),
-            })
+            .uri("/api/punch-corrections")
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({ "kind": "clock_out", "punched_at": local(8, 0), "reason": "退勤", "username": a.id }))
             .send_request(&mut app)
             .await;
 
-        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
 
-        let ps: Value = read_body_json(resp).await;
-        let sid = ps["id"].as_i64().unwrap();
+        let resp = test::call_service(&mut app, worktime(a.token.clone())).await;
+        let days: Value = read_body_json(resp).await;
+        assert_eq!(days[0]["split"]["worked"], 8 * 60 + 30);
+
+        // taking back an approval is checked the same way: without the clock
+        // out at noon, the next day's clock in after lunch would follow a clock in
+        let next = |h: u32| local(h, 0) + Duration::days(1);
+        diesel::insert_into(punches::table)
+            .values((
+                punches::username.eq(&a.id),
+                punches::kind.eq("clock_in"),
+                punches::punched_at.eq(next(9)),
+            ))
+            .execute(&conn)
+            .unwrap();
+        let mut added = vec![];
+        for (kind, h) in [("clock_out", 12), ("clock_in", 13)].iter() {
+            let resp = test::TestRequest::post()
+                .uri("/api/punch-corrections")
+                .header("Authorization", format!("bearer {}", root.token))
+                .set_json(&json!({ "kind": kind, "punched_at": next(*h), "reason": "打刻忘れ", "username": a.id }))
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), StatusCode::OK);
+            added.push(read_body_json::<PunchCorrection, _>(resp).await);
+        }
         let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/duration", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_test))
-            .set_json(&StartEnd {
-                start_time: today,
-                end_time: today.checked_add_signed(Duration::days(2)).unwrap(),
-            })
+            .uri(&format!("/api/punch-corrections/{}", added[0].id))
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({ "status": "cancelled" }))
             .send_request(&mut app)
             .await;
 
-        assert_ne!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = read_body_json(resp).await;
+        assert_eq!(body["error"]["details"]["punched_at"], json!(next(13)));
 
         let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/duration", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
-            .set_json(&StartEnd {
-                start_time: today,
-                end_time: today.checked_add_signed(Duration::days(2)).unwrap(),
-            })
+            .uri(&format!("/api/punch-corrections/{}", added[1].id))
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({ "status": "cancelled" }))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
 
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/absence", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_test))
+        // submissions ended long ago, but attendance stays open until the close
+        let pid = diesel::insert_into(periods::table)
+            .values((
+                periods::start_date.eq(NaiveDate::from_ymd(2020, 6, 1)),
+                periods::end_date.eq(NaiveDate::from_ymd(2020, 6, 30)),
+                periods::deadline.eq(local(0, 0) - Duration::days(14)),
+                periods::created_by.eq("root"),
+            ))
+            .returning(periods::id)
+            .get_result::<i64>(&conn)
+            .unwrap();
+        let break_start =
+            json!({ "kind": "break_start", "punched_at": local(12, 0), "reason": "休憩" });
+        let resp = test::TestRequest::post()
+            .uri("/api/punch-corrections")
+            .header("Authorization", format!("bearer {}", a.token))
+            .set_json(&break_start)
             .send_request(&mut app)
             .await;
 
-        assert_ne!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::OK);
 
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/absence", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
+        // as `kintai-admin period close` does
+        let p = period::close(&conn, pid, Utc::now().naive_utc()).unwrap();
+        assert!(p.closed_at.is_some());
+
+        let resp = test::TestRequest::post()
+            .uri("/api/punch-corrections")
+            .header("Authorization", format!("bearer {}", a.token))
+            .set_json(&break_start)
             .send_request(&mut app)
             .await;
 
-        assert_ne!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let body: Value = read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "period_closed");
 
         let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/permission", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
+            .uri(&format!("/api/punch-corrections/{}", moved.id))
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({ "status": "cancelled" }))
             .send_request(&mut app)
             .await;
 
-        assert_ne!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
 
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/permission", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_test))
+        let resp = test::TestRequest::get()
+            .uri("/api/punch-corrections?start=2020-06-01&end=2020-06-02&status=approved")
+            .header("Authorization", format!("bearer {}", a.token))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
+        let cs: Vec<PunchCorrection> = read_body_json(resp).await;
+        assert_eq!(
+            cs.iter().map(|c| c.id).collect::<Vec<_>>(),
+            vec![moved.id, c.id]
+        );
+    }
 
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/duration", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_test))
-            .set_json(&StartEnd {
-                start_time: today,
-                end_time: today.checked_add_signed(Duration::days(2)).unwrap(),
-            })
+    #[actix_rt::test]
+    async fn test_time_rounding() {
+        use diesel::ExpressionMethods;
+        use schema::{punches, wages};
+
+        let db = TestDb::new();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(db.pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+        let root = db.root();
+        let conn = db.conn();
+        let staff = db.staff();
+
+        let local =
+            |d: u32, h: u32, m: u32| from_local(&NaiveDate::from_ymd(2020, 6, d).and_hms(h, m, 0));
+        diesel::insert_into(wages::table)
+            .values((
+                wages::username.eq(&staff.id),
+                wages::hourly_wage.eq(1000),
+                wages::effective_from.eq(NaiveDate::from_ymd(2020, 1, 1)),
+                wages::created_by.eq("root"),
+            ))
+            .execute(&conn)
+            .unwrap();
+        diesel::insert_into(punches::table)
+            .values(
+                vec![
+                    ("clock_in", local(1, 8, 52)),
+                    ("clock_out", local(1, 17, 8)),
+                    ("clock_in", local(2, 9, 0)),
+                    ("clock_out", local(2, 19, 20)),
+                ]
+                .into_iter()
+                .map(|(k, t)| {
+                    (
+                        punches::username.eq(&staff.id),
+                        punches::kind.eq(k),
+                        punches::punched_at.eq(t),
+                    )
+                })
+                .collect::<Vec<_>>(),
+            )
+            .execute(&conn)
+            .unwrap();
+
+        for (key, value, status) in [
+            ("rounding.clock_in", "7", StatusCode::BAD_REQUEST),
+            ("rounding.clock_in", "15", StatusCode::OK),
+            ("rounding.clock_out", "15", StatusCode::OK),
+            ("rounding.daily", "30", StatusCode::OK),
+            ("rounding.monthly", "true", StatusCode::OK),
+        ]
+        .iter()
+        {
+            let resp = test::TestRequest::put()
+                .uri(&format!("/api/settings/{}", key))
+                .header("Authorization", format!("bearer {}", root.token))
+                .set_json(&json!({ "value": value }))
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), *status);
+        }
+
+        let resp = test::TestRequest::get()
+            .uri("/api/worktime?start=2020-06-01&end=2020-06-03")
+            .header("Authorization", format!("bearer {}", staff.token))
             .send_request(&mut app)
             .await;
 
-        assert_ne!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let days: Value = read_body_json(resp).await;
+        assert_eq!(days[0]["clock_in"], json!(local(1, 8, 52)));
+        assert_eq!(days[0]["rounded_clock_in"], json!(local(1, 9, 0)));
+        assert_eq!(days[0]["rounded_clock_out"], json!(local(1, 17, 0)));
+        assert_eq!(days[0]["split"]["worked"], 8 * 60 + 16);
+        assert_eq!(days[0]["rounded"]["worked"], 8 * 60);
+        // 09:00-19:15 is 615 minutes, 630 to the nearest half hour
+        assert_eq!(days[1]["split"]["worked"], 10 * 60 + 20);
+        assert_eq!(days[1]["rounded"]["worked"], 10 * 60 + 30);
+        assert_eq!(days[1]["rounded"]["overtime"], 2 * 60 + 30);
 
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/duration", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
-            .set_json(&StartEnd {
-                start_time: today,
-                end_time: today.checked_add_signed(Duration::days(2)).unwrap(),
-            })
+        let resp = test::TestRequest::get()
+            .uri("/api/payroll?start=2020-06-01&end=2020-07-01")
+            .header("Authorization", format!("bearer {}", root.token))
             .send_request(&mut app)
             .await;
 
-        assert_ne!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let ps: Value = read_body_json(resp).await;
+        let p = &ps[0];
+        assert_eq!(p["raw_worked_minutes"], 8 * 60 + 16 + 10 * 60 + 20);
+        assert_eq!(p["raw_overtime_minutes"], 16 + 2 * 60 + 20);
+        // 2:30 of overtime in the month is paid as 3 hours
+        assert_eq!(p["overtime_minutes"], 3 * 60);
+        assert_eq!(p["worked_minutes"], 19 * 60);
+        assert_eq!(p["regular_pay"], 16000);
+        assert_eq!(p["overtime_pay"], 3750);
+        assert_eq!(p["total"], 19750);
+
+        // each whole month is rounded on its own; a part of one is not
+        for (range, overtime) in [
+            ("start=2020-05-01&end=2020-08-01", 3 * 60),
+            ("start=2020-06-01&end=2020-06-03", 2 * 60 + 30),
+            ("start=2020-05-15&end=2020-06-15", 2 * 60 + 30),
+        ]
+        .iter()
+        {
+            let resp = test::TestRequest::get()
+                .uri(&format!("/api/payroll?{}", range))
+                .header("Authorization", format!("bearer {}", root.token))
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), StatusCode::OK);
+            let ps: Value = read_body_json(resp).await;
+            assert_eq!(ps[0]["overtime_minutes"], *overtime, "{}", range);
+        }
+    }
 
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/absence", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
+    #[actix_rt::test]
+    async fn test_kiosk_punches() {
+        use diesel::ExpressionMethods;
+        use schema::users;
+
+        let db = TestDb::new();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(db.pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+        let root = db.root();
+        let conn = db.conn();
+        let staff = db.staff();
+        diesel::update(diesel::QueryDsl::filter(
+            users::table,
+            users::id.eq(&staff.id),
+        ))
+        .set(users::employee_number.eq("E001"))
+        .execute(&conn)
+        .unwrap();
+
+        for (body, status) in [
+            (
+                json!({ "pin": "1234", "password": "wrong" }),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                json!({ "pin": "12a4", "password": staff.pass }),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                json!({ "pin": "2580", "password": staff.pass }),
+                StatusCode::OK,
+            ),
+        ]
+        .iter()
+        {
+            let resp = test::TestRequest::put()
+                .uri("/api/users/me/pin")
+                .header("Authorization", format!("bearer {}", staff.token))
+                .set_json(body)
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), *status);
+        }
+
+        let resp = test::TestRequest::post()
+            .uri("/api/kiosks")
+            .header("Authorization", format!("bearer {}", staff.token))
+            .set_json(&json!({ "name": "レジ横" }))
             .send_request(&mut app)
             .await;
 
-        assert_ne!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
 
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/absence", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_test))
+        let resp = test::TestRequest::post()
+            .uri("/api/kiosks")
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({ "name": "レジ横" }))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
+        let body: Value = read_body_json(resp).await;
+        let token = body["token"].as_str().unwrap().to_string();
+        let kiosk_id = body["kiosk"]["id"].as_i64().unwrap();
+        assert!(body["kiosk"].get("token").is_none());
+
+        let punch = |token: &str, pin: &str, kind: &str| {
+            test::TestRequest::post()
+                .uri("/api/kiosk/punches")
+                .header("X-Kiosk-Token", token)
+                .set_json(&json!({ "employee_number": "E001", "pin": pin, "kind": kind }))
+                .to_request()
+        };
+        let resp = test::call_service(&mut app, punch("unknown", "2580", "clock_in")).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = test::call_service(&mut app, punch(&token, "2580", "clock_in")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let p: Punch = read_body_json(resp).await;
+        assert_eq!(p.username, staff.id);
+        assert_eq!(p.kiosk_id, Some(kiosk_id));
 
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/availability", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_test))
+        for _ in 0..kiosk::MAX_PIN_FAILURES {
+            let resp = test::call_service(&mut app, punch(&token, "0000", "break_start")).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        }
+        // locked out even with the right PIN
+        let resp = test::call_service(&mut app, punch(&token, "2580", "break_start")).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let body: Value = read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "pin_locked");
+
+        // a manager resetting the PIN lifts the lockout
+        let resp = test::TestRequest::put()
+            .uri(&format!("/api/users/{}/pin", staff.id))
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({ "pin": "1357" }))
             .send_request(&mut app)
             .await;
 
-        assert_ne!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::OK);
 
-        let resp = test::TestRequest::patch()
-            .uri(&format!("/api/schedules/{}/availability", sid))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
+        let resp = test::call_service(&mut app, punch(&token, "1357", "clock_out")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::TestRequest::delete()
+            .uri(&format!("/api/kiosks/{}", kiosk_id))
+            .header("Authorization", format!("bearer {}", root.token))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::call_service(&mut app, punch(&token, "1357", "clock_in")).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
     }
 
     #[actix_rt::test]
-    async fn test_delete_user() {
-        let pool = establish_connection().unwrap();
+    async fn test_qr_punches() {
+        // .env gives the QR codes a key of their own
+        assert_eq!(qr::misconfigured(), None);
+        let db = TestDb::new();
         let pg = create_pg();
         let mut app = test::init_service(
             App::new()
                 .configure(config)
-                .data(pool.clone())
+                .data(db.pool.clone())
                 .data(pg.clone()),
         )
         .await;
+        let root = db.root();
+        let staff = db.staff();
 
         let resp = test::TestRequest::post()
-            .uri("/api/login")
-            .header(header::CONTENT_TYPE, "application/json")
-            .set_payload(r#"{"id":"root", "pass":"pass"}"#.as_bytes())
+            .uri("/api/kiosks")
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({ "name": "入口" }))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
+        let body: Value = read_body_json(resp).await;
+        let token = body["token"].as_str().unwrap().to_string();
+        let kiosk_id = body["kiosk"]["id"].as_i64().unwrap();
 
-        let ts: Value = read_body_json(resp).await;
-        let token_root = ts["token"].as_str().unwrap();
-        let user_id = Uuid::new_v4();
-        let resp = test::TestRequest::post()
-            .uri("/api/users")
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
-            .set_json(&json!({"id": user_id, "isadmin": false }))
+        let resp = test::TestRequest::get()
+            .uri("/api/kiosk/qr")
+            .header("X-Kiosk-Token", token.as_str())
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
+        let code: Value = read_body_json(resp).await;
+        let payload = code["payload"].as_str().unwrap().to_string();
+
+        let stale = qr::issue(
+            kiosk_id,
+            &(Utc::now().naive_utc() - Duration::seconds(qr::VALID_SECONDS + qr::ROTATE_SECONDS)),
+        )
+        .unwrap()
+        .payload;
+        let mut forged = payload.clone();
+        forged.pop();
+        // a well-formed code, but signed with the login key
+        let now = Utc::now().naive_utc().timestamp();
+        let login_signed = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &json!({ "kiosk": kiosk_id, "iat": now, "exp": now + qr::ROTATE_SECONDS }),
+            &jsonwebtoken::EncodingKey::from_secret(env::var("JWT_SECRET").unwrap().as_ref()),
+        )
+        .unwrap();
+        for (payload, code) in [
+            (stale, "qr_expired"),
+            (forged, "invalid_qr"),
+            (login_signed, "invalid_qr"),
+            (staff.token.clone(), "invalid_qr"),
+        ]
+        .iter()
+        {
+            let resp = test::TestRequest::post()
+                .uri("/api/users/me/punches/qr")
+                .header("Authorization", format!("bearer {}", staff.token))
+                .set_json(&json!({ "payload": payload, "kind": "clock_in" }))
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+            let body: Value = read_body_json(resp).await;
+            assert_eq!(body["error"]["code"], *code);
+        }
 
-        let ps: Value = read_body_json(resp).await;
-        let test_pass = ps["pass"].as_str().unwrap();
         let resp = test::TestRequest::post()
-            .uri("/api/login")
-            .header(header::CONTENT_TYPE, "application/json")
-            .set_json(&json!({"id": user_id, "pass": test_pass }))
+            .uri("/api/users/me/punches/qr")
+            .header("Authorization", format!("bearer {}", staff.token))
+            .set_json(&json!({ "payload": payload, "kind": "clock_in" }))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
+        let p: Punch = read_body_json(resp).await;
+        assert_eq!(p.username, staff.id);
+        assert_eq!(p.kiosk_id, Some(kiosk_id));
 
-        let ts: Value = read_body_json(resp).await;
-        let token_test = ts["token"].as_str().unwrap();
-        let today = Local::now().naive_local();
-        let resp = test::TestRequest::post()
-            .uri("/api/schedules")
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_test))
-            .set_json(&StartEndWithUser {
-                username: user_id.to_string(),
-                start_time: today,
-                end_time: today.checked_add_signed(Duration::days(1)).unwrap(),
-            })
+        let resp = test::TestRequest::delete()
+            .uri(&format!("/api/kiosks/{}", kiosk_id))
+            .header("Authorization", format!("bearer {}", root.token))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::OK);
 
-        let resp = test::TestRequest::delete()
-            .uri(&format!("/api/users/{}", user_id))
-            .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_test))
+        let resp = test::TestRequest::post()
+            .uri("/api/users/me/punches/qr")
+            .header("Authorization", format!("bearer {}", staff.token))
+            .set_json(&json!({ "payload": payload, "kind": "clock_out" }))
             .send_request(&mut app)
             .await;
 
         assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
 
-        let resp = test::TestRequest::delete()
-            .uri(&format!("/api/users/{}", user_id))
+    #[actix_rt::test]
+    async fn test_punch_networks() {
+        let db = TestDb::new();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(db.pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+        let root = db.root();
+        let staff = db.staff();
+
+        for (key, value, status) in [
+            ("punch_networks", "10.0.0.0/33", StatusCode::BAD_REQUEST),
+            (
+                "punch_networks",
+                "203.0.113.0/24, 2001:db8::/32",
+                StatusCode::OK,
+            ),
+            ("punch_outside_network", "ignore", StatusCode::BAD_REQUEST),
+        ]
+        .iter()
+        {
+            let resp = test::TestRequest::put()
+                .uri(&format!("/api/settings/{}", key))
+                .header("Authorization", format!("bearer {}", root.token))
+                .set_json(&json!({ "value": value }))
+                .send_request(&mut app)
+                .await;
+
+            assert_eq!(resp.status(), *status);
+        }
+
+        let punch = |peer: &str, forwarded_for: Option<&str>, kind: &str| {
+            let mut req = test::TestRequest::post()
+                .uri("/api/users/me/punches")
+                .peer_addr(peer.parse().unwrap())
+                .header("Authorization", format!("bearer {}", staff.token))
+                .set_json(&json!({ "kind": kind }));
+            if let Some(x) = forwarded_for {
+                req = req.header("X-Forwarded-For", x);
+            }
+            req.to_request()
+        };
+        let resp = test::call_service(&mut app, punch("203.0.113.7:5000", None, "clock_in")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let p: Punch = read_body_json(resp).await;
+        assert_eq!(p.client_ip.as_deref(), Some("203.0.113.7"));
+        assert!(!p.off_network);
+
+        // without a trusted proxy the header is not believed
+        let resp = test::call_service(
+            &mut app,
+            punch("198.51.100.1:5000", Some("203.0.113.7"), "break_start"),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let body: Value = read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "outside_network");
+
+        let resp = test::TestRequest::put()
+            .uri("/api/settings/trusted_proxy_hops")
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({ "value": "1" }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        for (forwarded_for, status) in [
+            // the client's own entry comes before the one the router appends
+            ("203.0.113.9, 198.51.100.1", StatusCode::FORBIDDEN),
+            ("198.51.100.1, 203.0.113.9", StatusCode::OK),
+        ]
+        .iter()
+        {
+            let resp = test::call_service(
+                &mut app,
+                punch("10.1.2.3:5000", Some(forwarded_for), "break_start"),
+            )
+            .await;
+            assert_eq!(resp.status(), *status);
+        }
+
+        let resp = test::TestRequest::put()
+            .uri("/api/settings/punch_outside_network")
+            .header("Authorization", format!("bearer {}", root.token))
+            .set_json(&json!({ "value": "flag" }))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::call_service(
+            &mut app,
+            punch("10.1.2.3:5000", Some("198.51.100.1"), "break_end"),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let p: Punch = read_body_json(resp).await;
+        assert_eq!(p.client_ip.as_deref(), Some("198.51.100.1"));
+        assert!(p.off_network);
+
+        let resp = test::TestRequest::get()
+            .uri(&format!(
+                "/api/exceptions?date={}",
+                to_local(&p.punched_at).date()
+            ))
+            .header("Authorization", format!("bearer {}", root.token))
+            .send_request(&mut app)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let es: Value = read_body_json(resp).await;
+        assert!(es
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|e| e["username"] == json!(staff.id)
+                && e["kind"] == "off_network"
+                && e["actual"] == json!(p.punched_at)));
+    }
+
+    #[actix_rt::test]
+    async fn test_seed() {
+        use kintai::seed;
+
+        let db = TestDb::new();
+        let pool = db.pool.clone();
+        let pg = create_pg();
+        let mut app = test::init_service(
+            App::new()
+                .configure(config)
+                .data(pool.clone())
+                .data(pg.clone()),
+        )
+        .await;
+
+        let opts = seed::Options {
+            seed: 1,
+            staff: 4,
+            month: NaiveDate::from_ymd(2021, 8, 1),
+            today: NaiveDate::from_ymd(2021, 8, 16),
+            password: "demo-pass".to_string(),
+            created_by: "root".to_string(),
+        };
+        let planned = seed::plan(&opts);
+        assert_eq!(planned, seed::plan(&opts));
+        assert_eq!(
+            seed::plan(&seed::Options {
+                staff: 5,
+                ..opts.clone()
+            })[..4],
+            planned[..]
+        );
+
+        let conn = pool.get().unwrap();
+        let summary = seed::run(&conn, &opts).unwrap();
+        assert_eq!(summary.users, 4);
+        assert_eq!(
+            summary.schedules,
+            planned.iter().map(|s| s.shifts.len()).sum::<usize>()
+        );
+        assert!(summary.punches > 0);
+        assert_eq!(seed::run(&conn, &opts).unwrap(), seed::Summary::default());
+
+        let resp = test::TestRequest::post()
+            .uri("/api/login")
             .header(header::CONTENT_TYPE, "application/json")
-            .header("Authorization", format!("bearer {}", token_root))
+            .set_json(&json!({"id": seed::username(opts.seed, 0), "pass": "demo-pass" }))
             .send_request(&mut app)
             .await;
 
@@ -5,6 +5,9 @@ pub struct User {
     pub first_name: Option<String>,
     pub last_name: Option<String>,
     pub isadmin: bool,
+    pub employee_number: Option<String>,
+    pub hire_date: Option<chrono::NaiveDate>,
+    pub deactivated_at: Option<chrono::NaiveDateTime>,
 }
 
 use super::schema::users;
@@ -21,6 +24,8 @@ pub struct NewUser<'a> {
 
 use super::schema::schedules;
 
+use super::absence::Reason;
+use super::schedule::Status;
 use serde::{Deserialize, Serialize};
 #[derive(Queryable, Associations, Serialize, Deserialize, Debug)]
 #[belongs_to(User, foreign_key = "username")]
@@ -29,8 +34,226 @@ pub struct Schedule {
     pub username: String,
     pub start_time: chrono::NaiveDateTime,
     pub end_time: chrono::NaiveDateTime,
-    pub permitted: bool,
-    pub absent: bool,
-    pub enable: bool,
     pub created_by: String,
+    pub status: Status,
+    pub absence_reason: Option<Reason>,
+}
+
+use super::schema::schedule_events;
+
+#[derive(Queryable, Associations, Serialize, Deserialize, Debug)]
+#[belongs_to(Schedule)]
+pub struct ScheduleEvent {
+    pub id: i64,
+    pub schedule_id: i64,
+    pub actor: String,
+    pub action: String,
+    pub from_status: Option<Status>,
+    pub to_status: Option<Status>,
+    pub old_start_time: Option<chrono::NaiveDateTime>,
+    pub old_end_time: Option<chrono::NaiveDateTime>,
+    pub new_start_time: Option<chrono::NaiveDateTime>,
+    pub new_end_time: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable, Default)]
+#[table_name = "schedule_events"]
+pub struct NewScheduleEvent<'a> {
+    pub schedule_id: i64,
+    pub actor: &'a str,
+    pub action: &'a str,
+    pub from_status: Option<Status>,
+    pub to_status: Option<Status>,
+    pub old_start_time: Option<chrono::NaiveDateTime>,
+    pub old_end_time: Option<chrono::NaiveDateTime>,
+    pub new_start_time: Option<chrono::NaiveDateTime>,
+    pub new_end_time: Option<chrono::NaiveDateTime>,
+}
+
+use super::schema::periods;
+
+#[derive(Queryable, Associations, Serialize, Deserialize, Debug)]
+#[belongs_to(User, foreign_key = "created_by")]
+pub struct Period {
+    pub id: i64,
+    pub start_date: chrono::NaiveDate,
+    pub end_date: chrono::NaiveDate,
+    pub deadline: chrono::NaiveDateTime,
+    pub created_by: String,
+    pub opens_at: Option<chrono::NaiveDateTime>,
+    pub closed_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(AsChangeset, Serialize, Deserialize, Debug)]
+#[table_name = "periods"]
+pub struct PeriodChanges {
+    // a missing field is left alone and null clears it
+    #[serde(default, deserialize_with = "nullable")]
+    pub opens_at: Option<Option<chrono::NaiveDateTime>>,
+    pub deadline: Option<chrono::NaiveDateTime>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub closed_at: Option<Option<chrono::NaiveDateTime>>,
+}
+
+impl PeriodChanges {
+    pub fn is_empty(&self) -> bool {
+        self.opens_at.is_none() && self.deadline.is_none() && self.closed_at.is_none()
+    }
+}
+
+// serde reads a null as the outer None, which would make it look missing
+fn nullable<'de, D, T>(d: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::Deserialize<'de>,
+{
+    serde::Deserialize::deserialize(d).map(Some)
+}
+
+use super::schema::availabilities;
+
+#[derive(Queryable, Associations, Serialize, Deserialize, Debug)]
+#[belongs_to(User, foreign_key = "username")]
+#[table_name = "availabilities"]
+pub struct Availability {
+    pub id: i64,
+    pub username: String,
+    pub start_time: chrono::NaiveDateTime,
+    pub end_time: chrono::NaiveDateTime,
+    pub kind: String,
+}
+
+use super::schema::weekly_availabilities;
+
+#[derive(Queryable, Associations, Serialize, Deserialize, Debug)]
+#[belongs_to(User, foreign_key = "username")]
+#[table_name = "weekly_availabilities"]
+pub struct WeeklyAvailability {
+    pub id: i64,
+    pub username: String,
+    pub weekday: i16,
+    pub start_time: chrono::NaiveTime,
+    pub end_time: chrono::NaiveTime,
+    pub kind: String,
+}
+
+use super::schema::punches;
+
+#[derive(Queryable, Associations, Serialize, Deserialize, Debug, Clone)]
+#[belongs_to(User, foreign_key = "username")]
+#[table_name = "punches"]
+pub struct Punch {
+    pub id: i64,
+    pub username: String,
+    pub kind: String,
+    pub punched_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+    // the kiosk the punch was taken on, if any
+    pub kiosk_id: Option<i64>,
+    pub client_ip: Option<String>,
+    // taken from outside the store's networks and accepted anyway
+    pub off_network: bool,
+}
+
+use super::schema::wages;
+
+#[derive(Queryable, Associations, Serialize, Deserialize, Debug)]
+#[belongs_to(User, foreign_key = "username")]
+#[table_name = "wages"]
+pub struct Wage {
+    pub id: i64,
+    pub username: String,
+    pub hourly_wage: i32,
+    pub effective_from: chrono::NaiveDate,
+    pub created_by: String,
+}
+
+use super::schema::leave_grants;
+
+#[derive(Queryable, Associations, Serialize, Deserialize, Debug, Clone)]
+#[belongs_to(User, foreign_key = "username")]
+#[table_name = "leave_grants"]
+pub struct LeaveGrant {
+    pub id: i64,
+    pub username: String,
+    pub granted_on: chrono::NaiveDate,
+    pub expires_on: chrono::NaiveDate,
+    pub days: i32,
+    pub weekly_days: i16,
+}
+
+use super::schema::leave_requests;
+
+#[derive(Queryable, Associations, Serialize, Deserialize, Debug, Clone)]
+#[belongs_to(User, foreign_key = "username")]
+#[table_name = "leave_requests"]
+pub struct LeaveRequest {
+    pub id: i64,
+    pub username: String,
+    pub leave_date: chrono::NaiveDate,
+    pub status: String,
+    pub minutes: i32,
+    pub note: Option<String>,
+    pub decided_by: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub decided_at: Option<chrono::NaiveDateTime>,
+}
+
+use super::schema::store_days;
+
+#[derive(Queryable, Insertable, Serialize, Deserialize, Debug, Clone)]
+#[table_name = "store_days"]
+pub struct StoreDay {
+    pub day: chrono::NaiveDate,
+    pub kind: String,
+    pub name: String,
+    pub created_by: String,
+}
+
+use super::schema::exception_resolutions;
+
+#[derive(Queryable, Associations, Serialize, Deserialize, Debug, Clone)]
+#[belongs_to(User, foreign_key = "username")]
+#[table_name = "exception_resolutions"]
+pub struct ExceptionResolution {
+    pub id: i64,
+    pub username: String,
+    pub work_date: chrono::NaiveDate,
+    pub kind: String,
+    pub note: String,
+    pub resolved_by: String,
+    pub resolved_at: chrono::NaiveDateTime,
+}
+
+use super::schema::punch_corrections;
+
+#[derive(Queryable, Associations, Serialize, Deserialize, Debug, Clone)]
+#[belongs_to(User, foreign_key = "username")]
+#[table_name = "punch_corrections"]
+pub struct PunchCorrection {
+    pub id: i64,
+    pub username: String,
+    pub punch_id: Option<i64>,
+    pub kind: Option<String>,
+    pub punched_at: chrono::NaiveDateTime,
+    pub reason: String,
+    pub status: String,
+    pub decided_by: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub decided_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Queryable, Serialize, Deserialize, Debug, Clone)]
+pub struct Kiosk {
+    pub id: i64,
+    pub name: String,
+    // shown once when the kiosk is registered
+    #[serde(skip_serializing)]
+    pub token: String,
+    pub created_by: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+    pub failures: i32,
+    pub locked_until: Option<chrono::NaiveDateTime>,
 }
@@ -0,0 +1,112 @@
+use diesel::pg::PgConnection;
+use diesel::QueryResult;
+use std::net::IpAddr;
+
+use super::settings;
+
+/// An address range such as `203.0.113.0/24`; a bare address is a range of one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+// ::ffff:a.b.c.d as a.b.c.d, which is how a dual-stack listener reports IPv4 peers
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        v4 => v4,
+    }
+}
+
+impl Cidr {
+    pub fn parse(s: &str) -> Option<Cidr> {
+        let mut it = s.trim().splitn(2, '/');
+        let addr = canonical(it.next()?.parse::<IpAddr>().ok()?);
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match it.next() {
+            Some(p) => p.parse::<u32>().ok().filter(|p| *p <= max)?,
+            None => max,
+        };
+        Some(Cidr { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, canonical(*ip)) {
+            (IpAddr::V4(a), IpAddr::V4(b)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(a) & mask == u32::from(b) & mask
+            }
+            (IpAddr::V6(a), IpAddr::V6(b)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(a) & mask == u128::from(b) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Ranges separated by commas or whitespace; None if any is malformed.
+pub fn parse_list(s: &str) -> Option<Vec<Cidr>> {
+    s.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|x| !x.is_empty())
+        .map(Cidr::parse)
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outside {
+    Reject,
+    // accepted, with the punch marked for a manager to look at
+    Flag,
+}
+
+impl Outside {
+    pub fn parse(s: &str) -> Option<Outside> {
+        match s {
+            "reject" => Some(Outside::Reject),
+            "flag" => Some(Outside::Flag),
+            _ => None,
+        }
+    }
+}
+
+/// Where punches are accepted from. Without any range configured, anywhere.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    pub networks: Vec<Cidr>,
+    pub outside: Outside,
+    // proxies in front of the app that append to X-Forwarded-For; 1 on Heroku
+    pub proxy_hops: usize,
+}
+
+impl Policy {
+    pub fn load(conn: &PgConnection) -> QueryResult<Policy> {
+        Ok(Policy {
+            networks: parse_list(&settings::get(conn, settings::PUNCH_NETWORKS)?)
+                .unwrap_or_default(),
+            outside: Outside::parse(&settings::get(conn, settings::PUNCH_OUTSIDE_NETWORK)?)
+                .unwrap_or(Outside::Reject),
+            proxy_hops: settings::get_number(conn, settings::TRUSTED_PROXY_HOPS)?.max(0) as usize,
+        })
+    }
+
+    /// The client's address. Behind proxies it is the entry the outermost
+    /// trusted proxy appended to X-Forwarded-For; anything to the left of it
+    /// was sent by the client and proves nothing.
+    pub fn client_ip(&self, peer: Option<IpAddr>, forwarded_for: Option<&str>) -> Option<IpAddr> {
+        if self.proxy_hops == 0 {
+            return peer.map(canonical);
+        }
+        let hops: Vec<&str> = forwarded_for?.split(',').map(|x| x.trim()).collect();
+        let i = hops.len().checked_sub(self.proxy_hops)?;
+        hops[i].parse::<IpAddr>().ok().map(canonical)
+    }
+
+    /// Whether a punch from `ip` is inside the store's networks. An unknown
+    /// address is outside unless no network is configured.
+    pub fn allows(&self, ip: Option<&IpAddr>) -> bool {
+        self.networks.is_empty()
+            || matches!(ip, Some(ip) if self.networks.iter().any(|c| c.contains(ip)))
+    }
+}
@@ -0,0 +1,277 @@
+use chrono::{Datelike, NaiveDate};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use super::absence::{self, Reason};
+use super::models::{Schedule, Wage};
+use super::rounding::Policy;
+use super::schedule::Status;
+use super::{attendance, calendar, from_local, leave, schema, settings, to_local};
+
+// 割増賃金令: at least 25% on top for overtime and for late-night work
+pub const OVERTIME_PERCENT: i64 = 125;
+pub const LATE_NIGHT_PREMIUM_PERCENT: i64 = 25;
+
+/// One worker's pay over a range, in yen. Minutes are rounded by the store's
+/// policy; the `raw_` ones are as punched.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Pay {
+    pub username: String,
+    pub raw_worked_minutes: i64,
+    pub raw_regular_minutes: i64,
+    pub raw_overtime_minutes: i64,
+    pub raw_late_night_minutes: i64,
+    pub raw_holiday_minutes: i64,
+    pub worked_minutes: i64,
+    pub regular_minutes: i64,
+    pub overtime_minutes: i64,
+    pub late_night_minutes: i64,
+    // worked on national holidays and store special days
+    pub holiday_minutes: i64,
+    pub leave_days: i64,
+    pub leave_minutes: i64,
+    pub regular_pay: i64,
+    pub overtime_pay: i64,
+    pub late_night_pay: i64,
+    pub holiday_pay: i64,
+    pub leave_pay: i64,
+    pub absence_pay: i64,
+    // approved absences by reason, unpaid ones included
+    pub absences: BTreeMap<&'static str, AbsenceTotal>,
+    pub total: i64,
+    // dates worked or on leave with no wage in effect; they are paid nothing
+    pub unpriced: Vec<NaiveDate>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AbsenceTotal {
+    pub days: i64,
+    // planned minutes of the shifts missed
+    pub minutes: i64,
+    pub percent: i64,
+    pub pay: i64,
+}
+
+// yen × minutes × percent, so nothing is rounded before the total
+#[derive(Default)]
+struct Acc {
+    regular: i64,
+    overtime: i64,
+    late_night: i64,
+    holiday: i64,
+    leave: i64,
+    absences: BTreeMap<Reason, i64>,
+    // by the first of each calendar month, for the monthly rounding
+    months: BTreeMap<NaiveDate, Month>,
+}
+
+#[derive(Default)]
+struct Month {
+    overtime: i64,
+    late_night: i64,
+    holiday: i64,
+    // wage and premium of the month's last day worked, which price the rounding
+    wage: i64,
+    premium: i64,
+}
+
+// 50銭未満切り捨て, 50銭以上切り上げ
+fn yen(x: i64) -> i64 {
+    (x + 3000) / 6000
+}
+
+/// The hourly wage in effect on `date`; `wages` must be sorted by date.
+pub fn wage_on(wages: &[Wage], date: NaiveDate) -> Option<i64> {
+    wages
+        .iter()
+        .rev()
+        .find(|w| w.effective_from <= date)
+        .map(|w| w.hourly_wage as i64)
+}
+
+/// Shifts on local dates `start..end` the worker was excused from.
+pub fn absences(
+    conn: &PgConnection,
+    start: NaiveDate,
+    end: NaiveDate,
+    username: Option<&str>,
+) -> QueryResult<Vec<Schedule>> {
+    use schema::schedules;
+    let mut q = schedules::table
+        .filter(schedules::status.eq(Status::AbsentApproved))
+        .filter(schedules::start_time.ge(from_local(&start.and_hms(0, 0, 0))))
+        .filter(schedules::start_time.lt(from_local(&end.and_hms(0, 0, 0))))
+        .into_boxed();
+    if let Some(u) = username {
+        q = q.filter(schedules::username.eq(u));
+    }
+    q.order(schedules::start_time.asc())
+        .get_results::<Schedule>(conn)
+}
+
+/// Pay for work, approved paid leave and excused absences on local dates
+/// `start..end`. An absence is paid the configured share of its planned shift;
+/// one without a reason is unpaid. The monthly rounding rule, when enabled,
+/// applies to the totals of each calendar month; months the range covers only
+/// in part are left as they are.
+pub fn calculate(
+    conn: &PgConnection,
+    start: NaiveDate,
+    end: NaiveDate,
+    username: Option<&str>,
+) -> QueryResult<Vec<Pay>> {
+    use schema::wages;
+    let mut q = wages::table.into_boxed();
+    if let Some(u) = username {
+        q = q.filter(wages::username.eq(u));
+    }
+    let mut wages: BTreeMap<String, Vec<Wage>> = BTreeMap::new();
+    for w in q
+        .order(wages::effective_from.asc())
+        .get_results::<Wage>(conn)?
+    {
+        wages.entry(w.username.clone()).or_default().push(w);
+    }
+
+    // the larger premium wins on a special day that is also a holiday
+    let (holiday, special) = (
+        settings::get_number(conn, settings::HOLIDAY_PREMIUM)?,
+        settings::get_number(conn, settings::SPECIAL_DAY_PREMIUM)?,
+    );
+    let mut premiums: BTreeMap<NaiveDate, i64> = BTreeMap::new();
+    for e in calendar::load(conn, start, end)? {
+        let x = match e.kind {
+            calendar::Kind::NationalHoliday => holiday,
+            calendar::Kind::Special => special,
+            calendar::Kind::Closed => continue,
+        };
+        let p = premiums.entry(e.date).or_default();
+        *p = (*p).max(x);
+    }
+
+    let policy = Policy::load(conn)?;
+    let mut ret: BTreeMap<String, (Pay, Acc)> = BTreeMap::new();
+    for d in attendance::load(conn, start, end, username)? {
+        let (p, acc) = ret.entry(d.username.clone()).or_default();
+        let premium = premiums.get(&d.date).cloned();
+        let r = &d.split;
+        p.raw_worked_minutes += r.worked;
+        p.raw_regular_minutes += r.regular;
+        p.raw_overtime_minutes += r.overtime;
+        p.raw_late_night_minutes += r.late_night;
+        if premium.is_some() {
+            p.raw_holiday_minutes += r.worked;
+        }
+        let s = &d.rounded;
+        p.worked_minutes += s.worked;
+        p.regular_minutes += s.regular;
+        p.overtime_minutes += s.overtime;
+        p.late_night_minutes += s.late_night;
+        if premium.is_some() {
+            p.holiday_minutes += s.worked;
+        }
+        let month = d.date.with_day(1).unwrap();
+        let m = acc.months.entry(month).or_default();
+        m.overtime += s.overtime;
+        m.late_night += s.late_night;
+        if premium.is_some() {
+            m.holiday += s.worked;
+        }
+        match wages.get(&d.username).and_then(|ws| wage_on(ws, d.date)) {
+            Some(w) => {
+                acc.regular += w * s.regular * 100;
+                acc.overtime += w * s.overtime * OVERTIME_PERCENT;
+                acc.late_night += w * s.late_night * LATE_NIGHT_PREMIUM_PERCENT;
+                acc.holiday += w * s.worked * premium.unwrap_or(0);
+                let m = acc.months.entry(month).or_default();
+                if s.worked > 0 {
+                    m.wage = w;
+                }
+                if let Some(x) = premium.filter(|_| s.worked > 0) {
+                    m.premium = w * x;
+                }
+            }
+            None if s.worked > 0 => p.unpriced.push(d.date),
+            None => {}
+        }
+    }
+    for r in leave::approved(conn, start, end, username)? {
+        let (p, acc) = ret.entry(r.username.clone()).or_default();
+        let m = r.minutes as i64;
+        p.leave_days += 1;
+        p.leave_minutes += m;
+        match wages
+            .get(&r.username)
+            .and_then(|ws| wage_on(ws, r.leave_date))
+        {
+            Some(w) => acc.leave += w * m * 100,
+            None => p.unpriced.push(r.leave_date),
+        }
+    }
+    let percents = absence::pay_percents(conn)?;
+    for s in absences(conn, start, end, username)? {
+        let (p, acc) = ret.entry(s.username.clone()).or_default();
+        let reason = s.absence_reason.unwrap_or(Reason::Unpaid);
+        let percent = percents
+            .iter()
+            .find(|(r, _)| *r == reason)
+            .map_or(0, |(_, x)| *x);
+        let m = attendance::planned(&s.start_time, &s.end_time).worked;
+        let t = p.absences.entry(reason.as_str()).or_default();
+        t.days += 1;
+        t.minutes += m;
+        t.percent = percent;
+        if percent == 0 {
+            continue;
+        }
+        let date = to_local(&s.start_time).date();
+        match wages.get(&s.username).and_then(|ws| wage_on(ws, date)) {
+            Some(w) => *acc.absences.entry(reason).or_default() += w * m * percent,
+            None => p.unpriced.push(date),
+        }
+    }
+    Ok(ret
+        .into_iter()
+        .map(|(u, (mut p, mut acc))| {
+            p.username = u;
+            for (first, m) in &acc.months {
+                if *first < start || leave::add_months(*first, 1) > end {
+                    continue;
+                }
+                let (o, l, h) = (
+                    policy.month(m.overtime) - m.overtime,
+                    policy.month(m.late_night) - m.late_night,
+                    policy.month(m.holiday) - m.holiday,
+                );
+                acc.overtime += m.wage * o * OVERTIME_PERCENT;
+                acc.late_night += m.wage * l * LATE_NIGHT_PREMIUM_PERCENT;
+                acc.holiday += m.premium * h;
+                p.worked_minutes += o;
+                p.overtime_minutes += o;
+                p.late_night_minutes += l;
+                p.holiday_minutes += h;
+            }
+            p.regular_pay = yen(acc.regular);
+            p.overtime_pay = yen(acc.overtime);
+            p.late_night_pay = yen(acc.late_night);
+            p.holiday_pay = yen(acc.holiday);
+            p.leave_pay = yen(acc.leave);
+            for (r, x) in acc.absences {
+                let t = p.absences.entry(r.as_str()).or_default();
+                t.pay = yen(x);
+                p.absence_pay += t.pay;
+            }
+            p.total = p.regular_pay
+                + p.overtime_pay
+                + p.late_night_pay
+                + p.holiday_pay
+                + p.leave_pay
+                + p.absence_pay;
+            p.unpriced.sort();
+            p.unpriced.dedup();
+            p
+        })
+        .collect())
+}
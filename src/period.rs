@@ -0,0 +1,98 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use std::collections::HashSet;
+
+use super::models::{Period, User};
+use super::{from_local, schema, to_local};
+
+pub fn containing(conn: &PgConnection, date: NaiveDate) -> QueryResult<Vec<Period>> {
+    use schema::periods;
+    periods::table
+        .filter(periods::start_date.le(date))
+        .filter(periods::end_date.ge(date))
+        .get_results::<Period>(conn)
+}
+
+/// UTC bounds `[start, end)` of the local dates covered by the period.
+pub fn bounds(p: &Period) -> (NaiveDateTime, NaiveDateTime) {
+    (
+        from_local(&p.start_date.and_hms(0, 0, 0)),
+        from_local(&(p.end_date + Duration::days(1)).and_hms(0, 0, 0)),
+    )
+}
+
+pub fn is_open(p: &Period, now: &NaiveDateTime) -> bool {
+    p.opens_at.iter().all(|o| o <= now) && *now < p.deadline
+}
+
+/// Whether submissions for the shift starting at `time` are rejected as of `now`,
+/// either because its period has closed or because it has not opened yet.
+pub fn is_locked(
+    conn: &PgConnection,
+    time: &NaiveDateTime,
+    now: &NaiveDateTime,
+) -> QueryResult<bool> {
+    containing(conn, to_local(time).date()).map(|ps| ps.iter().any(|p| !is_open(p, now)))
+}
+
+/// Whether attendance at `time` is final as of `now`: its period has been
+/// closed for payroll and may no longer be corrected.
+pub fn is_closed(
+    conn: &PgConnection,
+    time: &NaiveDateTime,
+    now: &NaiveDateTime,
+) -> QueryResult<bool> {
+    containing(conn, to_local(time).date())
+        .map(|ps| ps.iter().any(|p| p.closed_at.iter().any(|c| c <= now)))
+}
+
+/// Closes a period as of `now`: submissions end and its attendance becomes
+/// final. A deadline or close already past is kept.
+pub fn close(conn: &PgConnection, id: i64, now: NaiveDateTime) -> QueryResult<Period> {
+    use schema::periods;
+    conn.transaction(|| {
+        diesel::update(periods::table.filter(periods::id.eq(id)))
+            .filter(periods::deadline.gt(now))
+            .set(periods::deadline.eq(now))
+            .execute(conn)?;
+        diesel::update(periods::table.filter(periods::id.eq(id)))
+            .filter(periods::closed_at.is_null().or(periods::closed_at.gt(now)))
+            .set(periods::closed_at.eq(now))
+            .execute(conn)?;
+        periods::table
+            .filter(periods::id.eq(id))
+            .get_result::<Period>(conn)
+    })
+}
+
+/// Staff who have neither requested a shift nor declared availability in the period.
+pub fn unsubmitted(conn: &PgConnection, p: &Period) -> QueryResult<Vec<User>> {
+    use schema::{availabilities, schedules, users};
+    let (start, end) = bounds(p);
+    let mut submitted: HashSet<String> = schedules::table
+        .select(schedules::username)
+        .filter(schedules::username.eq(schedules::created_by))
+        .filter(schedules::start_time.ge(start))
+        .filter(schedules::start_time.lt(end))
+        .get_results::<String>(conn)?
+        .into_iter()
+        .collect();
+    submitted.extend(
+        availabilities::table
+            .select(availabilities::username)
+            .filter(availabilities::start_time.ge(start))
+            .filter(availabilities::start_time.lt(end))
+            .get_results::<String>(conn)?,
+    );
+    users::table
+        .order(users::id.asc())
+        .filter(users::isadmin.eq(false))
+        .filter(users::deactivated_at.is_null())
+        .get_results::<User>(conn)
+        .map(|us| {
+            us.into_iter()
+                .filter(|u| !submitted.contains(&u.id))
+                .collect()
+        })
+}
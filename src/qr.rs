@@ -0,0 +1,100 @@
+use chrono::NaiveDateTime;
+use dotenv::dotenv;
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use std::env;
+
+// a store display shows a new code this often; a code stays valid a little
+// longer so a scan started just before it changes still counts
+pub const ROTATE_SECONDS: i64 = 15;
+pub const VALID_SECONDS: i64 = 45;
+// displays and phones may disagree about the time by this much
+const CLOCK_SKEW_SECONDS: i64 = 5;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    // the registered kiosk showing the code
+    kiosk: i64,
+    iat: i64,
+    exp: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Code {
+    pub payload: String,
+    pub expires_at: NaiveDateTime,
+    // when the display should fetch the next code
+    pub refresh_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QrError {
+    Invalid,
+    Expired,
+}
+
+// kept apart from JWT_SECRET so a leaked display cannot mint login tokens, and
+// without a default so no two deployments share one
+fn key() -> Option<String> {
+    let _ = dotenv();
+    env::var("QR_SECRET").ok().filter(|k| !k.is_empty())
+}
+
+/// What is wrong with QR_SECRET, if anything; the server does not start then.
+pub fn misconfigured() -> Option<&'static str> {
+    match key() {
+        None => Some("QR_SECRET must be set"),
+        Some(k) if env::var("JWT_SECRET").map_or(false, |j| j == k) => {
+            Some("QR_SECRET must differ from JWT_SECRET")
+        }
+        Some(_) => None,
+    }
+}
+
+/// Signs the code for `kiosk_id` for the rotation step containing `now`, so
+/// every display refresh within a step shows the same code.
+pub fn issue(kiosk_id: i64, now: &NaiveDateTime) -> Option<Code> {
+    let iat = now.timestamp() / ROTATE_SECONDS * ROTATE_SECONDS;
+    let claims = Claims {
+        kiosk: kiosk_id,
+        iat,
+        exp: iat + VALID_SECONDS,
+    };
+    let payload = jsonwebtoken::encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(key()?.as_ref()),
+    )
+    .ok()?;
+    Some(Code {
+        payload,
+        expires_at: NaiveDateTime::from_timestamp(claims.exp, 0),
+        refresh_at: NaiveDateTime::from_timestamp(iat + ROTATE_SECONDS, 0),
+    })
+}
+
+/// The kiosk a scanned payload was shown on, if it is genuine and still fresh
+/// as of `now`.
+pub fn verify(payload: &str, now: &NaiveDateTime) -> Result<i64, QrError> {
+    // freshness is checked against `now` below rather than the system clock
+    let validation = Validation {
+        validate_exp: false,
+        ..Validation::default()
+    };
+    let key = key().ok_or(QrError::Invalid)?;
+    let claims = jsonwebtoken::decode::<Claims>(
+        payload,
+        &DecodingKey::from_secret(key.as_ref()),
+        &validation,
+    )
+    .map_err(|_| QrError::Invalid)?
+    .claims;
+    let t = now.timestamp();
+    if claims.exp - claims.iat > VALID_SECONDS {
+        return Err(QrError::Invalid);
+    }
+    if t + CLOCK_SKEW_SECONDS < claims.iat || claims.exp <= t {
+        return Err(QrError::Expired);
+    }
+    Ok(claims.kiosk)
+}
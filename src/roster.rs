@@ -0,0 +1,225 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::to_local;
+
+// 労働基準法 第32条: 1日8時間 / 1週40時間
+pub const LEGAL_DAILY_MINUTES: i64 = 8 * 60;
+pub const LEGAL_WEEKLY_MINUTES: i64 = 40 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Availability {
+    pub username: String,
+    pub start_time: NaiveDateTime,
+    pub end_time: NaiveDateTime,
+    #[serde(default)]
+    pub preferred: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Requirement {
+    pub start_time: NaiveDateTime,
+    pub end_time: NaiveDateTime,
+    pub headcount: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeeklyCap {
+    pub username: String,
+    pub max_minutes: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Assignment {
+    pub username: String,
+    pub start_time: NaiveDateTime,
+    pub end_time: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Unfilled {
+    pub start_time: NaiveDateTime,
+    pub end_time: NaiveDateTime,
+    pub missing: i32,
+}
+
+#[derive(Debug, Default)]
+pub struct RosterInput {
+    pub availability: Vec<Availability>,
+    pub requirements: Vec<Requirement>,
+    pub caps: Vec<WeeklyCap>,
+    // shifts already on the roster; they count against caps and block overlaps
+    pub fixed: Vec<Assignment>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Roster {
+    pub assignments: Vec<Assignment>,
+    pub unfilled: Vec<Unfilled>,
+}
+
+// splitmix64: small and stable across platforms, so a seed always yields the same roster
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn shuffle<T>(&mut self, xs: &mut [T]) {
+        for i in (1..xs.len()).rev() {
+            let j = (self.next() % (i as u64 + 1)) as usize;
+            xs.swap(i, j);
+        }
+    }
+}
+
+fn minutes(start: &NaiveDateTime, end: &NaiveDateTime) -> i64 {
+    (*end - *start).num_minutes()
+}
+
+struct Load {
+    // keyed on the local date the shift starts
+    daily: HashMap<NaiveDate, i64>,
+    weekly: i64,
+    shifts: Vec<(NaiveDateTime, NaiveDateTime)>,
+}
+
+impl Load {
+    fn new() -> Self {
+        Load {
+            daily: HashMap::new(),
+            weekly: 0,
+            shifts: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, start: NaiveDateTime, end: NaiveDateTime) {
+        let m = minutes(&start, &end);
+        *self.daily.entry(to_local(&start).date()).or_insert(0) += m;
+        self.weekly += m;
+        self.shifts.push((start, end));
+    }
+
+    fn accepts(&self, start: &NaiveDateTime, end: &NaiveDateTime, cap: i64) -> bool {
+        let m = minutes(start, end);
+        let daily = self
+            .daily
+            .get(&to_local(start).date())
+            .cloned()
+            .unwrap_or(0);
+        daily + m <= LEGAL_DAILY_MINUTES
+            && self.weekly + m <= cap.min(LEGAL_WEEKLY_MINUTES)
+            && self.shifts.iter().all(|(s, e)| e <= start || end <= s)
+    }
+}
+
+fn covers(a: &Availability, start: &NaiveDateTime, end: &NaiveDateTime) -> bool {
+    a.start_time <= *start && *end <= a.end_time
+}
+
+/// Greedy roster search. Requirements with the fewest eligible staff are filled
+/// first, and each slot goes to a candidate who prefers the window, then to the
+/// least loaded one; the seed only breaks ties, so the same input and seed
+/// always produce the same roster.
+pub fn generate(input: &RosterInput, seed: u64) -> Roster {
+    let mut rng = SplitMix64(seed);
+    let caps: HashMap<&str, i64> = input
+        .caps
+        .iter()
+        .map(|c| (c.username.as_str(), c.max_minutes))
+        .collect();
+    let mut staff: Vec<&str> = input
+        .availability
+        .iter()
+        .map(|a| a.username.as_str())
+        .collect();
+    staff.sort();
+    staff.dedup();
+
+    let mut loads: HashMap<&str, Load> = staff.iter().map(|u| (*u, Load::new())).collect();
+    for f in input.fixed.iter() {
+        if let Some(l) = loads.get_mut(f.username.as_str()) {
+            l.add(f.start_time, f.end_time);
+        }
+    }
+
+    let prefers = |u: &str, r: &Requirement| {
+        input
+            .availability
+            .iter()
+            .any(|a| a.username == u && a.preferred && covers(a, &r.start_time, &r.end_time))
+    };
+    let eligible = |r: &Requirement| -> Vec<&str> {
+        staff
+            .iter()
+            .filter(|u| {
+                input
+                    .availability
+                    .iter()
+                    .any(|a| a.username == **u && covers(a, &r.start_time, &r.end_time))
+            })
+            .cloned()
+            .collect()
+    };
+
+    let mut order: Vec<(usize, Vec<&str>)> = input
+        .requirements
+        .iter()
+        .enumerate()
+        .map(|(i, r)| (i, eligible(r)))
+        .collect();
+    order.sort_by_key(|(i, c)| (c.len(), input.requirements[*i].start_time, *i));
+
+    let mut assignments = Vec::new();
+    let mut missing = vec![0; input.requirements.len()];
+    for (i, mut candidates) in order {
+        let r = &input.requirements[i];
+        rng.shuffle(&mut candidates);
+        for _ in 0..r.headcount.max(0) {
+            let cap = |u: &str| caps.get(u).cloned().unwrap_or(LEGAL_WEEKLY_MINUTES);
+            let pick = candidates
+                .iter()
+                .filter(|u| loads[**u].accepts(&r.start_time, &r.end_time, cap(u)))
+                .min_by_key(|u| (!prefers(u, r), loads[**u].weekly))
+                .cloned();
+            match pick {
+                Some(u) => {
+                    loads.get_mut(u).unwrap().add(r.start_time, r.end_time);
+                    assignments.push(Assignment {
+                        username: u.to_string(),
+                        start_time: r.start_time,
+                        end_time: r.end_time,
+                    });
+                }
+                None => missing[i] += 1,
+            }
+        }
+    }
+
+    assignments.sort_by(|a, b| (a.start_time, &a.username).cmp(&(b.start_time, &b.username)));
+    let unfilled = input
+        .requirements
+        .iter()
+        .zip(missing)
+        .filter(|(_, m)| *m > 0)
+        .map(|(r, m)| Unfilled {
+            start_time: r.start_time,
+            end_time: r.end_time,
+            missing: m,
+        })
+        .collect();
+    Roster {
+        assignments,
+        unfilled,
+    }
+}
+
+pub fn week_end(week_start: NaiveDate) -> NaiveDate {
+    week_start + Duration::days(7)
+}
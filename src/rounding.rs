@@ -0,0 +1,98 @@
+use chrono::{Duration, NaiveDateTime, Timelike};
+use diesel::pg::PgConnection;
+use diesel::QueryResult;
+use serde::Serialize;
+
+use super::attendance::Split;
+use super::roster::LEGAL_DAILY_MINUTES;
+use super::{from_local, settings, to_local};
+
+/// Units a punch or a daily total may be rounded to, in minutes.
+pub const UNITS: [i64; 5] = [1, 5, 10, 15, 30];
+
+/// How worked time is rounded before it is paid. The default rounds nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Policy {
+    // clock ins are rounded up and clock outs down to these units
+    pub clock_in: i64,
+    pub clock_out: i64,
+    // a day's total is rounded to the nearest unit, halves up
+    pub daily: i64,
+    // 昭和63年3月14日基発第150号: monthly overtime, holiday and late-night
+    // totals may drop less than 30 minutes and round 30 or more up to the hour
+    pub monthly: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy {
+            clock_in: 1,
+            clock_out: 1,
+            daily: 1,
+            monthly: false,
+        }
+    }
+}
+
+impl Policy {
+    pub fn load(conn: &PgConnection) -> QueryResult<Policy> {
+        Ok(Policy {
+            clock_in: settings::get_number(conn, settings::ROUNDING_CLOCK_IN)?.max(1),
+            clock_out: settings::get_number(conn, settings::ROUNDING_CLOCK_OUT)?.max(1),
+            daily: settings::get_number(conn, settings::ROUNDING_DAILY)?.max(1),
+            monthly: settings::get_bool(conn, settings::ROUNDING_MONTHLY)?,
+        })
+    }
+
+    pub fn clock_in(&self, t: &NaiveDateTime) -> NaiveDateTime {
+        if self.clock_in == 1 {
+            return *t;
+        }
+        let down = floor(t, self.clock_in);
+        if down == *t {
+            down
+        } else {
+            down + Duration::minutes(self.clock_in)
+        }
+    }
+
+    pub fn clock_out(&self, t: &NaiveDateTime) -> NaiveDateTime {
+        if self.clock_out == 1 {
+            return *t;
+        }
+        floor(t, self.clock_out)
+    }
+
+    /// Rounds a day's worked total, redistributing it between regular time and
+    /// overtime. Late night cannot exceed what is left.
+    pub fn day(&self, s: &Split) -> Split {
+        let worked = (s.worked + self.daily / 2) / self.daily * self.daily;
+        let overtime = (worked - LEGAL_DAILY_MINUTES).max(0);
+        Split {
+            break_minutes: s.break_minutes,
+            worked,
+            regular: worked - overtime,
+            overtime,
+            late_night: s.late_night.min(worked),
+        }
+    }
+
+    /// Rounds a monthly total of overtime, holiday or late-night minutes.
+    pub fn month(&self, minutes: i64) -> i64 {
+        if self.monthly {
+            (minutes + 30) / 60 * 60
+        } else {
+            minutes
+        }
+    }
+}
+
+// units divide an hour, so the local minute past the hour decides
+fn floor(t: &NaiveDateTime, unit: i64) -> NaiveDateTime {
+    let l = to_local(t)
+        .with_second(0)
+        .unwrap()
+        .with_nanosecond(0)
+        .unwrap();
+    from_local(&(l - Duration::minutes(l.minute() as i64 % unit)))
+}
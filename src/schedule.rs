@@ -0,0 +1,232 @@
+use derive_more::{Display, Error};
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::{Pg, PgConnection};
+use diesel::prelude::*;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Varchar;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+use super::models::{NewScheduleEvent, Schedule, ScheduleEvent};
+use super::schema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[serde(rename_all = "snake_case")]
+#[sql_type = "Varchar"]
+pub enum Status {
+    // created by the worker, waiting for a manager
+    Requested,
+    // created by a manager for a worker, waiting for the worker
+    Assigned,
+    Approved,
+    AbsenceRequested,
+    AbsentApproved,
+    Cancelled,
+    Rejected,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Requested => "requested",
+            Status::Assigned => "assigned",
+            Status::Approved => "approved",
+            Status::AbsenceRequested => "absence_requested",
+            Status::AbsentApproved => "absent_approved",
+            Status::Cancelled => "cancelled",
+            Status::Rejected => "rejected",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Status> {
+        match s {
+            "requested" => Some(Status::Requested),
+            "assigned" => Some(Status::Assigned),
+            "approved" => Some(Status::Approved),
+            "absence_requested" => Some(Status::AbsenceRequested),
+            "absent_approved" => Some(Status::AbsentApproved),
+            "cancelled" => Some(Status::Cancelled),
+            "rejected" => Some(Status::Rejected),
+            _ => None,
+        }
+    }
+
+    // whether the shift still occupies the worker's time
+    pub fn is_active(&self) -> bool {
+        !matches!(
+            self,
+            Status::AbsentApproved | Status::Cancelled | Status::Rejected
+        )
+    }
+}
+
+impl ToSql<Varchar, Pg> for Status {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        ToSql::<Varchar, Pg>::to_sql(self.as_str(), out)
+    }
+}
+
+impl FromSql<Varchar, Pg> for Status {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        let s: String = FromSql::<Varchar, Pg>::from_sql(bytes)?;
+        Status::parse(&s).ok_or_else(|| format!("unknown schedule status: {}", s).into())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Approve,
+    Reject,
+    RequestAbsence,
+    ApproveAbsence,
+    RejectAbsence,
+    // a manager takes the worker off an approved shift, e.g. for company-ordered rest
+    Excuse,
+    Cancel,
+    // change start and end time; keeps the status
+    Edit,
+    // remove the row; keeps the status until it is gone
+    Delete,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Approve => "approve",
+            Action::Reject => "reject",
+            Action::RequestAbsence => "request_absence",
+            Action::ApproveAbsence => "approve_absence",
+            Action::RejectAbsence => "reject_absence",
+            Action::Excuse => "excuse",
+            Action::Cancel => "cancel",
+            Action::Edit => "edit",
+            Action::Delete => "delete",
+        }
+    }
+}
+
+// event action for a newly inserted schedule; every other event is an `Action`
+pub const CREATE: &str = "create";
+
+pub const ACTIONS: [Action; 9] = [
+    Action::Approve,
+    Action::Reject,
+    Action::RequestAbsence,
+    Action::ApproveAbsence,
+    Action::RejectAbsence,
+    Action::Excuse,
+    Action::Cancel,
+    Action::Edit,
+    Action::Delete,
+];
+
+pub struct Actor<'a> {
+    pub username: &'a str,
+    pub isadmin: bool,
+}
+
+#[derive(Debug, Display, Error)]
+pub enum TransitionError {
+    #[display(fmt = "not permitted")]
+    NotPermitted,
+    #[display(fmt = "invalid state")]
+    InvalidState,
+}
+
+fn next(status: Status, action: Action) -> Option<Status> {
+    use Action::*;
+    use Status::*;
+    match (status, action) {
+        (Requested, Approve) | (Assigned, Approve) => Some(Approved),
+        (Requested, Reject) | (Assigned, Reject) => Some(Rejected),
+        (Approved, RequestAbsence) => Some(AbsenceRequested),
+        (AbsenceRequested, ApproveAbsence) | (Approved, Excuse) => Some(AbsentApproved),
+        (AbsenceRequested, RejectAbsence) => Some(Approved),
+        (Requested, Cancel) | (Assigned, Cancel) | (Approved, Cancel) => Some(Cancelled),
+        (Requested, Edit) | (Assigned, Edit) => Some(status),
+        (Requested, Delete) | (Assigned, Delete) => Some(status),
+        _ => None,
+    }
+}
+
+fn may(s: &Schedule, actor: &Actor, action: Action) -> bool {
+    let creator = s.created_by == actor.username;
+    // a manager's assignment is answered by the worker it was assigned to
+    let assignee = s.username == actor.username && !creator;
+    match (s.status, action) {
+        (Status::Requested, Action::Approve) | (Status::Requested, Action::Reject) => actor.isadmin,
+        (Status::Assigned, Action::Approve) | (Status::Assigned, Action::Reject) => assignee,
+        (_, Action::RequestAbsence) => s.username == actor.username,
+        (_, Action::ApproveAbsence) | (_, Action::RejectAbsence) | (_, Action::Excuse) => {
+            actor.isadmin
+        }
+        (Status::Approved, Action::Cancel) => actor.isadmin,
+        (_, Action::Cancel) | (_, Action::Edit) | (_, Action::Delete) => creator,
+        _ => false,
+    }
+}
+
+/// The single place deciding who may move a schedule where.
+pub fn transition(s: &Schedule, actor: &Actor, action: Action) -> Result<Status, TransitionError> {
+    let to = next(s.status, action).ok_or(TransitionError::InvalidState)?;
+    if may(s, actor, action) {
+        Ok(to)
+    } else {
+        Err(TransitionError::NotPermitted)
+    }
+}
+
+pub fn allowed_actions(s: &Schedule, actor: &Actor) -> Vec<Action> {
+    ACTIONS
+        .iter()
+        .cloned()
+        .filter(|a| transition(s, actor, *a).is_ok())
+        .collect()
+}
+
+/// Whether `actor` may read the schedule. `shared` is the store's shared roster setting.
+pub fn visible(s: &Schedule, actor: &Actor, shared: bool) -> bool {
+    actor.isadmin
+        || shared
+        || s.username == actor.username
+        || s.created_by == actor.username
+        || s.status == Status::Approved
+}
+
+pub fn record(conn: &PgConnection, e: &NewScheduleEvent) -> QueryResult<usize> {
+    use schema::schedule_events;
+    diesel::insert_into(schedule_events::table)
+        .values(e)
+        .execute(conn)
+}
+
+pub fn record_created(conn: &PgConnection, ss: &[Schedule], actor: &str) -> QueryResult<usize> {
+    use schema::schedule_events;
+    if ss.is_empty() {
+        return Ok(0);
+    }
+    diesel::insert_into(schedule_events::table)
+        .values(
+            ss.iter()
+                .map(|s| NewScheduleEvent {
+                    schedule_id: s.id,
+                    actor,
+                    action: CREATE,
+                    to_status: Some(s.status),
+                    new_start_time: Some(s.start_time),
+                    new_end_time: Some(s.end_time),
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>(),
+        )
+        .execute(conn)
+}
+
+pub fn history(conn: &PgConnection, id: i64) -> QueryResult<Vec<ScheduleEvent>> {
+    use schema::schedule_events;
+    schedule_events::table
+        .filter(schedule_events::schedule_id.eq(id))
+        .order(schedule_events::id.asc())
+        .get_results::<ScheduleEvent>(conn)
+}
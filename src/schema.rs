@@ -1,16 +1,165 @@
+table! {
+    availabilities (id) {
+        id -> Int8,
+        username -> Varchar,
+        start_time -> Timestamptz,
+        end_time -> Timestamptz,
+        kind -> Varchar,
+    }
+}
+
+table! {
+    calendar_feeds (username) {
+        username -> Varchar,
+        token -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    exception_resolutions (id) {
+        id -> Int8,
+        username -> Varchar,
+        work_date -> Date,
+        kind -> Varchar,
+        note -> Text,
+        resolved_by -> Varchar,
+        resolved_at -> Timestamptz,
+    }
+}
+
+table! {
+    kiosks (id) {
+        id -> Int8,
+        name -> Varchar,
+        token -> Varchar,
+        created_by -> Varchar,
+        created_at -> Timestamptz,
+        revoked_at -> Nullable<Timestamptz>,
+        failures -> Int4,
+        locked_until -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    leave_grants (id) {
+        id -> Int8,
+        username -> Varchar,
+        granted_on -> Date,
+        expires_on -> Date,
+        days -> Int4,
+        weekly_days -> Int2,
+    }
+}
+
+table! {
+    leave_requests (id) {
+        id -> Int8,
+        username -> Varchar,
+        leave_date -> Date,
+        status -> Varchar,
+        minutes -> Int4,
+        note -> Nullable<Text>,
+        decided_by -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+        decided_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    periods (id) {
+        id -> Int8,
+        start_date -> Date,
+        end_date -> Date,
+        deadline -> Timestamptz,
+        created_by -> Varchar,
+        opens_at -> Nullable<Timestamptz>,
+        closed_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    punch_corrections (id) {
+        id -> Int8,
+        username -> Varchar,
+        punch_id -> Nullable<Int8>,
+        kind -> Nullable<Varchar>,
+        punched_at -> Timestamptz,
+        reason -> Text,
+        status -> Varchar,
+        decided_by -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+        decided_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    punches (id) {
+        id -> Int8,
+        username -> Varchar,
+        kind -> Varchar,
+        punched_at -> Timestamptz,
+        created_at -> Timestamptz,
+        kiosk_id -> Nullable<Int8>,
+        client_ip -> Nullable<Varchar>,
+        off_network -> Bool,
+    }
+}
+
+table! {
+    schedule_events (id) {
+        id -> Int8,
+        schedule_id -> Int8,
+        actor -> Varchar,
+        action -> Varchar,
+        from_status -> Nullable<Varchar>,
+        to_status -> Nullable<Varchar>,
+        old_start_time -> Nullable<Timestamptz>,
+        old_end_time -> Nullable<Timestamptz>,
+        new_start_time -> Nullable<Timestamptz>,
+        new_end_time -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
 table! {
     schedules (id) {
         id -> Int8,
         username -> Varchar,
         start_time -> Timestamptz,
         end_time -> Timestamptz,
-        permitted -> Bool,
-        absent -> Bool,
-        enable -> Bool,
+        created_by -> Varchar,
+        status -> Varchar,
+        absence_reason -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    settings (key) {
+        key -> Varchar,
+        value -> Text,
+    }
+}
+
+table! {
+    store_days (day) {
+        day -> Date,
+        kind -> Varchar,
+        name -> Varchar,
         created_by -> Varchar,
     }
 }
 
+table! {
+    user_pins (username) {
+        username -> Varchar,
+        pin -> Varchar,
+        failures -> Int4,
+        locked_until -> Nullable<Timestamptz>,
+        updated_at -> Timestamptz,
+    }
+}
+
 table! {
     users (id) {
         id -> Varchar,
@@ -18,7 +167,64 @@ table! {
         first_name -> Nullable<Varchar>,
         last_name -> Nullable<Varchar>,
         isadmin -> Bool,
+        employee_number -> Nullable<Varchar>,
+        hire_date -> Nullable<Date>,
+        deactivated_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    wages (id) {
+        id -> Int8,
+        username -> Varchar,
+        hourly_wage -> Int4,
+        effective_from -> Date,
+        created_by -> Varchar,
     }
 }
 
-allow_tables_to_appear_in_same_query!(schedules, users,);
+table! {
+    weekly_availabilities (id) {
+        id -> Int8,
+        username -> Varchar,
+        weekday -> Int2,
+        start_time -> Time,
+        end_time -> Time,
+        kind -> Varchar,
+    }
+}
+
+joinable!(availabilities -> users (username));
+joinable!(calendar_feeds -> users (username));
+joinable!(exception_resolutions -> users (username));
+joinable!(leave_grants -> users (username));
+joinable!(leave_requests -> users (username));
+joinable!(periods -> users (created_by));
+joinable!(punch_corrections -> punches (punch_id));
+joinable!(punch_corrections -> users (username));
+joinable!(punches -> kiosks (kiosk_id));
+joinable!(punches -> users (username));
+joinable!(schedule_events -> schedules (schedule_id));
+joinable!(user_pins -> users (username));
+joinable!(wages -> users (username));
+joinable!(weekly_availabilities -> users (username));
+
+allow_tables_to_appear_in_same_query!(
+    availabilities,
+    calendar_feeds,
+    exception_resolutions,
+    kiosks,
+    leave_grants,
+    leave_requests,
+    periods,
+    punch_corrections,
+    punches,
+    schedule_events,
+    schedules,
+    settings,
+    store_days,
+    user_pins,
+    users,
+    wages,
+    weekly_availabilities,
+);
@@ -0,0 +1,382 @@
+use bcrypt::{hash, DEFAULT_COST};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::Serialize;
+use std::collections::HashSet;
+
+use super::attendance;
+use super::availability::Kind;
+use super::roster::SplitMix64;
+use super::schedule::{self, Status};
+use super::{from_local, schema, CreateUserError};
+
+const LAST_NAMES: [&str; 12] = [
+    "佐藤", "鈴木", "高橋", "田中", "伊藤", "渡辺", "山本", "中村", "小林", "加藤", "吉田", "山田",
+];
+const FIRST_NAMES: [&str; 12] = [
+    "翔太",
+    "陽菜",
+    "大輝",
+    "結衣",
+    "蓮",
+    "美咲",
+    "悠真",
+    "葵",
+    "湊",
+    "さくら",
+    "颯",
+    "凛",
+];
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub seed: u64,
+    pub staff: usize,
+    // any date in the month to fill with schedules and attendance
+    pub month: NaiveDate,
+    // shifts before this local date are in the past: worked, or cancelled
+    pub today: NaiveDate,
+    // shared by every demo account, so seeding hashes a single password
+    pub password: String,
+    pub created_by: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shift {
+    pub start_time: NaiveDateTime,
+    pub end_time: NaiveDateTime,
+    pub status: Status,
+    pub punches: Vec<(attendance::Kind, NaiveDateTime)>,
+}
+
+/// Everything seeded for one demo account. Times are UTC like the tables.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Staff {
+    pub id: String,
+    pub last_name: &'static str,
+    pub first_name: &'static str,
+    pub hire_date: NaiveDate,
+    pub wages: Vec<(NaiveDate, i32)>,
+    // weekday (0 = Sunday), local start and end
+    pub weekly: Vec<(i16, NaiveTime, NaiveTime, Kind)>,
+    pub unavailable: Vec<NaiveDate>,
+    pub shifts: Vec<Shift>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Summary {
+    pub users: usize,
+    pub wages: usize,
+    pub weekly_availabilities: usize,
+    pub availabilities: usize,
+    pub schedules: usize,
+    pub absences: usize,
+    pub punches: usize,
+}
+
+pub fn username(seed: u64, i: usize) -> String {
+    format!("demo{}-{:03}", seed, i + 1)
+}
+
+fn below(r: &mut SplitMix64, n: u64) -> u64 {
+    r.next() % n
+}
+
+fn month_range(month: NaiveDate) -> (NaiveDate, NaiveDate) {
+    let start = month.with_day(1).unwrap();
+    let next = if start.month() == 12 {
+        NaiveDate::from_ymd(start.year() + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd(start.year(), start.month() + 1, 1)
+    };
+    (start, next)
+}
+
+fn status(r: &mut SplitMix64, past: bool) -> Status {
+    let x = below(r, 100);
+    if past {
+        match x {
+            0..=74 => Status::Approved,
+            75..=82 => Status::AbsentApproved,
+            83..=89 => Status::Cancelled,
+            90..=94 => Status::Rejected,
+            _ => Status::AbsenceRequested,
+        }
+    } else {
+        match x {
+            0..=34 => Status::Approved,
+            35..=64 => Status::Assigned,
+            65..=89 => Status::Requested,
+            _ => Status::AbsenceRequested,
+        }
+    }
+}
+
+// a worked shift: a little early or late, a break past six hours, now and then no clock out
+fn punches(
+    r: &mut SplitMix64,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+) -> Vec<(attendance::Kind, NaiveDateTime)> {
+    let m = |x: u64| Duration::minutes(x as i64);
+    let clock_in = if below(r, 10) == 0 {
+        start + m(1 + below(r, 15))
+    } else {
+        start - m(below(r, 10))
+    };
+    let mut ret = vec![(attendance::Kind::ClockIn, clock_in)];
+    let length = (end - start).num_minutes();
+    if length > 6 * 60 {
+        let b = start + Duration::minutes(length / 2 - 30);
+        ret.push((attendance::Kind::BreakStart, b));
+        ret.push((attendance::Kind::BreakEnd, b + m(45 + below(r, 16))));
+    }
+    if below(r, 20) != 0 {
+        ret.push((attendance::Kind::ClockOut, end + m(below(r, 25)) - m(5)));
+    }
+    ret
+}
+
+/// What `run` would create, computed without touching the database. Each
+/// account draws from its own stream, so adding staff leaves the others unchanged.
+pub fn plan(opts: &Options) -> Vec<Staff> {
+    let (start, end) = month_range(opts.month);
+    (0..opts.staff)
+        .map(|i| {
+            let mut r = SplitMix64(opts.seed ^ ((i as u64 + 1) << 40));
+            let hire_date = start - Duration::days(30 + below(&mut r, 1065) as i64);
+            let mut wage = 1000 + 10 * below(&mut r, 21) as i32;
+            let mut wages = vec![(hire_date, wage)];
+            let mut raise = hire_date + Duration::days(365);
+            while raise < end {
+                wage += 30;
+                wages.push((raise, wage));
+                raise += Duration::days(365);
+            }
+
+            let mut days: Vec<i16> = (0..7).collect();
+            for k in (1..days.len()).rev() {
+                days.swap(k, below(&mut r, k as u64 + 1) as usize);
+            }
+            let mut weekly: Vec<_> = days[..3 + below(&mut r, 3) as usize]
+                .iter()
+                .map(|d| {
+                    let from = 8 + below(&mut r, 6) as u32;
+                    let to = (from + 6 + below(&mut r, 4) as u32).min(22);
+                    let kind = if below(&mut r, 4) == 0 {
+                        Kind::Preferred
+                    } else {
+                        Kind::Available
+                    };
+                    (
+                        *d,
+                        NaiveTime::from_hms(from, 0, 0),
+                        NaiveTime::from_hms(to, 0, 0),
+                        kind,
+                    )
+                })
+                .collect();
+            weekly.sort_by_key(|w| w.0);
+
+            let length = (end - start).num_days() as u64;
+            let mut unavailable: Vec<NaiveDate> = (0..1 + below(&mut r, 3))
+                .map(|_| start + Duration::days(below(&mut r, length) as i64))
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect();
+            unavailable.sort();
+
+            let mut shifts = Vec::new();
+            let mut d = start;
+            while d < end {
+                let w = weekly
+                    .iter()
+                    .find(|w| w.0 as u32 == d.weekday().num_days_from_sunday());
+                if let Some((_, from, to, _)) = w {
+                    if !unavailable.contains(&d) && below(&mut r, 5) != 0 {
+                        let s = d.and_time(*from) + Duration::minutes(30 * below(&mut r, 3) as i64);
+                        let hours = 4 + below(&mut r, 5) as i64;
+                        let e = (s + Duration::hours(hours)).min(d.and_time(*to));
+                        let (s, e) = (from_local(&s), from_local(&e));
+                        let status = status(&mut r, d < opts.today);
+                        let punches = if d < opts.today && status == Status::Approved {
+                            punches(&mut r, s, e)
+                        } else {
+                            vec![]
+                        };
+                        shifts.push(Shift {
+                            start_time: s,
+                            end_time: e,
+                            status,
+                            punches,
+                        });
+                    }
+                }
+                d += Duration::days(1);
+            }
+
+            Staff {
+                id: username(opts.seed, i),
+                last_name: LAST_NAMES[below(&mut r, LAST_NAMES.len() as u64) as usize],
+                first_name: FIRST_NAMES[below(&mut r, FIRST_NAMES.len() as u64) as usize],
+                hire_date,
+                wages,
+                weekly,
+                unavailable,
+                shifts,
+            }
+        })
+        .collect()
+}
+
+/// Creates the planned demo accounts that do not exist yet, in one transaction.
+/// Accounts from an earlier run are left as they are, so running it twice is harmless.
+pub fn run(conn: &PgConnection, opts: &Options) -> Result<Summary, CreateUserError> {
+    use schema::{availabilities, punches, schedules, users, wages, weekly_availabilities};
+
+    let staff = plan(opts);
+    let existing: HashSet<String> = users::table
+        .select(users::id)
+        .filter(users::id.eq_any(staff.iter().map(|s| &s.id)))
+        .get_results::<String>(conn)
+        .map_err(CreateUserError::QueryError)?
+        .into_iter()
+        .collect();
+    let staff: Vec<Staff> = staff
+        .into_iter()
+        .filter(|s| !existing.contains(&s.id))
+        .collect();
+    if staff.is_empty() {
+        return Ok(Summary::default());
+    }
+    let hashed = hash(&opts.password, DEFAULT_COST).map_err(CreateUserError::HashError)?;
+
+    conn.transaction(|| {
+        let mut summary = Summary {
+            users: diesel::insert_into(users::table)
+                .values(
+                    staff
+                        .iter()
+                        .map(|s| {
+                            (
+                                users::id.eq(&s.id),
+                                users::pass.eq(&hashed),
+                                users::first_name.eq(s.first_name),
+                                users::last_name.eq(s.last_name),
+                                users::isadmin.eq(false),
+                                users::employee_number.eq(&s.id),
+                                users::hire_date.eq(s.hire_date),
+                            )
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .execute(conn)?,
+            ..Default::default()
+        };
+        summary.wages = diesel::insert_into(wages::table)
+            .values(
+                staff
+                    .iter()
+                    .flat_map(|s| {
+                        s.wages.iter().map(move |(from, w)| {
+                            (
+                                wages::username.eq(&s.id),
+                                wages::hourly_wage.eq(w),
+                                wages::effective_from.eq(from),
+                                wages::created_by.eq(&opts.created_by),
+                            )
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .execute(conn)?;
+        summary.weekly_availabilities = diesel::insert_into(weekly_availabilities::table)
+            .values(
+                staff
+                    .iter()
+                    .flat_map(|s| {
+                        s.weekly.iter().map(move |(d, from, to, kind)| {
+                            (
+                                weekly_availabilities::username.eq(&s.id),
+                                weekly_availabilities::weekday.eq(d),
+                                weekly_availabilities::start_time.eq(from),
+                                weekly_availabilities::end_time.eq(to),
+                                weekly_availabilities::kind.eq(kind.as_str()),
+                            )
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .execute(conn)?;
+        summary.availabilities = diesel::insert_into(availabilities::table)
+            .values(
+                staff
+                    .iter()
+                    .flat_map(|s| {
+                        s.unavailable.iter().map(move |d| {
+                            (
+                                availabilities::username.eq(&s.id),
+                                availabilities::start_time.eq(from_local(&d.and_hms(0, 0, 0))),
+                                availabilities::end_time
+                                    .eq(from_local(&(*d + Duration::days(1)).and_hms(0, 0, 0))),
+                                availabilities::kind.eq(Kind::Unavailable.as_str()),
+                            )
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .execute(conn)?;
+        let shifts: Vec<(&Staff, &Shift)> = staff
+            .iter()
+            .flat_map(|s| s.shifts.iter().map(move |x| (s, x)))
+            .collect();
+        if !shifts.is_empty() {
+            let created = diesel::insert_into(schedules::table)
+                .values(
+                    shifts
+                        .iter()
+                        .map(|(s, x)| {
+                            (
+                                schedules::username.eq(&s.id),
+                                schedules::created_by.eq(if x.status == Status::Requested {
+                                    &s.id
+                                } else {
+                                    &opts.created_by
+                                }),
+                                schedules::start_time.eq(x.start_time),
+                                schedules::end_time.eq(x.end_time),
+                                schedules::status.eq(x.status),
+                            )
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .get_results::<super::models::Schedule>(conn)?;
+            schedule::record_created(conn, &created, &opts.created_by)?;
+            summary.schedules = created.len();
+            summary.absences = created
+                .iter()
+                .filter(|s| matches!(s.status, Status::AbsenceRequested | Status::AbsentApproved))
+                .count();
+        }
+        let ps: Vec<_> = shifts
+            .iter()
+            .flat_map(|(s, x)| {
+                x.punches.iter().map(move |(k, t)| {
+                    (
+                        punches::username.eq(&s.id),
+                        punches::kind.eq(k.as_str()),
+                        punches::punched_at.eq(t),
+                    )
+                })
+            })
+            .collect();
+        if !ps.is_empty() {
+            summary.punches = diesel::insert_into(punches::table)
+                .values(ps)
+                .execute(conn)?;
+        }
+        Ok(summary)
+    })
+    .map_err(CreateUserError::QueryError)
+}
@@ -0,0 +1,158 @@
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use std::collections::BTreeMap;
+
+use super::absence::COMPANY_REST_MIN_PERCENT;
+use super::network::{self, Outside};
+use super::rounding::UNITS;
+use super::schema;
+
+// lets non-admins see every schedule, not only their own and the approved ones
+pub const SHARED_ROSTER: &str = "shared_roster";
+
+// share of the planned shift paid for an approved absence, in percent
+pub const ABSENCE_PAY_SICK: &str = "absence_pay.sick";
+pub const ABSENCE_PAY_FAMILY: &str = "absence_pay.family";
+pub const ABSENCE_PAY_BEREAVEMENT: &str = "absence_pay.bereavement";
+pub const ABSENCE_PAY_UNPAID: &str = "absence_pay.unpaid";
+pub const ABSENCE_PAY_COMPANY_REST: &str = "absence_pay.company_rest";
+
+// extra pay for work on national holidays and on store special days, in percent
+pub const HOLIDAY_PREMIUM: &str = "holiday_premium_percent";
+pub const SPECIAL_DAY_PREMIUM: &str = "special_day_premium_percent";
+
+// minutes late or early before a punch counts as an exception
+pub const EXCEPTION_GRACE_MINUTES: &str = "exception_grace_minutes";
+
+// units punches and daily totals are rounded to, and the monthly 30-minute rule
+pub const ROUNDING_CLOCK_IN: &str = "rounding.clock_in";
+pub const ROUNDING_CLOCK_OUT: &str = "rounding.clock_out";
+pub const ROUNDING_DAILY: &str = "rounding.daily";
+pub const ROUNDING_MONTHLY: &str = "rounding.monthly";
+
+// client ranges punches are accepted from, what happens to others, and how many
+// proxies in front of the app to trust for the client's address
+pub const PUNCH_NETWORKS: &str = "punch_networks";
+pub const PUNCH_OUTSIDE_NETWORK: &str = "punch_outside_network";
+pub const TRUSTED_PROXY_HOPS: &str = "trusted_proxy_hops";
+
+/// Known store settings and their defaults.
+pub const DEFAULTS: [(&str, &str); 16] = [
+    (SHARED_ROSTER, "false"),
+    (ABSENCE_PAY_SICK, "0"),
+    (ABSENCE_PAY_FAMILY, "0"),
+    (ABSENCE_PAY_BEREAVEMENT, "100"),
+    (ABSENCE_PAY_UNPAID, "0"),
+    (ABSENCE_PAY_COMPANY_REST, "60"),
+    (HOLIDAY_PREMIUM, "0"),
+    (SPECIAL_DAY_PREMIUM, "0"),
+    (EXCEPTION_GRACE_MINUTES, "5"),
+    (ROUNDING_CLOCK_IN, "1"),
+    (ROUNDING_CLOCK_OUT, "1"),
+    (ROUNDING_DAILY, "1"),
+    (ROUNDING_MONTHLY, "false"),
+    (PUNCH_NETWORKS, ""),
+    (PUNCH_OUTSIDE_NETWORK, "reject"),
+    (TRUSTED_PROXY_HOPS, "0"),
+];
+
+fn percent(value: &str, min: i64) -> Result<(), String> {
+    match value.parse::<i64>() {
+        Ok(x) if (0..=100).contains(&x) && x >= min => Ok(()),
+        Ok(x) if (0..=100).contains(&x) => Err(format!("must be at least {}", min)),
+        _ => Err("expected a percentage from 0 to 100".into()),
+    }
+}
+
+/// Checks that `key` is a known setting and `value` is valid for it.
+pub fn validate(key: &str, value: &str) -> Result<(), String> {
+    match key {
+        SHARED_ROSTER | ROUNDING_MONTHLY => value
+            .parse::<bool>()
+            .map(|_| ())
+            .map_err(|_| "expected true or false".into()),
+        ABSENCE_PAY_SICK
+        | ABSENCE_PAY_FAMILY
+        | ABSENCE_PAY_BEREAVEMENT
+        | ABSENCE_PAY_UNPAID
+        | HOLIDAY_PREMIUM
+        | SPECIAL_DAY_PREMIUM => percent(value, 0),
+        ABSENCE_PAY_COMPANY_REST => percent(value, COMPANY_REST_MIN_PERCENT),
+        EXCEPTION_GRACE_MINUTES => match value.parse::<i64>() {
+            Ok(x) if (0..=120).contains(&x) => Ok(()),
+            _ => Err("expected minutes from 0 to 120".into()),
+        },
+        ROUNDING_CLOCK_IN | ROUNDING_CLOCK_OUT | ROUNDING_DAILY => match value.parse::<i64>() {
+            Ok(x) if UNITS.contains(&x) => Ok(()),
+            _ => Err("expected 1, 5, 10, 15 or 30 minutes".into()),
+        },
+        PUNCH_NETWORKS => network::parse_list(value).map(|_| ()).ok_or_else(|| {
+            "expected CIDR ranges separated by commas, such as 203.0.113.0/24".into()
+        }),
+        PUNCH_OUTSIDE_NETWORK => Outside::parse(value)
+            .map(|_| ())
+            .ok_or_else(|| "expected reject or flag".into()),
+        TRUSTED_PROXY_HOPS => match value.parse::<i64>() {
+            Ok(x) if (0..=5).contains(&x) => Ok(()),
+            _ => Err("expected a number of proxies from 0 to 5".into()),
+        },
+        _ => Err("unknown setting".into()),
+    }
+}
+
+pub fn get(conn: &PgConnection, key: &str) -> QueryResult<String> {
+    use schema::settings;
+    settings::table
+        .select(settings::value)
+        .filter(settings::key.eq(key))
+        .get_result::<String>(conn)
+        .optional()
+        .map(|v| {
+            v.or_else(|| {
+                DEFAULTS
+                    .iter()
+                    .find(|(k, _)| *k == key)
+                    .map(|(_, d)| d.to_string())
+            })
+            .unwrap_or_default()
+        })
+}
+
+/// Fails rather than guessing when the stored value is not a number, which
+/// only happens when it was written around `validate`.
+pub fn get_number(conn: &PgConnection, key: &str) -> QueryResult<i64> {
+    let v = get(conn, key)?;
+    v.parse::<i64>().map_err(|e| {
+        diesel::result::Error::DeserializationError(
+            format!("setting {} is not a number: {:?}: {}", key, v, e).into(),
+        )
+    })
+}
+
+pub fn get_bool(conn: &PgConnection, key: &str) -> QueryResult<bool> {
+    get(conn, key).map(|v| v == "true")
+}
+
+pub fn all(conn: &PgConnection) -> QueryResult<BTreeMap<String, String>> {
+    use schema::settings;
+    let mut ret: BTreeMap<String, String> = DEFAULTS
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    ret.extend(
+        settings::table
+            .select((settings::key, settings::value))
+            .get_results::<(String, String)>(conn)?,
+    );
+    Ok(ret)
+}
+
+pub fn set(conn: &PgConnection, key: &str, value: &str) -> QueryResult<usize> {
+    use schema::settings;
+    diesel::insert_into(settings::table)
+        .values((settings::key.eq(key), settings::value.eq(value)))
+        .on_conflict(settings::key)
+        .do_update()
+        .set(settings::value.eq(value))
+        .execute(conn)
+}
@@ -0,0 +1,148 @@
+//! A database of its own for every test: a fresh schema in `DATABASE_URL`
+//! with all migrations applied and a `root`/`pass` administrator, dropped
+//! again when the test ends. Tests can then run in parallel without seeing
+//! each other's rows.
+
+use diesel::connection::SimpleConnection;
+use diesel::pg::PgConnection;
+use diesel::r2d2::{self, ConnectionManager, CustomizeConnection};
+use diesel::Connection;
+use kintai::{generate_token, schema};
+use std::time::Duration;
+use uuid::Uuid;
+
+embed_migrations!("migrations");
+
+pub const ROOT_PASS: &str = "pass";
+
+// bcrypt at its default cost takes most of a second per user in debug builds
+const TEST_COST: u32 = 4;
+
+#[derive(Debug)]
+struct SearchPath(String);
+
+impl CustomizeConnection<PgConnection, r2d2::Error> for SearchPath {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), r2d2::Error> {
+        // the application name lets the teardown find this test's connections
+        conn.batch_execute(&format!(
+            "SET search_path TO {0}; SET application_name TO '{0}'",
+            self.0
+        ))
+        .map_err(r2d2::Error::QueryError)
+    }
+}
+
+fn pool(
+    url: &str,
+    schema: &str,
+    max_size: u32,
+    timeout: Duration,
+) -> r2d2::Pool<ConnectionManager<PgConnection>> {
+    r2d2::Pool::builder()
+        .max_size(max_size)
+        .connection_timeout(timeout)
+        .connection_customizer(Box::new(SearchPath(schema.to_string())))
+        .build(ConnectionManager::<PgConnection>::new(url))
+        .expect("cannot connect to DATABASE_URL")
+}
+
+pub struct Account {
+    pub id: String,
+    pub pass: String,
+    pub token: String,
+}
+
+pub struct TestDb {
+    pub pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    schema: String,
+    url: String,
+}
+
+impl TestDb {
+    pub fn new() -> TestDb {
+        let _ = dotenv::dotenv();
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let schema = format!("test_{}", Uuid::new_v4().to_simple());
+        PgConnection::establish(&url)
+            .and_then(|c| {
+                c.batch_execute(&format!("CREATE SCHEMA {}", schema))
+                    .map_err(diesel::ConnectionError::CouldntSetupConfiguration)
+            })
+            .expect("cannot create the test schema");
+        let pool = pool(&url, &schema, 4, Duration::from_secs(30));
+        let db = TestDb { pool, schema, url };
+        embedded_migrations::run(&db.conn()).expect("cannot run migrations");
+        db.insert_user("root", ROOT_PASS, true);
+        db
+    }
+
+    /// Another pool on the same schema, small enough for a test to exhaust.
+    pub fn pool(
+        &self,
+        max_size: u32,
+        timeout: Duration,
+    ) -> r2d2::Pool<ConnectionManager<PgConnection>> {
+        pool(&self.url, &self.schema, max_size, timeout)
+    }
+
+    pub fn conn(&self) -> r2d2::PooledConnection<ConnectionManager<PgConnection>> {
+        self.pool.get().unwrap()
+    }
+
+    fn insert_user(&self, id: &str, pass: &str, isadmin: bool) {
+        use diesel::{ExpressionMethods, RunQueryDsl};
+        use schema::users;
+        diesel::insert_into(users::table)
+            .values((
+                users::id.eq(id),
+                users::pass.eq(bcrypt::hash(pass, TEST_COST).unwrap()),
+                users::isadmin.eq(isadmin),
+            ))
+            .execute(&self.conn())
+            .unwrap();
+    }
+
+    fn account(&self, isadmin: bool) -> Account {
+        let id = Uuid::new_v4().to_string();
+        let pass = Uuid::new_v4().to_simple().to_string();
+        self.insert_user(&id, &pass, isadmin);
+        Account {
+            token: generate_token(&id, &isadmin).unwrap(),
+            id,
+            pass,
+        }
+    }
+
+    pub fn root(&self) -> Account {
+        Account {
+            id: "root".to_string(),
+            pass: ROOT_PASS.to_string(),
+            token: generate_token("root", &true).unwrap(),
+        }
+    }
+
+    /// A new administrator with a token.
+    pub fn admin(&self) -> Account {
+        self.account(true)
+    }
+
+    /// A new staff member with a token.
+    pub fn staff(&self) -> Account {
+        self.account(false)
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        // a failing test still cleans up; a killed one leaves a test_ schema behind.
+        // Pooled connections may sit in a transaction that holds locks on the schema.
+        if let Ok(c) = PgConnection::establish(&self.url) {
+            let _ = c.batch_execute(&format!(
+                "SELECT pg_terminate_backend(pid) FROM pg_stat_activity \
+                 WHERE application_name = '{0}' AND pid <> pg_backend_pid(); \
+                 DROP SCHEMA {0} CASCADE",
+                self.schema
+            ));
+        }
+    }
+}